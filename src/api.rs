@@ -0,0 +1,242 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::content_type::ContentType;
+use crate::dates::to_sqlite_timestamp;
+use crate::duplicates::DuplicatePolicy;
+use crate::note::{MAX_TITLE_LENGTH, Note, NoteId};
+use crate::security::{encrypt_text, seal_note, unlock_boss_key, unseal_note, unwrap_data_key};
+use crate::store::NoteStore;
+use crate::sql::get_config_value;
+use crate::utils::{make_text_single_line, slice_text};
+
+/**
+* An error for API clients, status follows the HTTP status codes so every transport can use it
+*/
+#[derive(Debug, PartialEq)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: &str) -> ApiError {
+        ApiError { status, message: message.to_string() }
+    }
+
+    pub fn bad_request(message: &str) -> ApiError {
+        ApiError::new(400, message)
+    }
+
+    pub fn not_found(id: usize) -> ApiError {
+        ApiError::new(404, &format!("Could not find note for id: {}", id))
+    }
+
+    pub fn locked() -> ApiError {
+        ApiError::new(423, "This note is protected, unlock first.")
+    }
+}
+
+pub type ApiResult = Result<Value, ApiError>;
+
+#[derive(Serialize)]
+pub struct ApiNoteSummary {
+    pub id: usize,
+    pub title: String,
+    pub protected: bool,
+    pub updated: String,
+    pub due: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct ApiNote {
+    pub id: usize,
+    pub content_id: String,
+    pub title: String,
+    pub body: String,
+    pub protected: bool,
+    pub trashed: bool,
+    pub created: String,
+    pub updated: String,
+    pub due: Option<String>,
+    pub content_type: String
+}
+
+/**
+* Notes for editor plugins and scripts, shared by every transport (HTTP, JSON-RPC).
+* Protected notes can only be read or changed after unlock is called with the password.
+*/
+pub struct ApiSession<'a> {
+    store: &'a dyn NoteStore,
+    boss_key: Option<String>
+}
+
+impl<'a> ApiSession<'a> {
+    pub fn new(store: &'a dyn NoteStore) -> ApiSession<'a> {
+        ApiSession { store, boss_key: None }
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.boss_key.is_some()
+    }
+
+    pub fn unlock(&mut self, password: &str) -> ApiResult {
+        match unlock_boss_key(self.store, password) {
+            Some(boss_key) => {
+                self.boss_key = Some(boss_key);
+                Ok(json!({"unlocked": true}))
+            }
+            None => Err(ApiError::new(401, "Password incorrect."))
+        }
+    }
+
+    pub fn lock(&mut self) -> ApiResult {
+        self.boss_key = None;
+        Ok(json!({"unlocked": false}))
+    }
+
+    /**
+    * Returns the decrypted title and body, protected notes stay encrypted while the session is locked
+    */
+    fn reveal(&self, note: &Note) -> Option<(String, String)> {
+        if !note.protected {
+            return Some((note.title.to_string(), note.body.to_string()))
+        }
+        let boss_key = self.boss_key.as_deref()?;
+        unseal_note(boss_key, note.data_key.as_deref(), &note.title, &note.body)
+    }
+
+    fn find(&self, id: usize) -> Result<Note, ApiError> {
+        self.store.get_note(NoteId(id)).ok_or(ApiError::not_found(id))
+    }
+
+    fn find_unlocked(&self, id: usize) -> Result<Note, ApiError> {
+        let note = self.find(id)?;
+        if note.protected && !self.is_unlocked() {
+            return Err(ApiError::locked())
+        }
+
+        Ok(note)
+    }
+
+    fn summarize(&self, note: Note) -> ApiNoteSummary {
+        let title = match self.reveal(&note) {
+            Some((title, _)) => title,
+            None => "🔒 ENCRYPTED".to_string()
+        };
+        ApiNoteSummary { id: note.id.0, title, protected: note.protected, updated: to_sqlite_timestamp(note.updated), due: note.due.map(to_sqlite_timestamp) }
+    }
+
+    pub fn list(&self, trashed: bool) -> ApiResult {
+        let notes = self.store.list_notes(trashed).into_iter().map(|note| self.summarize(note)).collect::<Vec<ApiNoteSummary>>();
+
+        Ok(json!(notes))
+    }
+
+    pub fn get(&self, id: usize) -> ApiResult {
+        let note = self.find_unlocked(id)?;
+        let (title, body) = self.reveal(&note).ok_or(ApiError::new(500, "Could not decrypt the note."))?;
+        let content_type = note.content_type.unwrap_or_else(|| ContentType::detect(&body)).as_str().to_string();
+
+        Ok(json!(ApiNote {
+            id: note.id.0,
+            content_id: note.content_id.to_string(),
+            title,
+            body,
+            protected: note.protected,
+            trashed: note.trashed,
+            created: to_sqlite_timestamp(note.created),
+            updated: to_sqlite_timestamp(note.updated),
+            due: note.due.map(to_sqlite_timestamp),
+            content_type,
+        }))
+    }
+
+    /**
+    * Searches the titles and bodies of unprotected notes
+    */
+    pub fn search(&self, text: &str) -> ApiResult {
+        if text.trim().is_empty() {
+            return Err(ApiError::bad_request("Search text is required."))
+        }
+        let notes = self.store.search_notes(text).into_iter()
+            .filter_map(|id| self.store.get_note(id))
+            .map(|note| self.summarize(note))
+            .collect::<Vec<ApiNoteSummary>>();
+
+        Ok(json!(notes))
+    }
+
+    /**
+    * @title - the first line of the body is used when there is no title
+    */
+    pub fn create(&self, title: Option<&str>, body: &str, protected: bool) -> ApiResult {
+        if body.trim().is_empty() {
+            return Err(ApiError::bad_request("A note body is required."))
+        }
+        let title = slice_text(0, MAX_TITLE_LENGTH, &make_text_single_line(title.unwrap_or(body)));
+
+        let mut duplicate_of = vec![];
+        let note_id = if protected {
+            let boss_key = self.boss_key.as_deref().ok_or(ApiError::locked())?;
+            let (title, body, data_key) = seal_note(boss_key, &title, body);
+            self.store.insert_note(&title, &body, Some(&data_key))
+        } else {
+            let policy = DuplicatePolicy::from_config(&get_config_value(self.store, "duplicate_policy", "warn"));
+            if policy != DuplicatePolicy::Allow {
+                duplicate_of = self.store.find_duplicates(body);
+                if policy == DuplicatePolicy::Skip && !duplicate_of.is_empty() {
+                    return Err(ApiError::new(409, "Note not saved, it is a duplicate."))
+                }
+            }
+            self.store.insert_note(&title, body, None)
+        };
+
+        match note_id {
+            Some(id) => Ok(json!({"id": id, "duplicate_of": duplicate_of})),
+            None => Err(ApiError::new(500, "Could not save note."))
+        }
+    }
+
+    pub fn update(&self, id: usize, title: Option<&str>, body: Option<&str>) -> ApiResult {
+        let note = self.find_unlocked(id)?;
+        if title.is_none() && body.is_none() {
+            return Err(ApiError::bad_request("A title or body is required."))
+        }
+        let data_key = match (&self.boss_key, note.protected) {
+            (Some(boss_key), true) => Some(unwrap_data_key(boss_key, note.data_key.as_deref()).ok_or(ApiError::new(500, "Could not decrypt the note."))?),
+            _ => None
+        };
+        let encrypt = |text: &str| match &data_key {
+            Some(data_key) => encrypt_text(data_key, text),
+            None => text.to_string()
+        };
+
+        if let Some(title) = title {
+            let title = slice_text(0, MAX_TITLE_LENGTH, &make_text_single_line(title));
+            self.store.update_title(note.id, &encrypt(&title));
+        }
+        if let Some(body) = body {
+            self.store.update_body(note.id, &encrypt(body));
+        }
+
+        self.get(id)
+    }
+
+    pub fn set_trashed(&self, id: usize, trashed: bool) -> ApiResult {
+        self.find(id)?;
+        self.store.set_trashed(NoteId(id), trashed);
+
+        Ok(json!({"id": id, "trashed": trashed}))
+    }
+
+    pub fn delete(&self, id: usize) -> ApiResult {
+        // protected notes can only be removed by someone who could read them
+        let note = self.find_unlocked(id)?;
+        if !self.store.delete_note(note.id, note.protected) {
+            return Err(ApiError::new(500, "Could not delete note."))
+        }
+
+        Ok(json!({"id": id, "deleted": true}))
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng, Payload};
+use chacha20poly1305::aead::rand_core::RngCore;
+use pbkdf2::pbkdf2_hmac;
+use rusqlite::named_params;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::query::Query;
+use crate::security::{seal_note, unseal_note};
+use crate::store::SqliteStore;
+use crate::utils::hash_text;
+
+const ARCHIVE_MAGIC: &str = "TRUSTY-ARCHIVE";
+pub const ARCHIVE_VERSION: u32 = 1;
+const ARCHIVE_KDF: &str = "pbkdf2-sha256";
+// the OWASP recommendation for PBKDF2-HMAC-SHA256, it makes every passphrase guess expensive
+const ARCHIVE_KDF_ROUNDS: u32 = 600_000;
+// an archive asking for more rounds than this is damaged or hostile
+const MAX_ARCHIVE_KDF_ROUNDS: u32 = 10_000_000;
+const ARCHIVE_SALT_LENGTH: usize = 16;
+const ARCHIVE_NONCE_LENGTH: usize = 12;
+// only preferences are copied into another vault, ids, tokens and sync state belong to this vault
+const PORTABLE_CONFIG_KEYS: [&str; 7] = ["backup_keep", "duplicate_policy", "list_compact", "password_min_length", "password_min_strength", "rich_notes", "time_format"];
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveNote {
+    pub content_id: String,
+    pub title: String,
+    pub body: String,
+    // archived notes are always plain text inside the encrypted archive, this restores the flag on import
+    pub protected: bool,
+    pub trashed: bool,
+    pub created: String,
+    pub updated: String,
+    pub due: Option<String>,
+    // archives made before content types were added do not have one
+    #[serde(default)]
+    pub content_type: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    pub exported: String,
+    pub trusty_version: String,
+    pub notes: Vec<ArchiveNote>,
+    pub config: BTreeMap<String, String>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    // add the archived notes that are not in the vault yet
+    Merge,
+    // deletes every note in the vault and keeps only the archived notes, nothing is left to merge with
+    Overwrite
+}
+
+impl ImportMode {
+    pub fn parse(value: &str) -> Option<ImportMode> {
+        match value.trim().to_lowercase().as_str() {
+            "merge" | "m" => Some(ImportMode::Merge),
+            "overwrite" | "o" => Some(ImportMode::Overwrite),
+            _ => None
+        }
+    }
+}
+
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: usize,
+    pub config_keys: usize
+}
+
+pub fn has_protected_notes(store: &SqliteStore) -> bool {
+    store.conn().query_row("SELECT COUNT(*) FROM notes WHERE protected is TRUE;", [], |row| row.get::<usize, usize>(0)).unwrap_or(0) > 0
+}
+
+/**
+* Reads every note (including trashed notes) and the config into an archive
+* @boss_key - the decrypted boss key, protected notes are decrypted with it so they can be moved to a vault with another password
+*/
+pub fn collect_archive(store: &SqliteStore, boss_key: Option<&str>) -> Result<Archive, String> {
+    collect_matching_archive(store, boss_key, &Query::default())
+}
+
+/**
+* Like collect_archive with only the notes that match a query, the config is still exported
+*/
+pub fn collect_matching_archive(store: &SqliteStore, boss_key: Option<&str>, query: &Query) -> Result<Archive, String> {
+    let conn = store.conn();
+    let sql = format!("SELECT notes.content_id, notes.title, content.body, notes.protected, notes.trashed, notes.created, notes.updated, notes.due, notes.content_type, \
+    notes.data_key FROM notes JOIN content on notes.content_id = content.content_id WHERE {} ORDER BY notes.note_id;", query.condition());
+    let mut stmt = conn.prepare(&sql).map_err(|err| err.to_string())?;
+    let rows = stmt.query_map(query.params().as_slice(), |row| {
+        let note = ArchiveNote {
+            content_id: row.get(0)?,
+            title: row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
+            body: row.get::<usize, Option<String>>(2)?.unwrap_or_default(),
+            protected: row.get(3)?,
+            trashed: row.get::<usize, Option<bool>>(4)?.unwrap_or(false),
+            created: row.get(5)?,
+            updated: row.get(6)?,
+            due: row.get(7)?,
+            content_type: row.get(8)?,
+        };
+        Ok((note, row.get::<usize, Option<String>>(9)?))
+    }).map_err(|err| err.to_string())?;
+
+    let mut notes = vec![];
+    for row in rows {
+        let (mut note, data_key) = row.map_err(|err| err.to_string())?;
+        if note.protected {
+            let key = boss_key.ok_or("Your password is needed to export protected notes.".to_string())?;
+            (note.title, note.body) = unseal_note(key, data_key.as_deref(), &note.title, &note.body)
+                .ok_or("Could not decrypt a protected note.".to_string())?;
+        }
+        notes.push(note);
+    }
+
+    let mut config = BTreeMap::new();
+    let mut stmt = conn.prepare("SELECT key, value FROM config;").map_err(|err| err.to_string())?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<usize, String>(0)?, row.get::<usize, String>(1)?))).map_err(|err| err.to_string())?;
+    for (key, value) in rows.flatten() {
+        if PORTABLE_CONFIG_KEYS.contains(&key.as_str()) {
+            config.insert(key, value);
+        }
+    }
+
+    let exported = conn.query_row("SELECT CURRENT_TIMESTAMP;", [], |row| row.get(0)).map_err(|err| err.to_string())?;
+
+    Ok(Archive {
+        version: ARCHIVE_VERSION,
+        exported,
+        trusty_version: env!("CARGO_PKG_VERSION").to_string(),
+        notes,
+        config,
+    })
+}
+
+/**
+* Encrypts an archive under a passphrase. The first line is a plain text header with the format version and
+* the salt and rounds of the key derivation, the second line is the nonce and the encrypted archive in hex.
+* The key is derived from the passphrase with PBKDF2 and the archive is sealed with ChaCha20-Poly1305,
+* the header is authenticated too so it can not be changed without the passphrase.
+*/
+pub fn seal_archive(archive: &Archive, passphrase: &str) -> String {
+    seal_archive_with_rounds(archive, passphrase, ARCHIVE_KDF_ROUNDS)
+}
+
+fn seal_archive_with_rounds(archive: &Archive, passphrase: &str, rounds: u32) -> String {
+    let json = serde_json::to_string(archive).unwrap();
+    let mut salt = [0u8; ARCHIVE_SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let header = format!("{}/{} kdf={} rounds={} salt={}", ARCHIVE_MAGIC, ARCHIVE_VERSION, ARCHIVE_KDF, rounds, to_hex(&salt));
+
+    let cipher = archive_cipher(passphrase, &salt, rounds);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut payload = nonce.to_vec();
+    payload.extend(cipher.encrypt(&nonce, Payload { msg: json.as_bytes(), aad: header.as_bytes() }).unwrap());
+
+    format!("{}\n{}\n", header, to_hex(&payload))
+}
+
+/**
+* Reverses seal_archive, a wrong passphrase, a damaged file and a changed header all fail to decrypt
+*/
+pub fn open_archive(text: &str, passphrase: &str) -> Result<Archive, String> {
+    let (header, payload) = text.split_once('\n').ok_or("This is not a tRusty archive.".to_string())?;
+    let header = header.trim();
+    let format = header.split_whitespace().next().unwrap_or_default();
+    let version = match format.split_once('/') {
+        Some((ARCHIVE_MAGIC, version)) => version.parse::<u32>().map_err(|_| "This is not a tRusty archive.".to_string())?,
+        _ => return Err("This is not a tRusty archive.".to_string())
+    };
+    if version > ARCHIVE_VERSION {
+        return Err(format!("This archive uses format version {}, upgrade tRusty to import it.", version))
+    }
+
+    if header_field(header, "kdf") != Some(ARCHIVE_KDF) {
+        return Err("This archive uses an unknown key derivation, upgrade tRusty to import it.".to_string())
+    }
+    let rounds = header_field(header, "rounds").and_then(|rounds| rounds.parse::<u32>().ok())
+        .filter(|rounds| (1..=MAX_ARCHIVE_KDF_ROUNDS).contains(rounds))
+        .ok_or("The archive header is damaged.".to_string())?;
+    let salt = header_field(header, "salt").and_then(from_hex).ok_or("The archive header is damaged.".to_string())?;
+    let payload = from_hex(payload.trim()).filter(|payload| payload.len() > ARCHIVE_NONCE_LENGTH)
+        .ok_or("The archive is damaged.".to_string())?;
+
+    let (nonce, ciphertext) = payload.split_at(ARCHIVE_NONCE_LENGTH);
+    let json = archive_cipher(passphrase, &salt, rounds)
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header.as_bytes() })
+        .map_err(|_| "Wrong passphrase or damaged archive.".to_string())?;
+    let json = String::from_utf8(json).map_err(|_| "The archive is damaged.".to_string())?;
+
+    serde_json::from_str(&json).map_err(|err| format!("Could not read the archive: {}", err))
+}
+
+fn archive_cipher(passphrase: &str, salt: &[u8], rounds: u32) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, rounds, &mut key);
+    ChaCha20Poly1305::new(&key.into())
+}
+
+fn header_field<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split_whitespace().find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None
+    }
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok()).collect()
+}
+
+/**
+* Writes archived notes and config into the vault in one transaction
+* @mode - Overwrite deletes every note in the vault first, callers should save a backup before using it
+* @boss_key - the decrypted boss key of this vault, protected notes are encrypted again with new data keys wrapped with it
+*/
+pub fn import_archive(store: &SqliteStore, archive: &Archive, mode: ImportMode, boss_key: Option<&str>) -> Result<ImportReport, String> {
+    if boss_key.is_none() && archive.notes.iter().any(|note| note.protected) {
+        return Err("Your password is needed to import protected notes.".to_string())
+    }
+
+    let result = store.with_transaction(|tx| {
+        if mode == ImportMode::Overwrite {
+            tx.execute("DELETE FROM notes;", [])?;
+            tx.execute("DELETE FROM content;", [])?;
+            tx.execute("DELETE FROM history;", [])?;
+            // the deleted notes are not deleted from the databases this vault is synced with
+            tx.execute("DELETE FROM tombstones;", [])?;
+        }
+
+        let mut report = ImportReport { imported: 0, skipped: 0, config_keys: 0 };
+        for note in &archive.notes {
+            let exists: usize = tx.query_row("SELECT COUNT(*) FROM content WHERE content_id = :content_id;",
+                                             named_params! {":content_id": note.content_id}, |row| row.get(0))?;
+            if exists > 0 {
+                report.skipped += 1;
+                continue
+            }
+
+            let (title, body, hash, data_key) = match (note.protected, boss_key) {
+                (true, Some(key)) => {
+                    let (title, body, data_key) = seal_note(key, &note.title, &note.body);
+                    (title, body, None, Some(data_key))
+                }
+                _ => (note.title.to_string(), note.body.to_string(), Some(hash_text(&note.body)), None)
+            };
+            tx.execute("INSERT INTO content (content_id, body, hash) VALUES (:content_id, :body, :hash);", named_params! {
+                ":content_id": note.content_id,
+                ":body": body,
+                ":hash": hash,
+            })?;
+            tx.execute("INSERT INTO notes (title, protected, trashed, created, updated, due, content_type, content_id, data_key) \
+            VALUES (:title, :protected, :trashed, :created, :updated, :due, :content_type, :content_id, :data_key);", named_params! {
+                ":title": title,
+                ":protected": note.protected,
+                ":trashed": note.trashed,
+                ":created": note.created,
+                ":updated": note.updated,
+                ":due": note.due,
+                ":content_type": note.content_type,
+                ":content_id": note.content_id,
+                ":data_key": data_key,
+            })?;
+            report.imported += 1;
+        }
+
+        // a merge keeps the settings of this vault
+        let config_sql = match mode {
+            ImportMode::Merge => "INSERT OR IGNORE INTO config (key, value) VALUES (:key, :value);",
+            ImportMode::Overwrite => "INSERT INTO config (key, value) VALUES (:key, :value) ON CONFLICT(key) DO UPDATE SET value = :value;"
+        };
+        for (key, value) in &archive.config {
+            if PORTABLE_CONFIG_KEYS.contains(&key.as_str()) {
+                report.config_keys += tx.execute(config_sql, named_params! {":key": key, ":value": value})?;
+            }
+        }
+
+        Ok(report)
+    });
+
+    result.map_err(|err| format!("Could not import the archive: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::archive::{Archive, ArchiveNote, ImportMode, open_archive, seal_archive, seal_archive_with_rounds};
+
+    fn make_archive() -> Archive {
+        Archive {
+            version: 1,
+            exported: "2024-07-01 22:56:27".to_string(),
+            trusty_version: "1.0.5".to_string(),
+            notes: vec![ArchiveNote {
+                content_id: "0b7d5c4e-8e8c-4b5c-9a6b-3f1f2c1d0e9a".to_string(),
+                title: "foo".to_string(),
+                body: "bar".to_string(),
+                protected: true,
+                trashed: false,
+                created: "2024-07-01 22:56:27".to_string(),
+                updated: "2024-07-01 22:56:27".to_string(),
+                due: None,
+                content_type: Some("json".to_string()),
+            }],
+            config: BTreeMap::from([("duplicate_policy".to_string(), "skip".to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_seal_and_open_archive() {
+        let sealed = seal_archive(&make_archive(), "correct horse");
+        assert!(sealed.starts_with("TRUSTY-ARCHIVE/1 kdf=pbkdf2-sha256 rounds=600000 salt="));
+        assert!(!sealed.contains("bar"));
+
+        // a new salt and nonce every time, the same archive never looks the same twice
+        let sealed = seal_archive_with_rounds(&make_archive(), "correct horse", 1000);
+        assert_ne!(sealed, seal_archive_with_rounds(&make_archive(), "correct horse", 1000));
+        let archive = open_archive(&sealed, "correct horse").unwrap();
+        assert_eq!(archive.notes[0].body, "bar");
+        assert!(archive.notes[0].protected);
+        assert_eq!(archive.config["duplicate_policy"], "skip");
+    }
+
+    #[test]
+    fn test_open_archive_errors() {
+        // the full key derivation is slow in debug builds, the rounds are read back from the header
+        let sealed = seal_archive_with_rounds(&make_archive(), "correct horse", 1000);
+        assert!(open_archive(&sealed, "wrong horse").is_err());
+        assert!(open_archive("foo\nbar", "correct horse").is_err());
+        let newer = sealed.replacen("TRUSTY-ARCHIVE/1", "TRUSTY-ARCHIVE/2", 1);
+        assert!(open_archive(&newer, "correct horse").unwrap_err().contains("version 2"));
+        let tampered = sealed.replacen("rounds=1000", "rounds=1001", 1);
+        assert!(open_archive(&tampered, "correct horse").unwrap_err().contains("Wrong passphrase"));
+        let (header, payload) = sealed.split_once('\n').unwrap();
+        let flipped = if payload.ends_with("0\n") { "1\n" } else { "0\n" };
+        let damaged = format!("{}\n{}{}", header, &payload[..payload.len() - 2], flipped);
+        assert!(open_archive(&damaged, "correct horse").is_err());
+    }
+
+    #[test]
+    fn test_import_mode_parse() {
+        assert_eq!(ImportMode::parse("Merge"), Some(ImportMode::Merge));
+        assert_eq!(ImportMode::parse("o"), Some(ImportMode::Overwrite));
+        assert_eq!(ImportMode::parse("Overwrite"), Some(ImportMode::Overwrite));
+        assert_eq!(ImportMode::parse("new"), None);
+        assert_eq!(ImportMode::parse("foo"), None);
+    }
+}
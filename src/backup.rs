@@ -0,0 +1,296 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::Duration;
+
+use rusqlite::{Connection, OpenFlags};
+use rusqlite::backup::Backup;
+
+use crate::db_encryption::apply_db_key;
+use crate::setup::{get_db_conn, migrate_trusty_db, PathOperations};
+use crate::sql::get_config_value;
+use crate::store::NoteStore;
+use crate::utils::hash_text;
+
+const BACKUP_PREFIX: &str = "trusty-";
+const BACKUP_EXTENSION: &str = ".db";
+const MANIFEST_EXTENSION: &str = ".manifest";
+const DEFAULT_BACKUP_KEEP: usize = 5;
+
+/**
+* Describes a snapshot, it is saved next to the snapshot as key=value lines
+*/
+pub struct BackupManifest {
+    pub file_name: String,
+    pub created: String,
+    // identifies the recovery code that can unlock the snapshot without revealing it
+    pub recovery_fingerprint: String,
+    pub note_count: usize,
+    pub last_updated: String,
+    pub trusty_version: String
+}
+
+pub struct BackupStatus {
+    pub manifest: BackupManifest,
+    pub path: PathBuf,
+    // "ok" or the problems reported by the integrity check
+    pub integrity: String,
+    pub recovery_matches: bool
+}
+
+pub struct RestorePreview {
+    pub backup: BackupStatus,
+    pub current_note_count: usize,
+    pub current_last_updated: String
+}
+
+pub fn get_backup_dir(cpo: &dyn PathOperations) -> PathBuf {
+    cpo.get_trusty_dir().join("backups")
+}
+
+fn get_manifest_path(backup_path: &Path) -> PathBuf {
+    backup_path.with_extension(&MANIFEST_EXTENSION[1..])
+}
+
+/**
+* The recovery code is stored encrypted with itself, so its hash changes whenever a new code is issued
+*/
+fn get_recovery_fingerprint(conn: &Connection) -> String {
+    let sql = "SELECT value FROM app WHERE key = 'recovery_code';";
+    match conn.query_row(sql, [], |row| row.get::<usize, String>(0)) {
+        Ok(recovery_code) => hash_text(&recovery_code)[0..16].to_string(),
+        Err(_) => "".to_string()
+    }
+}
+
+/**
+* Returns (recovery fingerprint, note count, last updated) for an open database
+*/
+fn describe_db(conn: &Connection) -> (String, usize, String) {
+    let note_count = conn.query_row("SELECT COUNT(*) FROM notes;", [], |row| row.get(0)).unwrap_or(0);
+    let last_updated = conn.query_row("SELECT MAX(updated) FROM notes;", [], |row| row.get::<usize, Option<String>>(0))
+        .unwrap_or(None)
+        .unwrap_or_default();
+
+    (get_recovery_fingerprint(conn), note_count, last_updated)
+}
+
+fn open_read_only(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    apply_db_key(&conn)?;
+    Ok(conn)
+}
+
+/**
+* Runs PRAGMA integrity_check on a database file, returns "ok" when the file is healthy
+*/
+pub fn check_integrity(path: &Path) -> String {
+    let conn = match open_read_only(path) {
+        Ok(conn) => conn,
+        Err(err) => return err.to_string()
+    };
+    let mut stmt = match conn.prepare("PRAGMA integrity_check;") {
+        Ok(stmt) => stmt,
+        Err(err) => return err.to_string()
+    };
+    let rows = stmt.query_map([], |row| row.get::<usize, String>(0));
+    match rows {
+        Ok(rows) => rows.map(|row| row.unwrap_or_default()).collect::<Vec<String>>().join("; "),
+        Err(err) => err.to_string()
+    }
+}
+
+fn write_manifest(backup_path: &Path, manifest: &BackupManifest) -> std::io::Result<()> {
+    let text = format!("file_name={}\ncreated={}\nrecovery_fingerprint={}\nnote_count={}\nlast_updated={}\ntrusty_version={}\n",
+                       manifest.file_name, manifest.created, manifest.recovery_fingerprint, manifest.note_count,
+                       manifest.last_updated, manifest.trusty_version);
+    fs::write(get_manifest_path(backup_path), text)
+}
+
+/**
+* Reads the manifest saved with a snapshot, snapshots copied in by hand are described from their own data
+*/
+fn read_manifest(backup_path: &Path) -> BackupManifest {
+    let file_name = backup_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mut manifest = BackupManifest {
+        file_name,
+        created: "".to_string(),
+        recovery_fingerprint: "".to_string(),
+        note_count: 0,
+        last_updated: "".to_string(),
+        trusty_version: "".to_string(),
+    };
+
+    match fs::read_to_string(get_manifest_path(backup_path)) {
+        Ok(text) => {
+            for line in text.lines() {
+                if let Some((key, value)) = line.split_once('=') {
+                    match key {
+                        "created" => manifest.created = value.to_string(),
+                        "recovery_fingerprint" => manifest.recovery_fingerprint = value.to_string(),
+                        "note_count" => manifest.note_count = value.parse().unwrap_or(0),
+                        "last_updated" => manifest.last_updated = value.to_string(),
+                        "trusty_version" => manifest.trusty_version = value.to_string(),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Err(_) => {
+            if let Ok(conn) = open_read_only(backup_path) {
+                let (recovery_fingerprint, note_count, last_updated) = describe_db(&conn);
+                manifest.recovery_fingerprint = recovery_fingerprint;
+                manifest.note_count = note_count;
+                manifest.last_updated = last_updated;
+            }
+        }
+    }
+
+    manifest
+}
+
+/**
+* Copies the database with the SQLite online backup API so the snapshot is consistent even while tRusty is in use.
+* The snapshot is removed again if it fails the integrity check.
+*/
+pub fn create_backup(cpo: &dyn PathOperations) -> Result<BackupStatus, String> {
+    let backup_dir = get_backup_dir(cpo);
+    fs::create_dir_all(&backup_dir).map_err(|err| format!("Could not create the backup directory: {}", err))?;
+
+    let conn = get_db_conn(&cpo.get_trusty_db_path());
+    let (created, stamp): (String, String) = conn.query_row(
+        "SELECT strftime('%Y-%m-%d %H:%M:%S', 'now'), strftime('%Y%m%d-%H%M%f', 'now');", [],
+        |row| Ok((row.get(0)?, row.get(1)?))).map_err(|err| err.to_string())?;
+    let mut backup_path = backup_dir.join(format!("{}{}{}", BACKUP_PREFIX, stamp.replace('.', ""), BACKUP_EXTENSION));
+    // snapshot names sort in the order they were taken, wait for the clock rather than add a suffix
+    while backup_path.exists() {
+        sleep(Duration::from_millis(1));
+        let stamp: String = conn.query_row("SELECT strftime('%Y%m%d-%H%M%f', 'now');", [], |row| row.get(0)).map_err(|err| err.to_string())?;
+        backup_path = backup_dir.join(format!("{}{}{}", BACKUP_PREFIX, stamp.replace('.', ""), BACKUP_EXTENSION));
+    }
+
+    // snapshots of an encrypted database are encrypted with the same key
+    let mut backup_conn = get_db_conn(&backup_path);
+    Backup::new(&conn, &mut backup_conn).and_then(|backup| backup.run_to_completion(100, Duration::ZERO, None))
+        .map_err(|err| format!("Could not back up your notes: {}", err))?;
+    drop(backup_conn);
+
+    let integrity = check_integrity(&backup_path);
+    if !integrity.eq("ok") {
+        fs::remove_file(&backup_path).unwrap_or(());
+        return Err(format!("The backup failed its integrity check: {}", integrity))
+    }
+
+    let (recovery_fingerprint, note_count, last_updated) = describe_db(&conn);
+    let manifest = BackupManifest {
+        file_name: backup_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        created,
+        recovery_fingerprint,
+        note_count,
+        last_updated,
+        trusty_version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    write_manifest(&backup_path, &manifest).map_err(|err| format!("Could not write the backup manifest: {}", err))?;
+
+    Ok(BackupStatus { manifest, path: backup_path, integrity, recovery_matches: true })
+}
+
+fn list_backup_paths(cpo: &dyn PathOperations) -> Vec<PathBuf> {
+    let mut paths = match fs::read_dir(get_backup_dir(cpo)) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_EXTENSION)
+        }).collect::<Vec<PathBuf>>(),
+        Err(_) => vec![]
+    };
+    // newest first
+    paths.sort();
+    paths.reverse();
+
+    paths
+}
+
+/**
+* Lists the snapshots newest first, each one is checked with PRAGMA integrity_check
+*/
+pub fn list_backups(cpo: &dyn PathOperations) -> Vec<BackupStatus> {
+    let current_fingerprint = get_recovery_fingerprint(&get_db_conn(&cpo.get_trusty_db_path()));
+    list_backup_paths(cpo).into_iter().map(|path| {
+        let manifest = read_manifest(&path);
+        BackupStatus {
+            recovery_matches: manifest.recovery_fingerprint.eq(&current_fingerprint),
+            integrity: check_integrity(&path),
+            manifest,
+            path,
+        }
+    }).collect()
+}
+
+/**
+* Removes the oldest snapshots, the backup_keep config value sets how many are kept (0 keeps them all)
+* Returns the file names that were removed
+*/
+pub fn rotate_backups(cpo: &dyn PathOperations, store: &dyn NoteStore) -> Vec<String> {
+    let keep = get_config_value(store, "backup_keep", &DEFAULT_BACKUP_KEEP.to_string()).trim().parse::<usize>().unwrap_or(DEFAULT_BACKUP_KEEP);
+    if keep == 0 {
+        return vec![]
+    }
+
+    let mut removed = vec![];
+    for path in list_backup_paths(cpo).into_iter().skip(keep) {
+        if fs::remove_file(&path).is_ok() {
+            fs::remove_file(get_manifest_path(&path)).unwrap_or(());
+            removed.push(path.file_name().unwrap_or_default().to_string_lossy().to_string());
+        }
+    }
+
+    removed
+}
+
+/**
+* Finds a snapshot by its file name in the backup directory or by its path
+*/
+pub fn find_backup(cpo: &dyn PathOperations, name: &str) -> Option<PathBuf> {
+    let in_backup_dir = get_backup_dir(cpo).join(name);
+    if in_backup_dir.is_file() {
+        return Some(in_backup_dir)
+    }
+    let path = PathBuf::from(name);
+    if path.is_file() { Some(path) } else { None }
+}
+
+pub fn preview_restore(cpo: &dyn PathOperations, backup_path: &Path) -> RestorePreview {
+    let conn = get_db_conn(&cpo.get_trusty_db_path());
+    let (current_fingerprint, current_note_count, current_last_updated) = describe_db(&conn);
+    let manifest = read_manifest(backup_path);
+    let backup = BackupStatus {
+        recovery_matches: manifest.recovery_fingerprint.eq(&current_fingerprint),
+        integrity: check_integrity(backup_path),
+        manifest,
+        path: backup_path.to_path_buf(),
+    };
+
+    RestorePreview { backup, current_note_count, current_last_updated }
+}
+
+/**
+* Replaces the current database with a snapshot. The current notes are backed up first, that snapshot is returned
+* so it can be restored if this was a mistake.
+*/
+pub fn restore_backup(cpo: &dyn PathOperations, backup_path: &Path) -> Result<BackupStatus, String> {
+    let integrity = check_integrity(backup_path);
+    if !integrity.eq("ok") {
+        return Err(format!("The backup failed its integrity check: {}", integrity))
+    }
+
+    let safety_backup = create_backup(cpo)?;
+    let mut conn = get_db_conn(&cpo.get_trusty_db_path());
+    let backup_conn = open_read_only(backup_path).map_err(|err| format!("Could not restore the backup: {}", err))?;
+    Backup::new(&backup_conn, &mut conn).and_then(|backup| backup.run_to_completion(100, Duration::ZERO, None))
+        .map_err(|err| format!("Could not restore the backup: {}", err))?;
+    drop(conn);
+    // snapshots from older versions need the current schema
+    migrate_trusty_db(cpo);
+
+    Ok(safety_backup)
+}
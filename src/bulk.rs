@@ -0,0 +1,200 @@
+use std::collections::HashSet;
+
+use rusqlite::{Connection, named_params};
+
+use crate::security::{seal_note, unseal_note};
+use crate::store::SqliteStore;
+use crate::utils::hash_text;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BulkAction {
+    Trash,
+    Restore,
+    Delete,
+    Protect,
+    Unprotect
+}
+
+impl BulkAction {
+    pub fn needs_boss_key(&self) -> bool {
+        matches!(self, BulkAction::Protect | BulkAction::Unprotect)
+    }
+
+    fn past_tense(&self) -> &str {
+        match self {
+            BulkAction::Trash => "trashed",
+            BulkAction::Restore => "restored",
+            BulkAction::Delete => "deleted",
+            BulkAction::Protect => "encrypted",
+            BulkAction::Unprotect => "decrypted"
+        }
+    }
+}
+
+// a typo like 1-4000000000 should fail fast instead of filling memory
+const MAX_BULK_IDS: usize = 10_000;
+
+pub struct BulkResult {
+    pub note_id: usize,
+    pub ok: bool,
+    pub message: String
+}
+
+/**
+* Parses note ids like "3", "3,5" and "3,5,10-20", duplicates are dropped and the order is kept
+* At most MAX_BULK_IDS notes can be changed at once
+*/
+pub fn parse_id_list(spec: &str) -> Result<Vec<usize>, String> {
+    let mut ids: Vec<usize> = vec![];
+    let mut seen: HashSet<usize> = HashSet::new();
+    for segment in spec.split(',').map(|segment| segment.trim()).filter(|segment| !segment.is_empty()) {
+        let (start, stop) = match segment.split_once('-') {
+            Some((start, stop)) => (start.trim(), stop.trim()),
+            None => (segment, segment)
+        };
+        let start = start.parse::<usize>().map_err(|_| format!("Invalid note ID: {}", segment))?;
+        let stop = stop.parse::<usize>().map_err(|_| format!("Invalid note ID: {}", segment))?;
+        if start > stop {
+            return Err(format!("Invalid note ID range: {}", segment))
+        }
+        if stop - start >= MAX_BULK_IDS {
+            return Err(format!("Note ID range {} is too large, at most {} notes can be changed at once.", segment, MAX_BULK_IDS))
+        }
+        for id in start..=stop {
+            if seen.insert(id) {
+                ids.push(id);
+            }
+        }
+        if ids.len() > MAX_BULK_IDS {
+            return Err(format!("Too many note IDs, at most {} notes can be changed at once.", MAX_BULK_IDS))
+        }
+    }
+
+    if ids.is_empty() {
+        return Err("No note IDs specified.".to_string())
+    }
+
+    Ok(ids)
+}
+
+/**
+* Reads the leading note id from each line, this works with plain ids and menu lines like `tru | grep foo`
+*/
+pub fn read_id_lines(text: &str) -> Result<Vec<usize>, String> {
+    let spec = text.lines()
+        .filter_map(|line| line.split(|ch: char| ch.is_whitespace() || ch == '|').find(|token| !token.is_empty()))
+        .collect::<Vec<&str>>()
+        .join(",");
+
+    parse_id_list(&spec)
+}
+
+/**
+* Applies an action to every note in a single transaction. A note that cannot be changed is reported
+* as a failure without stopping the batch, a database error rolls back the whole batch.
+* @boss_key - the decrypted boss key, only needed to protect or unprotect notes
+*/
+pub fn run_bulk_action(store: &SqliteStore, action: BulkAction, ids: &[usize], boss_key: Option<&str>) -> Vec<BulkResult> {
+    let result = store.with_transaction(|tx| {
+        let mut results = vec![];
+        for id in ids {
+            let message = apply_bulk_action(tx, action, *id, boss_key)?;
+            results.push(BulkResult { note_id: *id, ok: message.is_none(), message: message.unwrap_or(action.past_tense().to_string()) });
+        }
+        Ok(results)
+    });
+
+    match result {
+        Ok(results) => {
+            results
+        }
+        Err(err) => {
+            ids.iter().map(|id| BulkResult {
+                note_id: *id,
+                ok: false,
+                message: format!("rolled back: {}", err),
+            }).collect()
+        }
+    }
+}
+
+/**
+* Returns Ok(None) when the note changed, or Ok(Some(reason)) when the note was skipped
+*/
+fn apply_bulk_action(conn: &Connection, action: BulkAction, id: usize, boss_key: Option<&str>) -> rusqlite::Result<Option<String>> {
+    let not_found = Some("not found".to_string());
+    match action {
+        BulkAction::Trash | BulkAction::Restore => {
+            let sql = "UPDATE notes SET trashed = :trashed, updated = CURRENT_TIMESTAMP WHERE note_id = :note_id;";
+            let count = conn.execute(sql, named_params! {":note_id": id, ":trashed": action == BulkAction::Trash})?;
+            Ok(if count > 0 { None } else { not_found })
+        }
+        BulkAction::Delete => {
+            let sql = "DELETE FROM notes WHERE note_id = :note_id AND protected is FALSE;";
+            let count = conn.execute(sql, named_params! {":note_id": id})?;
+            Ok(if count > 0 { None } else { Some("not found or protected".to_string()) })
+        }
+        BulkAction::Protect | BulkAction::Unprotect => {
+            let sql = "SELECT notes.title, content.body, notes.protected, notes.data_key FROM notes JOIN content on notes.content_id = content.content_id \
+            WHERE notes.note_id = :note_id;";
+            let note = conn.query_row(sql, named_params! {":note_id": id}, |row| {
+                Ok((row.get::<usize, String>(0)?, row.get::<usize, String>(1)?, row.get::<usize, bool>(2)?, row.get::<usize, Option<String>>(3)?))
+            });
+            let (title, body, is_protected, data_key) = match note {
+                Ok(note) => note,
+                Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(not_found),
+                Err(err) => return Err(err)
+            };
+
+            let protect = action == BulkAction::Protect;
+            if protect == is_protected {
+                return Ok(Some(if protect { "already encrypted" } else { "not encrypted" }.to_string()))
+            }
+
+            let key = boss_key.unwrap_or_default();
+            let (new_title, new_body, hash, new_data_key) = if protect {
+                let (new_title, new_body, new_data_key) = seal_note(key, &title, &body);
+                (new_title, new_body, None, Some(new_data_key))
+            } else {
+                let Some((decrypted_title, decrypted_body)) = unseal_note(key, data_key.as_deref(), &title, &body) else {
+                    return Ok(Some("could not decrypt".to_string()))
+                };
+                let hash = hash_text(&decrypted_body);
+                (decrypted_title, decrypted_body, Some(hash), None)
+            };
+
+            conn.execute("UPDATE content SET body = :body, hash = :hash WHERE content_id = (SELECT content_id FROM notes WHERE note_id = :note_id);",
+                         named_params! {":note_id": id, ":body": new_body, ":hash": hash})?;
+            conn.execute("UPDATE notes SET title = :title, protected = :protected, data_key = :data_key, updated = CURRENT_TIMESTAMP WHERE note_id = :note_id;",
+                         named_params! {":note_id": id, ":title": new_title, ":protected": protect, ":data_key": new_data_key})?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bulk::{parse_id_list, read_id_lines};
+
+    #[test]
+    fn test_parse_id_list() {
+        assert_eq!(parse_id_list("3").unwrap(), vec![3]);
+        assert_eq!(parse_id_list("3,5,10-12").unwrap(), vec![3, 5, 10, 11, 12]);
+        assert_eq!(parse_id_list(" 4 , 2-3,3 ").unwrap(), vec![4, 2, 3]);
+        assert!(parse_id_list("5-3").is_err());
+        assert!(parse_id_list("foo").is_err());
+        assert!(parse_id_list("1-").is_err());
+        assert!(parse_id_list("").is_err());
+        assert_eq!(parse_id_list("1-10000").unwrap().len(), 10_000);
+        assert!(parse_id_list("1-4000000000").unwrap_err().contains("too large"));
+        assert!(parse_id_list("1-10000,20000").unwrap_err().starts_with("Too many note IDs"));
+    }
+
+    #[test]
+    fn test_read_id_lines() {
+        let menu = "        1 | 2024-07-01 22:56:27 | Get Started with tRusty\n       12 | 2024-07-02 10:00:00 | foo\n";
+        assert_eq!(read_id_lines(menu).unwrap(), vec![1, 12]);
+        assert_eq!(read_id_lines("3\n5 6\n\n7").unwrap(), vec![3, 5, 7]);
+        assert!(read_id_lines("foo | bar").is_err());
+    }
+}
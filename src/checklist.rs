@@ -0,0 +1,192 @@
+use regex::Regex;
+
+use crate::note::{Note, NoteId};
+use crate::security::{encrypt_text, try_decrypt_text, unwrap_data_key};
+use crate::sql::update_note_by_note_id;
+use crate::store::NoteStore;
+
+pub struct ChecklistItem {
+    // 1 based position of the item among the checklist items in a note
+    pub index: usize,
+    // 1 based line number in the note body
+    pub line: usize,
+    pub checked: bool,
+    pub text: String
+}
+
+/**
+* Why a checklist item could not be toggled, a locked note is one the boss key could not open
+*/
+#[derive(Debug, PartialEq)]
+pub enum ToggleError {
+    NoteNotFound,
+    ItemNotFound,
+    Locked,
+    NotSaved
+}
+
+pub struct OpenChecklistItem {
+    pub note_id: NoteId,
+    pub title: String,
+    pub item: ChecklistItem
+}
+
+fn get_checklist_regex() -> Regex {
+    // matches markdown task list items like "- [ ] foo", "* [x] bar" and "  + [X] baz"
+    Regex::new(r"^(\s*[-*+]\s+\[)([ xX])(\].*)$").unwrap()
+}
+
+pub fn parse_checklist(body: &str) -> Vec<ChecklistItem> {
+    let re = get_checklist_regex();
+    let mut items = vec![];
+    for (line_index, line) in body.lines().enumerate() {
+        if let Some(caps) = re.captures(line) {
+            let text = caps[3][1..].trim().to_string();
+            items.push(ChecklistItem {
+                index: items.len() + 1,
+                line: line_index + 1,
+                checked: !caps[2].eq(" "),
+                text,
+            });
+        }
+    }
+
+    items
+}
+
+/**
+* Returns (checked, total) for the checklist items in a note body
+*/
+pub fn count_checklist(body: &str) -> (usize, usize) {
+    let items = parse_checklist(body);
+    let checked = items.iter().filter(|item| item.checked).count();
+
+    (checked, items.len())
+}
+
+/**
+* @index - the 1 based checklist item index, returns None if the note does not have that many items
+*/
+pub fn toggle_checklist_item(body: &str, index: usize) -> Option<String> {
+    let item = parse_checklist(body).into_iter().find(|item| item.index == index)?;
+    let re = get_checklist_regex();
+    // split_inclusive keeps the original line endings so that the rest of the note is untouched
+    let toggled = body.split_inclusive('\n').enumerate().map(|(line_index, line)| {
+        if line_index + 1 != item.line {
+            return line.to_string()
+        }
+        let content = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[content.len()..];
+        let mark = if item.checked { " " } else { "x" };
+        format!("{}{}", re.replace(content, format!("${{1}}{}${{3}}", mark)), ending)
+    }).collect::<String>();
+
+    Some(toggled)
+}
+
+/**
+* Toggles a checklist item and saves the note, a protected note is opened and encrypted again with the same data key.
+* Nothing is written when the boss key cannot open the note.
+* @boss_key - the decrypted boss key, only needed for protected notes
+*/
+pub fn toggle_note_item(store: &dyn NoteStore, note_id: usize, index: usize, boss_key: Option<&str>) -> Result<(), ToggleError> {
+    let note = store.get_note(NoteId(note_id)).ok_or(ToggleError::NoteNotFound)?;
+    let data_key = match note.protected {
+        true => Some(unwrap_data_key(boss_key.ok_or(ToggleError::Locked)?, note.data_key.as_deref()).ok_or(ToggleError::Locked)?),
+        false => None
+    };
+    let body = match &data_key {
+        Some(data_key) => try_decrypt_text(data_key, &note.body).ok_or(ToggleError::Locked)?,
+        None => note.body
+    };
+
+    let toggled = toggle_checklist_item(&body, index).ok_or(ToggleError::ItemNotFound)?;
+    let new_body = match &data_key {
+        Some(data_key) => encrypt_text(data_key, &toggled),
+        None => toggled
+    };
+
+    if update_note_by_note_id(store, note_id, &new_body) { Ok(()) } else { Err(ToggleError::NotSaved) }
+}
+
+pub fn get_open_checklist_items(notes: Vec<Note>) -> Vec<OpenChecklistItem> {
+    let mut open_items = vec![];
+    for note in notes {
+        for item in parse_checklist(&note.body) {
+            if !item.checked {
+                open_items.push(OpenChecklistItem {
+                    note_id: note.id,
+                    title: note.title.to_string(),
+                    item,
+                });
+            }
+        }
+    }
+
+    open_items
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checklist::{count_checklist, get_open_checklist_items, parse_checklist, toggle_checklist_item};
+    use chrono::Utc;
+
+    use crate::note::{ContentId, Note, NoteId};
+
+    const TEST_NOTE: &str = "# Deploy\r\n- [ ] tag release\r\n- [x] run tests\r\nnot a task [ ]\r\n  * [X] bump version\r\n+ [ ]\r\n";
+
+    #[test]
+    fn test_parse_checklist() {
+        let items = parse_checklist(TEST_NOTE);
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].text, "tag release");
+        assert_eq!(items[0].line, 2);
+        assert!(!items[0].checked);
+        assert!(items[1].checked);
+        assert_eq!(items[2].line, 5);
+        assert!(items[2].checked);
+        assert_eq!(items[3].index, 4);
+        assert_eq!(items[3].text, "");
+        assert_eq!(parse_checklist("no tasks here").len(), 0);
+    }
+
+    #[test]
+    fn test_count_checklist() {
+        assert_eq!(count_checklist(TEST_NOTE), (2, 4));
+        assert_eq!(count_checklist(""), (0, 0));
+    }
+
+    #[test]
+    fn test_toggle_checklist_item() {
+        let toggled = toggle_checklist_item(TEST_NOTE, 1).unwrap();
+        assert_eq!(toggled, TEST_NOTE.replace("- [ ] tag release", "- [x] tag release"));
+        let toggled_2 = toggle_checklist_item(&toggled, 3).unwrap();
+        assert!(toggled_2.contains("\r\n  * [ ] bump version\r\n"));
+        assert_eq!(count_checklist(&toggled_2), (2, 4));
+        assert!(toggle_checklist_item(TEST_NOTE, 5).is_none());
+        assert!(toggle_checklist_item(TEST_NOTE, 0).is_none());
+    }
+
+    #[test]
+    fn test_get_open_checklist_items() {
+        let notes = vec![Note {
+            id: NoteId(3),
+            content_id: ContentId::from("abc"),
+            title: "Deploy".to_string(),
+            body: TEST_NOTE.to_string(),
+            protected: false,
+            trashed: false,
+            created: Utc::now(),
+            updated: Utc::now(),
+            due: None,
+            content_type: None,
+            data_key: None,
+        }];
+        let open_items = get_open_checklist_items(notes);
+        assert_eq!(open_items.len(), 2);
+        assert_eq!(open_items[0].note_id, NoteId(3));
+        assert_eq!(open_items[0].item.index, 1);
+        assert_eq!(open_items[1].item.index, 4);
+        assert_eq!(open_items[1].item.line, 6);
+    }
+}
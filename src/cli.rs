@@ -1,14 +1,31 @@
+use std::{env, fs};
 use std::io;
 use std::io::Read;
 use std::process::exit;
 
 use clap::Parser;
+use clap_complete::Shell;
+use regex::Regex;
 
+use crate::archive::{collect_matching_archive, has_protected_notes, import_archive, ImportMode, open_archive, seal_archive};
+use crate::backup::{create_backup, find_backup, preview_restore, restore_backup, rotate_backups};
+use crate::bulk::{BulkAction, BulkResult, parse_id_list, read_id_lines, run_bulk_action};
+use crate::checklist::{toggle_note_item, ToggleError};
+use crate::content_type::{ContentType, pretty_print, validate};
+use crate::db_encryption::{check_db_key, DB_KEY_ENV, decrypt_database, encrypt_database, is_encrypted_db, is_encryption_supported, set_db_key};
+use crate::dates::{DEFAULT_TIME_FORMAT, parse_since_local, parse_utc_timestamp, TimeDisplay};
 use crate::errors::Errors;
-use crate::render::{TrustyPrinter, Printer};
-use crate::security::encrypt_note;
-use crate::setup::{TrustyPathOperations, PathOperations};
-use crate::sql::{add_note, get_last_touched_note, get_note_by_id, update_note_by_content_id, update_note_by_note_id, update_title_by_content_id};
+use crate::grep::grep_notes;
+use crate::picker::{pick_note, PickAction};
+use crate::password::normalize_password;
+use crate::query::Query;
+use crate::render::{ListOptions, print_backup_status, print_grep_results, print_restore_preview, print_rich_note, TrustyPrinter, Printer};
+use crate::server::{get_api_token, run_server, start_server};
+use crate::security::{change_password, encrypt_note, get_boss_key, prompt_for_password, report_password_strength, rotate_boss_key};
+use crate::setup::PathOperations;
+use crate::note::{MAX_TITLE_LENGTH, Note, NoteId};
+use crate::sql::{add_note, get_config_value, get_last_touched_note, get_note_by_id, list_matching_note_records, list_picker_notes, set_note_pinned, query_note_ids};
+use crate::store::{NoteStore, SqliteStore};
 use crate::utils::slice_text;
 
 #[derive(Debug, Parser)]
@@ -26,24 +43,54 @@ pub(crate) struct Cli {
     pub(crate) input: Option<bool>,
     #[arg(short, long, help = "Use this flag to specify an ID to print a saved note.")]
     pub find: Option<usize>,
+    #[arg(long, default_missing_value = "true", num_args = 0, requires = "find", help = "Render a markdown note with colors when printing it with -f.")]
+    pub rich: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, requires = "find", help = "Pretty print a JSON or YAML note when printing it with -f.")]
+    pub pretty: Option<bool>,
+    #[arg(long, value_name = "ID", help = "Check that a JSON or YAML note by ID can be parsed.")]
+    pub validate: Option<usize>,
+    #[arg(long, value_name = "ID", requires = "content_type", help = "Set the content type of a note by ID with --content-type.")]
+    pub set_type: Option<usize>,
+    #[arg(long, value_name = "TYPE", requires = "set_type", help = "One of: text, json, yaml, sh, rust, diff, log.")]
+    pub content_type: Option<String>,
     #[arg(short, long, default_missing_value = "true", num_args = 0, help = "Prints a summary list of all note (default behavior if no flag(s) specified.")]
     pub list: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "List notes without separator lines, or set --config list_compact=true.")]
+    pub compact: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Include trashed notes in the list.")]
+    pub with_trash: Option<bool>,
+    #[arg(long, value_name = "WHEN", help = "Only list or dump notes updated since a time, ex: yesterday, '3 days ago', 12h or 2024-07-01.")]
+    pub since: Option<String>,
+    #[arg(long, value_name = "QUERY", conflicts_with = "search", help = "Only list, dump, export or change (with --trash, --restore, -D, -p or -u) the notes that match a query, ex: 'tag:infra updated:>2026-01-01 protected:no size:>10k'")]
+    pub query: Option<String>,
+    #[arg(long, value_name = "PATTERN", help = "Search note bodies with a regular expression and print the matching lines with the note ID, title and line numbers, ex: '(?i)deploy'")]
+    pub grep: Option<String>,
+    #[arg(short = 'C', long, value_name = "LINES", requires = "grep", help = "Print this many lines before and after each --grep match.")]
+    pub context: Option<usize>,
+    #[arg(long, default_missing_value = "true", num_args = 0, requires = "grep", help = "Also --grep protected notes, your password is asked for once.")]
+    pub grep_protected: Option<bool>,
+    #[arg(long, value_name = "ID", help = "Pin a note by ID, pinned notes are listed last so they stay next to the prompt.")]
+    pub pin: Option<usize>,
+    #[arg(long, value_name = "ID", help = "Unpin a note by ID.")]
+    pub unpin: Option<usize>,
+    #[arg(long, value_name = "ACTION", num_args = 0..=1, default_missing_value = "print", value_parser = ["print", "open", "edit"], help = "Fuzzy find a note by its title (or the body of an unprotected note), then print, open or edit it.")]
+    pub pick: Option<String>,
     #[arg(short = 'g', long, default_missing_value = "true", num_args = 0, help = "Use this flag to find a note by piping in a menu row. Think -g like grep.")]
     pub find_from: Option<bool>,
     #[arg(short, long, default_missing_value = "true", num_args = 0, help = "Use this flag to edit the last touched note.")]
     pub edit: Option<bool>,
     #[arg(short, long, default_missing_value = "0", num_args(0..=1), help = "Use this flag to open a note by ID.")]
     pub open: Option<usize>,
-    #[arg(short = 'D', long, help = "Use this flag to delete an unprotected note by ID.")]
-    pub delete: Option<usize>,
+    #[arg(short = 'D', long, value_name = "IDS", default_missing_value = "", num_args(0..=1), help = "Use this flag to delete unprotected notes by ID, ex: 3 or 3,5,10-20 or - to read IDs from the standard input.")]
+    pub delete: Option<String>,
     #[arg(short = 'F', long, help = "DANGER: This is a will indiscriminately delete a note. Use this flag to force delete a note by ID.")]
     pub force_delete: Option<usize>,
     #[arg(short, long, default_missing_value = "true", num_args = 0, help = "Permanently delete all notes that are in the trash.")]
     pub clean: Option<bool>,
-    #[arg(long, help = "Use this flag to soft delete (trash) a note by ID.")]
-    pub trash: Option<usize>,
-    #[arg(long, help = "Use this flag to restore a soft deleted (trashed) note by ID.")]
-    pub restore: Option<usize>,
+    #[arg(long, value_name = "IDS", default_missing_value = "", num_args(0..=1), help = "Use this flag to soft delete (trash) notes by ID, ex: 3 or 3,5,10-20 or - to read IDs from the standard input.")]
+    pub trash: Option<String>,
+    #[arg(long, value_name = "IDS", default_missing_value = "", num_args(0..=1), help = "Use this flag to restore soft deleted (trashed) notes by ID, ex: 3 or 3,5,10-20 or - to read IDs from the standard input.")]
+    pub restore: Option<String>,
     #[arg(short = 'A', long, default_missing_value = "true", num_args = 0, help = "When editing, this modifier will allow you to edit a title.")]
     pub all: Option<bool>,
     #[arg(short, long, default_missing_value = "true", num_args = 0, help = "Print all unprotected notes, very good for using grep to search or less to review.")]
@@ -56,10 +103,75 @@ pub(crate) struct Cli {
     pub encrypt: Option<bool>,
     #[arg(long, help = "Use this flag to reset your password with a recovery code.")]
     pub recover: Option<String>,
-    #[arg(short, long, help = "Decrypt a note and save it as plain text.")]
-    pub unprotect: Option<usize>,
-    #[arg(short, long, help = "Encrypt and save an existing note.")]
-    pub protect: Option<usize>
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Replace the key protected notes are encrypted under, you get a new recovery code.")]
+    pub rotate_key: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Change your password, you are asked for your current password first.")]
+    pub change_password: Option<bool>,
+    #[arg(long, requires = "change_password", default_missing_value = "true", num_args = 0, help = "Also replace your recovery code when you change your password.")]
+    pub new_recovery_code: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Encrypt the whole database with a key you enter once per session, needs a build with --features sqlcipher.")]
+    pub encrypt_db: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Turn an encrypted database back into plain text.")]
+    pub decrypt_db: Option<bool>,
+    #[arg(short, long, value_name = "IDS", default_missing_value = "", num_args(0..=1), help = "Decrypt notes by ID and save them as plain text, ex: 3 or 3,5,10-20 or - to read IDs from the standard input.")]
+    pub unprotect: Option<String>,
+    #[arg(short, long, value_name = "IDS", default_missing_value = "", num_args(0..=1), help = "Encrypt and save existing notes by ID, ex: 3 or 3,5,10-20 or - to read IDs from the standard input.")]
+    pub protect: Option<String>,
+    #[arg(long, help = "Select the notes for --trash, --restore, -D, -p or -u by searching unprotected titles and bodies, use instead of IDs.")]
+    pub search: Option<String>,
+    #[arg(long, help = "Set a due date on a note by ID with --date, leave off --date to clear it.")]
+    pub due: Option<usize>,
+    #[arg(long, requires = "due", help = "A local due date or time for --due, ex: 2024-07-01 or '2024-07-01 09:30'.")]
+    pub date: Option<String>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "List overdue and upcoming notes.")]
+    pub agenda: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Print overdue notes and notes due in the next 24 hours as tab separated lines for desktop notifications.")]
+    pub notify: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Export notes with due dates as an iCalendar (.ics) file on the standard output.")]
+    pub ics: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "List the open checklist items (- [ ]) in all unprotected notes.")]
+    pub todos: Option<bool>,
+    #[arg(long, requires = "item", help = "Check or uncheck a checklist item in a note by ID, use with --item.")]
+    pub toggle: Option<usize>,
+    #[arg(long, requires = "toggle", help = "The checklist item number to toggle, the first item in a note is 1.")]
+    pub item: Option<usize>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Report unprotected notes with duplicate or nearly duplicate bodies.")]
+    pub duplicates: Option<bool>,
+    #[arg(long, requires = "into", help = "Merge a note by ID into the note specified with --into, the merged note is removed.")]
+    pub merge: Option<usize>,
+    #[arg(long, requires = "merge", help = "The ID of the note that a --merge note is appended to.")]
+    pub into: Option<usize>,
+    #[arg(long, help = "Print the history (ex: merged titles and timestamps) of a note by ID.")]
+    pub history: Option<usize>,
+    #[arg(long, value_name = "KEY[=VALUE]", help = "Print a config value, or set it with KEY=VALUE, ex: duplicate_policy=skip")]
+    pub config: Option<String>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Save a snapshot of your notes to the backups folder, older snapshots are rotated out.")]
+    pub backup: Option<bool>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "List your backups and check their integrity.")]
+    pub backups: Option<bool>,
+    #[arg(long, value_name = "BACKUP", help = "Replace your notes with a backup, use a file name from --backups or a path.")]
+    pub restore_backup: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Export all notes and config to a single file encrypted with a passphrase.")]
+    pub export_archive: Option<String>,
+    #[arg(long, value_name = "FILE", help = "Import notes and config from an archive made with --export-archive.")]
+    pub import_archive: Option<String>,
+    #[arg(long, value_name = "MODE", requires = "import_archive", help = "merge: add the archived notes to your notes, overwrite: delete all of your notes and import the archive.")]
+    pub import_mode: Option<String>,
+    #[arg(long, value_name = "DB", help = "Two-way sync your notes with another trusty.db, ex: one on a USB drive or a shared folder.")]
+    pub sync: Option<String>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Sync your notes with the git repository set with --config git_remote=URL.")]
+    pub git_sync: Option<bool>,
+    #[arg(long, value_name = "ADDR", num_args = 0..=1, default_missing_value = "127.0.0.1:7878", help = "Serve a local REST API for editor plugins and scripts, defaults to 127.0.0.1:7878.")]
+    pub serve: Option<String>,
+    #[arg(long, value_name = "PATH", requires = "serve", help = "Serve the REST API on a Unix socket instead of a port, ex: --serve --socket ~/.trusty/trusty.sock")]
+    pub socket: Option<String>,
+    #[arg(long, default_missing_value = "true", num_args = 0, help = "Answer JSON-RPC requests on stdin, one per line, for editor plugins.")]
+    pub rpc: Option<bool>,
+    #[arg(long, value_name = "SHELL", help = "Print a completion script for bash, zsh or fish, ex: tru --completions zsh > ~/.zfunc/_tru")]
+    pub completions: Option<Shell>,
+    // used by the completion scripts to complete note IDs
+    #[arg(long, value_name = "KIND", hide = true, num_args = 0..=1, default_missing_value = "notes")]
+    pub complete_notes: Option<String>
 }
 
 pub(crate) fn read_from_std_in() -> Option<String> {
@@ -78,14 +190,14 @@ pub(crate) fn read_from_std_in() -> Option<String> {
     Some(buffer.to_string())
 }
 
-pub(crate) fn insert_note_from_std_in(title: &str, protected: bool) -> bool {
+pub(crate) fn insert_note_from_std_in(store: &dyn NoteStore, title: &str, protected: bool) -> bool {
     let result = match read_from_std_in() {
         None => {
             false
         }
         Some(piped_input) => {
             if !piped_input.trim().is_empty() {
-                add_note(&TrustyPathOperations {}, title, &piped_input, protected);
+                add_note(store, title, &piped_input, protected);
                 true
             } else {
                 TrustyPrinter {}.print_error(format!("{}", "Input was either empty or flag was not specified, please fix your command."));
@@ -97,24 +209,24 @@ pub(crate) fn insert_note_from_std_in(title: &str, protected: bool) -> bool {
     result
 }
 
-pub(crate) fn edit_note() {
-    let note = get_last_touched_note(&TrustyPathOperations {});
+pub(crate) fn edit_note(store: &dyn NoteStore) {
+    let note = get_last_touched_note(store);
     let body = note.body.as_str();
     let edited = edit::edit(body).unwrap();
 
     let new_body = match note.protected {
         true => {
-            let encrypted_note = encrypt_note("", &edited);
-            encrypted_note.body
+            let (_, encrypted_body) = encrypt_note(store, note.note_id, "", &edited);
+            encrypted_body
         }
         false => {edited}
     };
-    update_note_by_content_id(&TrustyPathOperations {}, &note.content_id, &new_body);
+    store.update_body(note.note_id, &new_body);
 }
 
-pub(crate) fn edit_title(note_id: Option<usize>) {
+pub(crate) fn edit_title(store: &dyn NoteStore, note_id: Option<usize>) {
     let id = note_id.unwrap_or(0);
-    let note = if id > 0  {get_note_by_id(&TrustyPathOperations {}, id)} else {get_last_touched_note(&TrustyPathOperations {})};
+    let note = if id > 0  {get_note_by_id(store, NoteId(id))} else {get_last_touched_note(store)};
     let title = note.title.to_string();
 
     let edited_title = edit::edit(title).unwrap();
@@ -123,26 +235,637 @@ pub(crate) fn edit_title(note_id: Option<usize>) {
 
     let new_title = match note.protected {
         true => {
-            let encrypted_note = encrypt_note(&edited_title, "");
-            encrypted_note.title
+            let (encrypted_title, _) = encrypt_note(store, note.note_id, &edited_title, "");
+            encrypted_title
         }
         false => {edited_title}
     };
 
-    update_title_by_content_id(&TrustyPathOperations {}, &note.content_id, &new_title);
+    store.update_title(note.note_id, &new_title);
 }
 
-pub(crate) fn open_note(cpo: &dyn PathOperations, id: usize, protected: bool) -> bool  {
+/**
+* The password is asked for once for a protected note, the note is not changed when it is rejected
+*/
+pub(crate) fn toggle_note_checklist_item(store: &dyn NoteStore, note_id: usize, index: usize) {
+    let protected = store.get_note(NoteId(note_id)).map(|note| note.protected).unwrap_or(false);
+    let result = match protected {
+        true => {
+            let mut result = Err(ToggleError::Locked);
+            let handle_toggle = |password: &str| -> bool {
+                result = toggle_note_item(store, note_id, index, Some(&get_boss_key(store, password)));
+                result != Err(ToggleError::Locked)
+            };
+            prompt_for_password(store, handle_toggle, true, false);
+            result
+        }
+        false => toggle_note_item(store, note_id, index, None)
+    };
+
+    match result {
+        Ok(()) => {
+            store.set_last_touched(NoteId(note_id));
+        }
+        Err(ToggleError::NoteNotFound) => {
+            TrustyPrinter {}.print_error(format!("Could not find note for id: {}", note_id));
+            exit(Errors::NoteIdErr as i32);
+        }
+        Err(ToggleError::ItemNotFound) => {
+            TrustyPrinter {}.print_error(format!("Note: {} does not have checklist item: {}", note_id, index));
+            exit(Errors::ChecklistItemErr as i32);
+        }
+        Err(_) => {
+            TrustyPrinter {}.print_error(format!("Note: {} was not changed.", note_id));
+            exit(Errors::ChecklistItemErr as i32);
+        }
+    }
+}
+
+/**
+* @spec - note ids like 3,5,10-20, - to read ids from the standard input or empty to use the search or query results
+*/
+pub(crate) fn select_note_ids(store: &SqliteStore, spec: &str, search: Option<&str>, query: &Query) -> Vec<usize> {
+    let selected = match (spec.trim(), search) {
+        ("", Some(text)) => {
+            let ids = store.search_notes(text).into_iter().map(|id| id.0).collect::<Vec<usize>>();
+            if ids.is_empty() { Err(format!("No unprotected notes match: {}", text)) } else { Ok(ids) }
+        }
+        ("", None) if !query.is_empty() => {
+            let ids = query_note_ids(store, query);
+            if ids.is_empty() { Err("No notes match the query.".to_string()) } else { Ok(ids) }
+        }
+        ("", None) => {
+            Err("Specify note IDs or use --search or --query to select notes.".to_string())
+        }
+        ("-", _) => {
+            read_id_lines(&read_from_std_in().unwrap_or_default())
+        }
+        (spec, _) => {
+            parse_id_list(spec)
+        }
+    };
+
+    match selected {
+        Ok(ids) => {
+            ids
+        }
+        Err(message) => {
+            TrustyPrinter {}.print_error(message);
+            exit(Errors::NoteIdListErr as i32);
+        }
+    }
+}
+
+/**
+* Runs an action on many notes at once, protecting and unprotecting only prompt for a password once
+*/
+pub(crate) fn bulk_update_notes(store: &SqliteStore, action: BulkAction, ids: &[usize]) -> Vec<BulkResult> {
+    if !action.needs_boss_key() {
+        return run_bulk_action(store, action, ids, None)
+    }
+
+    let mut results = None;
+    let handle_bulk_action = |password: &str| -> bool {
+        let boss_key = get_boss_key(store, password);
+        results = Some(run_bulk_action(store, action, ids, Some(&boss_key)));
+        true
+    };
+    prompt_for_password(store, handle_bulk_action, true, false);
+
+    results.unwrap_or_else(|| ids.iter().map(|id| BulkResult {
+        note_id: *id,
+        ok: false,
+        message: "password incorrect".to_string(),
+    }).collect())
+}
+
+pub(crate) fn prompt_for_confirmation(question: &str) -> bool {
+    TrustyPrinter {}.println(format!("{} [y/N]", question));
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).unwrap_or(0);
+
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+pub(crate) fn backup_notes(cpo: &dyn PathOperations, store: &dyn NoteStore) {
+    let printer = TrustyPrinter {};
+    match create_backup(cpo) {
+        Ok(backup) => {
+            printer.println("Backup saved 💾".to_string());
+            print_backup_status(&printer, &backup);
+            for removed in rotate_backups(cpo, store) {
+                printer.println(format!("Removed old backup: {}", removed));
+            }
+        }
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::BackupErr as i32)
+        }
+    }
+}
+
+pub(crate) fn restore_notes_from_backup(cpo: &dyn PathOperations, name: &str) -> bool {
+    let printer = TrustyPrinter {};
+    let backup_path = match find_backup(cpo, name) {
+        Some(backup_path) => backup_path,
+        None => {
+            printer.print_error(format!("Could not find a backup named: {}", name));
+            exit(Errors::RestoreBackupErr as i32)
+        }
+    };
+
+    let preview = preview_restore(cpo, &backup_path);
+    print_restore_preview(&printer, &preview);
+    if !preview.backup.integrity.eq("ok") {
+        exit(Errors::RestoreBackupErr as i32)
+    }
+    if !prompt_for_confirmation("Replace your notes with this backup?") {
+        printer.println("Restore cancelled.".to_string());
+        return false
+    }
+
+    match restore_backup(cpo, &backup_path) {
+        Ok(safety_backup) => {
+            printer.println("Backup restored 🛟".to_string());
+            printer.println(format!("Your previous notes were saved as: {}", safety_backup.manifest.file_name));
+            true
+        }
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::RestoreBackupErr as i32)
+        }
+    }
+}
+
+fn prompt_for_db_key() -> String {
+    normalize_password(&rpassword::prompt_password("Database key: ").unwrap())
+}
+
+/**
+* Asks for the database key when the database is encrypted, it is only asked for once per session.
+* TRUSTY_DB_KEY can be set instead, ex: for scripts.
+*/
+pub(crate) fn unlock_database(cpo: &dyn PathOperations) {
+    let printer = TrustyPrinter {};
+    let db_path = cpo.get_trusty_db_path();
+    if !is_encrypted_db(&db_path) {
+        return
+    }
+    if !is_encryption_supported() {
+        printer.print_error("Your database is encrypted, build tRusty with --features sqlcipher to open it.".to_string());
+        exit(Errors::DbEncryptionErr as i32)
+    }
+    if let Ok(key) = env::var(DB_KEY_ENV) {
+        if !check_db_key(&db_path, &key) {
+            printer.print_error(format!("{} does not unlock your database.", DB_KEY_ENV));
+            exit(Errors::DbEncryptionErr as i32)
+        }
+        set_db_key(Some(&key));
+        return
+    }
+
+    for _ in 0..2 {
+        let key = prompt_for_db_key();
+        if check_db_key(&db_path, &key) {
+            set_db_key(Some(&key));
+            return
+        }
+        printer.println("Database key incorrect, try again.".to_string());
+    }
+
+    printer.print_error("Database key incorrect.".to_string());
+    exit(Errors::DbEncryptionErr as i32)
+}
+
+/**
+* Titles, timestamps and config are encrypted too, unlike protected notes
+*/
+pub(crate) fn encrypt_database_with_prompt(cpo: &dyn PathOperations, store: &dyn NoteStore) {
+    let printer = TrustyPrinter {};
+    printer.println("Choose a database key, you enter it once each time you use tRusty".to_string());
+    let key = prompt_for_db_key();
+    if !report_password_strength(store, &key) {
+        exit(Errors::DbEncryptionErr as i32)
+    }
+    if !key.eq(&normalize_password(&rpassword::prompt_password("Enter the database key again: ").unwrap())) {
+        printer.print_error("The keys do not match.".to_string());
+        exit(Errors::DbEncryptionErr as i32)
+    }
+
+    match encrypt_database(&cpo.get_trusty_db_path(), &key) {
+        Ok(_) => {
+            printer.println("Database encrypted".to_string());
+            printer.println("Snapshots taken before now are still plain text, remove the ones you no longer need with your file manager.".to_string());
+        }
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::DbEncryptionErr as i32)
+        }
+    }
+}
+
+pub(crate) fn decrypt_database_file(cpo: &dyn PathOperations) {
+    let printer = TrustyPrinter {};
+    match decrypt_database(&cpo.get_trusty_db_path()) {
+        Ok(_) => printer.println("Database decrypted, protected notes are still encrypted".to_string()),
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::DbEncryptionErr as i32)
+        }
+    }
+}
+
+fn prompt_for_passphrase(confirm: bool) -> String {
+    let passphrase = rpassword::prompt_password("Archive passphrase: ").unwrap();
+    if confirm {
+        if passphrase.chars().count() < 8 {
+            TrustyPrinter {}.print_error("The archive passphrase must be at least 8 characters.".to_string());
+            exit(Errors::ArchiveErr as i32)
+        }
+        if !passphrase.eq(&rpassword::prompt_password("Enter the archive passphrase again: ").unwrap()) {
+            TrustyPrinter {}.print_error("The passphrases do not match.".to_string());
+            exit(Errors::ArchiveErr as i32)
+        }
+    }
+
+    passphrase
+}
+
+/**
+* Runs the closure with the decrypted boss key when it is needed, the password is prompted for once
+*/
+fn with_optional_boss_key<T, F>(store: &dyn NoteStore, needed: bool, fun: F) -> Option<T> where F: FnOnce(Option<&str>) -> T {
+    if !needed {
+        return Some(fun(None))
+    }
+
+    let mut fun = Some(fun);
+    let mut result = None;
+    let handle_password = |password: &str| -> bool {
+        let boss_key = get_boss_key(store, password);
+        if let Some(fun) = fun.take() {
+            result = Some(fun(Some(&boss_key)));
+        }
+        true
+    };
+    prompt_for_password(store, handle_password, true, false);
+
+    result
+}
+
+/**
+* Searches the notes that match the query, protected notes are only searched when asked for
+*/
+pub(crate) fn grep_and_print_notes(store: &SqliteStore, pattern: &str, context: usize, protected: bool, query: &Query) {
+    let printer = TrustyPrinter {};
+    let regex = match Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            printer.print_error(format!("Could not read the pattern: {}", err));
+            exit(Errors::GrepErr as i32)
+        }
+    };
+
+    let notes = list_matching_note_records(store, query);
+    let needs_password = protected && notes.iter().any(|note| note.protected);
+    match with_optional_boss_key(store, needs_password, |boss_key| grep_notes(notes, &regex, context, boss_key)) {
+        Some(results) => print_grep_results(&printer, results),
+        None => {
+            printer.print_error("Password incorrect.".to_string());
+            exit(Errors::GrepErr as i32)
+        }
+    }
+}
+
+/**
+* Only the data key of each protected note is wrapped again, the notes themselves are not re-encrypted
+*/
+pub(crate) fn rotate_key(store: &dyn NoteStore) {
+    let printer = TrustyPrinter {};
+    let mut result = Err("Password incorrect.".to_string());
+    let rotate = |password: &str| -> bool {
+        result = rotate_boss_key(store, password);
+        true
+    };
+
+    prompt_for_password(store, rotate, true, false);
+    match result {
+        Ok(recovery_code) => {
+            printer.println("Boss key rotated".to_string());
+            printer.println(format!("🛟 Recovery code generated: {}", recovery_code));
+            printer.println("Your old recovery code no longer works, save this one instead.".to_string());
+        }
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::RotateKeyErr as i32)
+        }
+    }
+}
+
+/**
+* Unlike --recover this needs your current password instead of the recovery code
+*/
+pub(crate) fn change_password_with_prompt(store: &dyn NoteStore, rotate_recovery_code: bool) {
+    let printer = TrustyPrinter {};
+    let mut result = Err("Password incorrect.".to_string());
+    let change = |password: &str| -> bool {
+        printer.println("Choose a new password".to_string());
+        // the current password was right, so a new password that is never accepted is not "incorrect"
+        result = Err("Password not changed.".to_string());
+        let set_new_password = |new_password: &str| -> bool {
+            // checked before change_password so that the reason is printed before asking again
+            if !report_password_strength(store, new_password) {
+                return false
+            }
+            result = change_password(store, password, new_password, rotate_recovery_code);
+            result.is_ok()
+        };
+        prompt_for_password(store, set_new_password, false, true);
+        true
+    };
+
+    printer.println("Enter your current password".to_string());
+    prompt_for_password(store, change, true, false);
+    match result {
+        Ok(recovery_code) => {
+            printer.println("Password changed".to_string());
+            if let Some(recovery_code) = recovery_code {
+                printer.println(format!("🛟 Recovery code generated: {}", recovery_code));
+                printer.println("Your old recovery code no longer works, save this one instead.".to_string());
+            }
+        }
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::SetPasswordErr as i32)
+        }
+    }
+}
+
+pub(crate) fn export_archive_to_file(store: &SqliteStore, path: &str, query: &Query) {
+    let printer = TrustyPrinter {};
+    // the archive passphrase is separate from your password so that the archive can be opened on another vault
+    let archive = with_optional_boss_key(store, has_protected_notes(store), |boss_key| collect_matching_archive(store, boss_key, query))
+        .unwrap_or(Err("Password incorrect.".to_string()));
+    let archive = match archive {
+        Ok(archive) => archive,
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::ArchiveErr as i32)
+        }
+    };
+
+    let passphrase = prompt_for_passphrase(true);
+    match fs::write(path, seal_archive(&archive, &passphrase)) {
+        Ok(_) => {
+            printer.println(format!("Exported {} notes to: {}", archive.notes.len(), path));
+        }
+        Err(err) => {
+            printer.print_error(format!("Could not write the archive: {}", err));
+            exit(Errors::ArchiveErr as i32)
+        }
+    }
+}
+
+pub(crate) fn import_archive_from_file(cpo: &dyn PathOperations, store: &SqliteStore, path: &str, mode: Option<&str>) {
+    let printer = TrustyPrinter {};
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => {
+            printer.print_error(format!("Could not read the archive: {}", err));
+            exit(Errors::ArchiveErr as i32)
+        }
+    };
+    let archive = match open_archive(&text, &prompt_for_passphrase(false)) {
+        Ok(archive) => archive,
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::ArchiveErr as i32)
+        }
+    };
+    printer.println(format!("Archive exported {} with {} notes.", archive.exported, archive.notes.len()));
+
+    let mode = match mode {
+        Some(mode) => ImportMode::parse(mode),
+        None => {
+            printer.println("Merge the archive into your notes (m) or delete all of your notes and import the archive (o)? [m/o]".to_string());
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer).unwrap_or(0);
+            ImportMode::parse(&answer)
+        }
+    };
+    let mode = match mode {
+        Some(mode) => mode,
+        None => {
+            printer.print_error("Choose merge or overwrite.".to_string());
+            exit(Errors::ArchiveErr as i32)
+        }
+    };
+
+    if mode == ImportMode::Overwrite {
+        if !prompt_for_confirmation("Delete all of your notes and import the archive? A backup is saved first.") {
+            printer.println("Import cancelled.".to_string());
+            return
+        }
+        match create_backup(cpo) {
+            Ok(backup) => printer.println(format!("Your previous notes were saved as: {}", backup.manifest.file_name)),
+            Err(err) => {
+                printer.print_error(err);
+                exit(Errors::ArchiveErr as i32)
+            }
+        }
+    }
+
+    let needs_boss_key = archive.notes.iter().any(|note| note.protected);
+    let report = with_optional_boss_key(store, needs_boss_key, |boss_key| import_archive(store, &archive, mode, boss_key))
+        .unwrap_or(Err("Password incorrect.".to_string()));
+    match report {
+        Ok(report) => {
+            printer.println(format!("Imported {} notes, skipped {} notes already in your vault, {} config values set.",
+                                    report.imported, report.skipped, report.config_keys));
+        }
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::ArchiveErr as i32)
+        }
+    }
+}
+
+/**
+* Prints a note by ID, code and structured data are highlighted when printing to a terminal
+* @markdown - render text notes as markdown
+* @pretty - reformat JSON and YAML notes
+*/
+pub(crate) fn print_note(store: &dyn NoteStore, id: usize, markdown: bool, pretty: bool) {
+    let mut note = get_note_by_id(store, NoteId(id));
+    let content_type = get_content_type(store, &note.body, id);
+    if pretty {
+        match pretty_print(content_type, &note.body) {
+            Ok(body) => note.body = body,
+            Err(err) => {
+                TrustyPrinter {}.print_error(err);
+                exit(Errors::ContentTypeErr as i32)
+            }
+        }
+    }
+
+    print_rich_note(note, content_type, markdown);
+}
+
+pub(crate) fn pick_and_use_note(store: &SqliteStore, action: &str) {
+    let printer = TrustyPrinter {};
+    let notes = list_picker_notes(store);
+    let picked = match pick_note(&notes, PickAction::parse(action).unwrap_or(PickAction::Print)) {
+        Ok(picked) => picked,
+        Err(err) => {
+            printer.print_error(format!("Could not open the note picker: {}", err));
+            exit(Errors::PickerErr as i32)
+        }
+    };
+
+    match picked {
+        Some((id, PickAction::Print)) => {
+            let markdown = get_config_value(store, "rich_notes", "false").eq("true");
+            print_note(store, id, markdown, false);
+        }
+        Some((id, PickAction::Open)) => {
+            open_note(store, id, false);
+        }
+        Some((id, PickAction::Edit)) => {
+            edit_title(store, Some(id));
+            open_note(store, id, false);
+        }
+        None => {}
+    }
+}
+
+pub(crate) fn validate_note(store: &dyn NoteStore, id: usize) {
+    let printer = TrustyPrinter {};
+    let note = get_note_by_id(store, NoteId(id));
+    let content_type = get_content_type(store, &note.body, id);
+    match validate(content_type, &note.body) {
+        Ok(_) => printer.println(format!("Note {} is valid {} ✅", id, content_type.as_str().to_uppercase())),
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::ContentTypeErr as i32)
+        }
+    }
+}
+
+/**
+* The saved type of a note, or the type detected from its decrypted body when none was saved
+*/
+fn get_content_type(store: &dyn NoteStore, body: &str, id: usize) -> ContentType {
+    store.get_note(NoteId(id)).and_then(|note| note.content_type).unwrap_or_else(|| ContentType::detect(body))
+}
+
+pub(crate) fn set_content_type(store: &dyn NoteStore, id: usize, value: &str) {
+    let printer = TrustyPrinter {};
+    let content_type = match ContentType::parse(value) {
+        Some(content_type) => content_type,
+        None => {
+            printer.print_error(format!("Unknown content type: {}, use one of: text, json, yaml, sh, rust, diff, log", value));
+            exit(Errors::ContentTypeErr as i32)
+        }
+    };
+    if !store.set_content_type(NoteId(id), content_type) {
+        printer.print_error(format!("Could not find note for id: {}", id));
+        exit(Errors::NoteIdErr as i32)
+    }
+
+    printer.println(format!("Note {} is now {}.", id, content_type.as_str()));
+}
+
+pub(crate) fn get_time_display(store: &dyn NoteStore) -> TimeDisplay {
+    TimeDisplay::from_config(&get_config_value(store, "time_format", DEFAULT_TIME_FORMAT))
+}
+
+/**
+* Exits when the --since filter can't be parsed, the filter is returned as a UTC timestamp
+*/
+pub(crate) fn parse_since_filter(since: Option<&str>) -> Option<String> {
+    let since = since?;
+    match parse_since_local(since) {
+        Some(timestamp) => Some(timestamp),
+        None => {
+            TrustyPrinter {}.print_error(format!("Could not read the date: {}, ex: yesterday, 3 days ago, 12h or 2024-07-01.", since));
+            exit(Errors::DateParseErr as i32)
+        }
+    }
+}
+
+/**
+* Exits when the --query filter can't be parsed, no filter is an empty query that matches every note
+*/
+pub(crate) fn parse_query_filter(query: Option<&str>) -> Query {
+    let Some(query) = query else {
+        return Query::default()
+    };
+    match Query::parse(query) {
+        Ok(query) => query,
+        Err(err) => {
+            TrustyPrinter {}.print_error(format!("Could not read the query: {}", err));
+            exit(Errors::QueryParseErr as i32)
+        }
+    }
+}
+
+// the since filter is a SQLite UTC timestamp, see parse_since_filter
+pub(crate) fn filter_since(notes: Vec<Note>, since: &Option<String>) -> Vec<Note> {
+    match since.as_deref().and_then(parse_utc_timestamp) {
+        Some(since) => notes.into_iter().filter(|note| note.updated >= since).collect(),
+        None => notes
+    }
+}
+
+pub(crate) fn get_list_options(store: &dyn NoteStore, compact: bool, include_trashed: bool, since: Option<&str>, query: Option<&str>) -> ListOptions {
+    let compact = compact || get_config_value(store, "list_compact", "false").eq("true");
+
+    ListOptions::for_stdout(compact, include_trashed, get_time_display(store), parse_since_filter(since), parse_query_filter(query))
+}
+
+pub(crate) fn pin_note(store: &SqliteStore, id: usize, pinned: bool) {
+    let printer = TrustyPrinter {};
+    if !set_note_pinned(store, NoteId(id), pinned) {
+        printer.print_error(format!("Could not find note for id: {}", id));
+        exit(Errors::NoteIdErr as i32)
+    }
+
+    printer.println(format!("Note {} is {}.", id, if pinned { "pinned 📌" } else { "unpinned" }));
+}
+
+pub(crate) fn serve_api(store: &dyn NoteStore, addr: Option<&str>, socket: Option<&str>) {
+    let printer = TrustyPrinter {};
+    let server = match start_server(addr, socket) {
+        Ok(server) => server,
+        Err(err) => {
+            printer.print_error(err);
+            exit(Errors::ServeErr as i32)
+        }
+    };
+
+    let token = get_api_token(store);
+    match socket {
+        Some(path) => printer.println(format!("Serving your notes on {} 🛰️", path)),
+        None => printer.println(format!("Serving your notes on http://{} 🛰️", server.server_addr()))
+    }
+    printer.println(format!("Send this header with each request: Authorization: Bearer {}", token));
+    printer.println("Press Ctrl+C to stop.".to_string());
+    run_server(server, store, &token);
+}
+
+pub(crate) fn open_note(store: &dyn NoteStore, id: usize, protected: bool) -> bool  {
     if id > 0 {
-        let note = get_note_by_id(&TrustyPathOperations {}, id);
+        let note = get_note_by_id(store, NoteId(id));
         let body = note.body.as_str();
         let edited = edit::edit(body).unwrap();
 
-        update_note_by_note_id(cpo, id, &edited)
+        // protected notes are encrypted again before they are saved
+        let new_body = if note.protected { encrypt_note(store, note.note_id, "", &edited).1 } else { edited };
+        store.update_body(note.note_id, &new_body)
     } else {
         let draft = edit::edit("").unwrap();
-        let title = slice_text(0, 128, &draft);
+        let title = slice_text(0, MAX_TITLE_LENGTH, &draft);
 
-        add_note(cpo, &title, &draft, protected)
+        add_note(store, &title, &draft, protected)
     }
 }
\ No newline at end of file
@@ -0,0 +1,138 @@
+use std::io;
+use std::io::Write;
+
+use clap::CommandFactory;
+use clap_complete::{generate, Shell};
+
+use crate::cli::Cli;
+
+// flags that take note IDs, their short flag and which notes they complete
+const NOTE_ID_FLAGS: [(Option<char>, &str, &str); 6] = [
+    (Some('f'), "find", "notes"),
+    (Some('o'), "open", "notes"),
+    (None, "trash", "notes"),
+    (None, "restore", "trashed"),
+    (Some('p'), "protect", "notes"),
+    (Some('u'), "unprotect", "notes"),
+];
+
+// _describe splits each entry on the first unescaped colon, so colons in titles are escaped
+const ZSH_NOTE_IDS: &str = r#"_tru_note_ids() {
+    local -a notes
+    local line
+    for line in "${(@f)$(tru --complete-notes $1 2>/dev/null)}"; do
+        [[ -n $line ]] || continue
+        notes+=("${line%%$'\t'*}:${${line#*$'\t'}//:/\\:}")
+    done
+    _describe -t notes 'note' notes
+}
+
+"#;
+
+/**
+* Finds the note flag a line of a completion script is about
+* @pattern - builds the text that marks the flag, ex: |flag| format!("--{})", flag)
+*/
+fn find_note_flag(line: &str, pattern: impl Fn(&str) -> String) -> Option<&'static str> {
+    NOTE_ID_FLAGS.iter()
+        .find(|(short, long, _)| line.starts_with(&pattern(&format!("--{}", long))) || short.map(|short| line.starts_with(&pattern(&format!("-{}", short)))).unwrap_or(false))
+        .map(|(_, _, kind)| *kind)
+}
+
+/**
+* Adds note ID completion to the script clap generates, the IDs come from tru --complete-notes
+*/
+fn add_note_completions(shell: Shell, script: &str) -> String {
+    let mut lines = vec![];
+    let mut pending_kind = None;
+    for line in script.lines() {
+        let trimmed = line.trim_start();
+        let line = match shell {
+            // case branches are followed by a line that completes file names
+            Shell::Bash => {
+                if let Some(kind) = find_note_flag(trimmed, |flag| format!("{})", flag)) {
+                    pending_kind = Some(kind);
+                    line.to_string()
+                } else if let Some(kind) = pending_kind.take() {
+                    line.replace("compgen -f \"${cur}\"", &format!("compgen -W \"$(tru --complete-notes {} 2>/dev/null | cut -f1)\" -- \"${{cur}}\"", kind))
+                } else {
+                    line.to_string()
+                }
+            }
+            Shell::Zsh => {
+                if trimmed.starts_with("if [ \"$funcstack[1]\" = \"_tru\" ]") {
+                    lines.push(ZSH_NOTE_IDS.trim_end().to_string());
+                    lines.push(String::new());
+                }
+                match find_note_flag(trimmed, |flag| format!("'{}{}[", flag, if flag.starts_with("--") { "=" } else { "+" })) {
+                    Some(kind) => line.replace(":_default'", &format!(":_tru_note_ids {}'", kind)),
+                    None => line.to_string()
+                }
+            }
+            Shell::Fish => {
+                let flag = NOTE_ID_FLAGS.iter().find(|(_, long, _)| trimmed.starts_with("complete -c tru") && trimmed.contains(&format!(" -l {} ", long)));
+                match flag {
+                    Some((_, _, kind)) => format!("{} -f -a \"(tru --complete-notes {})\"", line, kind),
+                    None => line.to_string()
+                }
+            }
+            _ => line.to_string()
+        };
+        lines.push(line);
+    }
+
+    format!("{}\n", lines.join("\n"))
+}
+
+/**
+* Writes a completion script, bash, zsh and fish also complete note IDs (and titles for zsh and fish)
+*/
+pub fn write_completions(shell: Shell, out: &mut dyn Write) -> io::Result<()> {
+    let mut script = vec![];
+    generate(shell, &mut Cli::command(), "tru", &mut script);
+    let script = String::from_utf8_lossy(&script);
+    out.write_all(add_note_completions(shell, &script).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap_complete::Shell;
+
+    use crate::completions::write_completions;
+
+    fn completions(shell: Shell) -> String {
+        let mut out = vec![];
+        write_completions(shell, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_bash_completions() {
+        let script = completions(Shell::Bash);
+        let note_ids = "compgen -W \"$(tru --complete-notes notes 2>/dev/null | cut -f1)\" -- \"${cur}\"";
+        // -f, --find, -o, --open, --trash, -p, --protect, -u, --unprotect
+        assert_eq!(script.matches(note_ids).count(), 9);
+        assert_eq!(script.matches("tru --complete-notes trashed").count(), 1);
+        // other flags still complete file names
+        assert!(script.contains("--export-archive)\n                    COMPREPLY=($(compgen -f \"${cur}\"))"));
+    }
+
+    #[test]
+    fn test_zsh_completions() {
+        let script = completions(Shell::Zsh);
+        assert!(script.contains("'--find=[Use this flag to specify an ID to print a saved note.]:FIND:_tru_note_ids notes'"));
+        assert!(script.contains("::IDS:_tru_note_ids trashed'"));
+        assert!(!script.contains("'--restore-backup=[Replace your notes with a backup, use a file name from --backups or a path.]:RESTORE_BACKUP:_tru_note_ids"));
+        // the helper has to be defined before _tru runs
+        assert!(script.contains(r#"${${line#*$'\t'}//:/\\:}"#));
+        assert!(script.find("_tru_note_ids() {").unwrap() < script.find("if [ \"$funcstack[1]\" = \"_tru\" ]").unwrap());
+    }
+
+    #[test]
+    fn test_fish_completions() {
+        let script = completions(Shell::Fish);
+        assert!(script.lines().any(|line| line.starts_with("complete -c tru -s f -l find ") && line.ends_with("-f -a \"(tru --complete-notes notes)\"")));
+        assert!(script.lines().any(|line| line.contains(" -l restore ") && line.ends_with("(tru --complete-notes trashed)\"")));
+        assert!(!script.lines().any(|line| line.contains(" -l find-from ") && line.contains("--complete-notes")));
+    }
+}
@@ -0,0 +1,150 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    Text,
+    Json,
+    Yaml,
+    Sh,
+    Rust,
+    Diff,
+    Log
+}
+
+impl ContentType {
+    pub fn parse(value: &str) -> Option<ContentType> {
+        match value.trim().to_lowercase().as_str() {
+            "text" | "txt" | "markdown" | "md" => Some(ContentType::Text),
+            "json" => Some(ContentType::Json),
+            "yaml" | "yml" => Some(ContentType::Yaml),
+            "sh" | "shell" | "bash" | "zsh" => Some(ContentType::Sh),
+            "rust" | "rs" => Some(ContentType::Rust),
+            "diff" | "patch" => Some(ContentType::Diff),
+            "log" => Some(ContentType::Log),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentType::Text => "text",
+            ContentType::Json => "json",
+            ContentType::Yaml => "yaml",
+            ContentType::Sh => "sh",
+            ContentType::Rust => "rust",
+            ContentType::Diff => "diff",
+            ContentType::Log => "log"
+        }
+    }
+
+    /**
+    * Guesses the content type from the body, anything that is not clearly structured is text
+    */
+    pub fn detect(body: &str) -> ContentType {
+        let trimmed = body.trim();
+        let lines = trimmed.lines().filter(|line| !line.trim().is_empty()).collect::<Vec<&str>>();
+        if lines.is_empty() {
+            return ContentType::Text
+        }
+
+        if (trimmed.starts_with('{') || trimmed.starts_with('[')) && serde_json::from_str::<serde_json::Value>(trimmed).is_ok() {
+            return ContentType::Json
+        }
+
+        let first_line = lines[0];
+        if first_line.starts_with("diff --git") || lines.windows(2).any(|pair| pair[0].starts_with("--- ") && pair[1].starts_with("+++ ")) {
+            return ContentType::Diff
+        }
+
+        if Regex::new(r"^#!.*\b(sh|bash|zsh|dash|ksh)\b").unwrap().is_match(first_line) || lines.iter().all(|line| line.starts_with("$ ")) {
+            return ContentType::Sh
+        }
+
+        let rust_re = Regex::new(r"^\s*(pub(\(crate\))?\s+)?(fn|struct|enum|impl|trait|mod|use)\s+\w").unwrap();
+        if lines.iter().any(|line| rust_re.is_match(line)) && (trimmed.contains(';') || trimmed.contains('{')) && !trimmed.contains("function") {
+            return ContentType::Rust
+        }
+
+        let log_re = Regex::new(r"^\[?\d{4}-\d{2}-\d{2}[ T]\d{2}:\d{2}|^\s*(TRACE|DEBUG|INFO|WARN|WARNING|ERROR|FATAL)\b|^\s+at |^Traceback \(most recent call last\)|panicked at").unwrap();
+        let log_lines = lines.iter().filter(|line| log_re.is_match(line)).count();
+        if log_lines * 2 > lines.len() || trimmed.starts_with("Traceback (most recent call last)") {
+            return ContentType::Log
+        }
+
+        // plain sentences like "Note: foo" would pass as YAML, so every line has to look like YAML
+        let yaml_re = Regex::new(r"^(---|\s*#.*|\s*-\s.*|\s*-$|\s*[\w.\-]+:(\s.*)?)$").unwrap();
+        let keys = lines.iter().filter(|line| line.contains(':')).count();
+        if lines.len() >= 2 && keys >= 2 && lines.iter().all(|line| yaml_re.is_match(line)) && validate(ContentType::Yaml, body).is_ok() {
+            return ContentType::Yaml
+        }
+
+        ContentType::Text
+    }
+}
+
+/**
+* Checks that a JSON or YAML body can be parsed, the error says where parsing failed
+*/
+pub fn validate(content_type: ContentType, body: &str) -> Result<(), String> {
+    match content_type {
+        ContentType::Json => serde_json::from_str::<serde_json::Value>(body).map(|_| ()).map_err(|err| format!("Invalid JSON: {}", err)),
+        ContentType::Yaml => serde_yaml::from_str::<serde_yaml::Value>(body).map(|_| ()).map_err(|err| format!("Invalid YAML: {}", err)),
+        _ => Err(format!("Only JSON and YAML notes can be validated, this note is {}.", content_type.as_str()))
+    }
+}
+
+pub fn pretty_print(content_type: ContentType, body: &str) -> Result<String, String> {
+    validate(content_type, body)?;
+    match content_type {
+        ContentType::Json => {
+            let value = serde_json::from_str::<serde_json::Value>(body).unwrap();
+            Ok(serde_json::to_string_pretty(&value).unwrap())
+        }
+        _ => {
+            let value = serde_yaml::from_str::<serde_yaml::Value>(body).unwrap();
+            serde_yaml::to_string(&value).map(|text| text.trim_end().to_string()).map_err(|err| err.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::content_type::{ContentType, pretty_print, validate};
+
+    #[test]
+    fn test_parse_content_type() {
+        assert_eq!(ContentType::parse("JSON"), Some(ContentType::Json));
+        assert_eq!(ContentType::parse("yml"), Some(ContentType::Yaml));
+        assert_eq!(ContentType::parse("bash"), Some(ContentType::Sh));
+        assert_eq!(ContentType::parse("patch"), Some(ContentType::Diff));
+        assert_eq!(ContentType::parse("foo"), None);
+        assert_eq!(ContentType::parse(ContentType::Rust.as_str()), Some(ContentType::Rust));
+    }
+
+    #[test]
+    fn test_detect_content_type() {
+        assert_eq!(ContentType::detect("{\"foo\": [1, 2]}"), ContentType::Json);
+        assert_eq!(ContentType::detect("[1, 2"), ContentType::Text);
+        assert_eq!(ContentType::detect("name: trusty\nversion: 1.0.5\ntags:\n  - notes"), ContentType::Yaml);
+        assert_eq!(ContentType::detect("Note: buy milk\nand eggs"), ContentType::Text);
+        assert_eq!(ContentType::detect("#!/usr/bin/env bash\necho hi"), ContentType::Sh);
+        assert_eq!(ContentType::detect("$ cargo build\n$ cargo test"), ContentType::Sh);
+        assert_eq!(ContentType::detect("use std::io;\n\nfn main() {\n    println!(\"hi\");\n}"), ContentType::Rust);
+        assert_eq!(ContentType::detect("diff --git a/foo b/foo\n--- a/foo\n+++ b/foo\n@@ -1 +1 @@\n-a\n+b"), ContentType::Diff);
+        assert_eq!(ContentType::detect("2024-07-01 22:56:27 INFO started\n2024-07-01 22:56:28 ERROR failed"), ContentType::Log);
+        assert_eq!(ContentType::detect("Traceback (most recent call last):\n  File \"foo.py\", line 1\nNameError: foo"), ContentType::Log);
+        assert_eq!(ContentType::detect("Welcome to tRusty the CLI notes app. -Ron"), ContentType::Text);
+        assert_eq!(ContentType::detect(""), ContentType::Text);
+    }
+
+    #[test]
+    fn test_pretty_print() {
+        assert_eq!(pretty_print(ContentType::Json, "{\"foo\":[1,2]}").unwrap(), "{\n  \"foo\": [\n    1,\n    2\n  ]\n}");
+        assert_eq!(pretty_print(ContentType::Yaml, "{foo: [1, 2]}").unwrap(), "foo:\n- 1\n- 2");
+        assert!(pretty_print(ContentType::Json, "{\"foo\":").unwrap_err().starts_with("Invalid JSON"));
+        assert!(validate(ContentType::Yaml, "foo: [1, 2").is_err());
+        assert!(validate(ContentType::Sh, "echo hi").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono::format::{Item, StrftimeItems};
+use regex::Regex;
+
+// the format SQLite uses for CURRENT_TIMESTAMP, always UTC
+const SQLITE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+// wide enough for the longest relative time, ex: "59 minutes ago"
+const RELATIVE_WIDTH: usize = 14;
+
+/**
+* How timestamps are shown, set with the time_format config value
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeDisplay {
+    // as stored, in UTC
+    Utc,
+    // in the local timezone with a strftime format
+    Local(String),
+    // ex: 3 hours ago
+    Relative
+}
+
+impl TimeDisplay {
+    /**
+    * @value - "relative", "utc" or a strftime format, invalid formats fall back to the default
+    */
+    pub fn from_config(value: &str) -> TimeDisplay {
+        match value.trim() {
+            "relative" => TimeDisplay::Relative,
+            "utc" => TimeDisplay::Utc,
+            format if is_valid_format(format) => TimeDisplay::Local(format.to_string()),
+            _ => TimeDisplay::Local(DEFAULT_TIME_FORMAT.to_string())
+        }
+    }
+
+    pub fn format(&self, utc_timestamp: &str) -> String {
+        self.format_at(utc_timestamp, Utc::now())
+    }
+
+    /**
+    * Timestamps that can't be parsed are shown as they are
+    */
+    pub fn format_at(&self, utc_timestamp: &str, now: DateTime<Utc>) -> String {
+        let time = match parse_utc_timestamp(utc_timestamp) {
+            Some(time) => time,
+            None => return utc_timestamp.to_string()
+        };
+        match self {
+            TimeDisplay::Utc => utc_timestamp.to_string(),
+            TimeDisplay::Local(format) => time.with_timezone(&Local).format(format).to_string(),
+            TimeDisplay::Relative => format!("{:>width$}", format_relative(time, now), width = RELATIVE_WIDTH)
+        }
+    }
+}
+
+impl Default for TimeDisplay {
+    fn default() -> Self {
+        TimeDisplay::Local(DEFAULT_TIME_FORMAT.to_string())
+    }
+}
+
+fn is_valid_format(format: &str) -> bool {
+    !format.is_empty() && StrftimeItems::new(format).all(|item| item != Item::Error)
+}
+
+pub fn parse_utc_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    // some timestamps carry fractional seconds, ex: 2024-07-01 22:56:27.123
+    let timestamp = timestamp.trim().split('.').next()?;
+    NaiveDateTime::parse_from_str(timestamp, SQLITE_FORMAT).ok().map(|time| time.and_utc())
+}
+
+pub fn to_sqlite_timestamp(time: DateTime<Utc>) -> String {
+    time.format(SQLITE_FORMAT).to_string()
+}
+
+fn plural(count: i64, unit: &str) -> String {
+    if count == 1 { format!("1 {}", unit) } else { format!("{} {}s", count, unit) }
+}
+
+pub fn format_relative(time: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let seconds = (now - time).num_seconds();
+    let past = seconds >= 0;
+    let seconds = seconds.abs();
+    let minutes = seconds / 60;
+    let hours = minutes / 60;
+    let days = hours / 24;
+
+    let amount = match days {
+        _ if seconds < 60 => return "just now".to_string(),
+        _ if minutes < 60 => plural(minutes, "minute"),
+        _ if hours < 24 => plural(hours, "hour"),
+        1 => return if past { "yesterday".to_string() } else { "tomorrow".to_string() },
+        2..=29 => plural(days, "day"),
+        30..=364 => plural(days / 30, "month"),
+        _ => plural(days / 365, "year")
+    };
+
+    if past { format!("{} ago", amount) } else { format!("in {}", amount) }
+}
+
+fn local_midnight<Tz: TimeZone>(date: NaiveDate, timezone: &Tz) -> Option<DateTime<Utc>> {
+    timezone.from_local_datetime(&date.and_hms_opt(0, 0, 0)?).earliest().map(|time| time.with_timezone(&Utc))
+}
+
+/**
+* Turns a filter like --since yesterday into a UTC time
+* @text - now, today, yesterday, 3 days ago, 12h, 2w, last week, 2024-07-01 or 2024-07-01 09:30 (local time)
+*/
+pub fn parse_since<Tz: TimeZone>(text: &str, now: DateTime<Tz>) -> Option<DateTime<Utc>> {
+    let text = text.trim().to_lowercase();
+    let timezone = now.timezone();
+    let today = now.naive_local().date();
+    match text.as_str() {
+        "now" => return Some(now.with_timezone(&Utc)),
+        "today" => return local_midnight(today, &timezone),
+        "yesterday" => return local_midnight(today.pred_opt()?, &timezone),
+        _ => {}
+    }
+
+    let ago_re = Regex::new(r"^(?:(\d+)\s*|last\s+)(m|mins?|minutes?|h|hours?|d|days?|w|weeks?|months?|y|years?)(?:\s+ago)?$").unwrap();
+    if let Some(caps) = ago_re.captures(&text) {
+        let count = caps.get(1).map(|count| count.as_str().parse::<i64>()).unwrap_or(Ok(1)).ok()?;
+        let unit = &caps[2];
+        let duration = match unit.chars().next()? {
+            'm' if unit.starts_with("mo") => Duration::days(30 * count),
+            'm' => Duration::minutes(count),
+            'h' => Duration::hours(count),
+            'd' => Duration::days(count),
+            'w' => Duration::weeks(count),
+            _ => Duration::days(365 * count)
+        };
+        return Some(now.with_timezone(&Utc) - duration)
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(&text, "%Y-%m-%d") {
+        return local_midnight(date, &timezone)
+    }
+    ["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S"].iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&text, format).ok())
+        .and_then(|time| timezone.from_local_datetime(&time).earliest())
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/**
+* Parses a --since filter relative to the local time, the result is a SQLite UTC timestamp
+*/
+pub fn parse_since_local(text: &str) -> Option<String> {
+    parse_since(text, Local::now()).map(to_sqlite_timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, TimeZone, Utc};
+
+    use crate::dates::{format_relative, parse_since, parse_utc_timestamp, TimeDisplay, to_sqlite_timestamp};
+
+    #[test]
+    fn test_time_display_from_config() {
+        assert_eq!(TimeDisplay::from_config("relative"), TimeDisplay::Relative);
+        assert_eq!(TimeDisplay::from_config("utc"), TimeDisplay::Utc);
+        assert_eq!(TimeDisplay::from_config("%d/%m/%Y %H:%M"), TimeDisplay::Local("%d/%m/%Y %H:%M".to_string()));
+        assert_eq!(TimeDisplay::from_config("%Q"), TimeDisplay::default());
+        assert_eq!(TimeDisplay::from_config(""), TimeDisplay::default());
+    }
+
+    #[test]
+    fn test_format_timestamps() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 2, 12, 0, 0).unwrap();
+        assert_eq!(TimeDisplay::Utc.format_at("2024-07-01 22:56:27", now), "2024-07-01 22:56:27");
+        assert_eq!(TimeDisplay::Relative.format_at("2024-07-02 09:00:00", now), "   3 hours ago");
+        assert_eq!(TimeDisplay::Relative.format_at("", now), "");
+        assert_eq!(parse_utc_timestamp("2024-07-01 22:56:27.123"), Some(Utc.with_ymd_and_hms(2024, 7, 1, 22, 56, 27).unwrap()));
+    }
+
+    #[test]
+    fn test_format_relative() {
+        let now = Utc.with_ymd_and_hms(2024, 7, 10, 12, 0, 0).unwrap();
+        let relative = |y, mo, d, h, mi| format_relative(Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap(), now);
+        assert_eq!(relative(2024, 7, 10, 11, 59), "1 minute ago");
+        assert_eq!(relative(2024, 7, 10, 12, 0), "just now");
+        assert_eq!(relative(2024, 7, 10, 9, 0), "3 hours ago");
+        assert_eq!(relative(2024, 7, 9, 6, 0), "yesterday");
+        assert_eq!(relative(2024, 7, 5, 12, 0), "5 days ago");
+        assert_eq!(relative(2024, 5, 1, 12, 0), "2 months ago");
+        assert_eq!(relative(2022, 7, 1, 12, 0), "2 years ago");
+        assert_eq!(relative(2024, 7, 10, 14, 30), "in 2 hours");
+        assert_eq!(relative(2024, 7, 11, 18, 0), "tomorrow");
+    }
+
+    #[test]
+    fn test_parse_since() {
+        // 10:30 on July 10th in New York (UTC-4)
+        let now = FixedOffset::west_opt(4 * 3600).unwrap().with_ymd_and_hms(2024, 7, 10, 10, 30, 0).unwrap();
+        let since = |text| parse_since(text, now).map(to_sqlite_timestamp);
+        assert_eq!(since("now"), Some("2024-07-10 14:30:00".to_string()));
+        assert_eq!(since("today"), Some("2024-07-10 04:00:00".to_string()));
+        assert_eq!(since("Yesterday"), Some("2024-07-09 04:00:00".to_string()));
+        assert_eq!(since("3 hours ago"), Some("2024-07-10 11:30:00".to_string()));
+        assert_eq!(since("2d"), Some("2024-07-08 14:30:00".to_string()));
+        assert_eq!(since("last week"), Some("2024-07-03 14:30:00".to_string()));
+        assert_eq!(since("1 month ago"), Some("2024-06-10 14:30:00".to_string()));
+        assert_eq!(since("15 min ago"), Some("2024-07-10 14:15:00".to_string()));
+        assert_eq!(since("2024-07-01"), Some("2024-07-01 04:00:00".to_string()));
+        assert_eq!(since("2024-07-01 09:30"), Some("2024-07-01 13:30:00".to_string()));
+        assert_eq!(since("someday"), None);
+    }
+}
@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use rusqlite::{Connection, params};
+
+use crate::password::normalize_password;
+
+// every plain text SQLite file starts with this, an encrypted one starts with random bytes
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+// scripts, completions and editor plugins can set the database key instead of typing it
+pub const DB_KEY_ENV: &str = "TRUSTY_DB_KEY";
+
+thread_local! {
+    // the database key is asked for once and used by every connection for the rest of the session
+    static DB_KEY: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/**
+* Whole database encryption needs tRusty to be built with --features sqlcipher
+*/
+pub fn is_encryption_supported() -> bool {
+    cfg!(feature = "sqlcipher")
+}
+
+/**
+* Checks the file header, a missing or empty file is a new plain text database
+*/
+pub fn is_encrypted_db(path: &Path) -> bool {
+    let mut header = [0u8; 16];
+    match fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(_) => !header.eq(SQLITE_HEADER),
+        Err(_) => false
+    }
+}
+
+/**
+* Sets the key connections are opened with, None for a plain text database
+*/
+pub fn set_db_key(key: Option<&str>) {
+    DB_KEY.with(|db_key| *db_key.borrow_mut() = key.map(normalize_password));
+}
+
+pub(crate) fn apply_db_key(conn: &Connection) -> rusqlite::Result<()> {
+    DB_KEY.with(|db_key| match db_key.borrow().as_deref() {
+        Some(key) => conn.pragma_update(None, "key", key),
+        None => Ok(())
+    })
+}
+
+/**
+* SQLCipher only finds out that a key is wrong when the first page is read
+*/
+pub fn check_db_key(path: &Path, key: &str) -> bool {
+    let Ok(conn) = Connection::open(path) else {
+        return false
+    };
+
+    conn.pragma_update(None, "key", normalize_password(key)).is_ok()
+        && conn.query_row("SELECT COUNT(*) FROM sqlite_master;", [], |row| row.get::<usize, usize>(0)).is_ok()
+}
+
+/**
+* Returns the key from TRUSTY_DB_KEY when it unlocks the database
+*/
+pub fn db_key_from_env(path: &Path) -> Option<String> {
+    env::var(DB_KEY_ENV).ok().filter(|key| check_db_key(path, key))
+}
+
+/**
+* Encrypts a plain text database with a key, the session key is set so the database can still be used
+*/
+pub fn encrypt_database(path: &Path, key: &str) -> Result<(), String> {
+    if !is_encryption_supported() {
+        return Err("This build of tRusty can not encrypt databases, build it with --features sqlcipher.".to_string())
+    }
+    if is_encrypted_db(path) {
+        return Err("Your database is already encrypted.".to_string())
+    }
+    let key = normalize_password(key);
+    if key.trim().is_empty() {
+        return Err("The database key may not be blank.".to_string())
+    }
+
+    convert_database(path, &key)?;
+    set_db_key(Some(&key));
+
+    Ok(())
+}
+
+/**
+* Writes an encrypted database back out as plain text, it has to be unlocked with the session key first
+*/
+pub fn decrypt_database(path: &Path) -> Result<(), String> {
+    if !is_encryption_supported() {
+        return Err("This build of tRusty can not decrypt databases, build it with --features sqlcipher.".to_string())
+    }
+    if !is_encrypted_db(path) {
+        return Err("Your database is not encrypted.".to_string())
+    }
+
+    convert_database(path, "")?;
+    set_db_key(None);
+
+    Ok(())
+}
+
+/**
+* Exports the database into a new file with another key and swaps the files, the old file is untouched if anything fails
+* @new_key - an empty key writes a plain text database
+*/
+fn convert_database(path: &Path, new_key: &str) -> Result<(), String> {
+    let converted_path = path.with_extension("converting");
+    if converted_path.exists() {
+        fs::remove_file(&converted_path).map_err(|err| format!("Could not remove {}: {}", converted_path.display(), err))?;
+    }
+
+    let export = || -> rusqlite::Result<()> {
+        let conn = Connection::open(path)?;
+        apply_db_key(&conn)?;
+        conn.execute("ATTACH DATABASE ?1 AS converted KEY ?2;", params![converted_path.to_string_lossy(), new_key])?;
+        conn.query_row("SELECT sqlcipher_export('converted');", [], |_| Ok(()))?;
+        conn.execute("DETACH DATABASE converted;", [])?;
+        Ok(())
+    };
+    if let Err(err) = export() {
+        fs::remove_file(&converted_path).unwrap_or(());
+        return Err(format!("Could not convert your database, nothing was changed: {}", err))
+    }
+
+    fs::rename(&converted_path, path).map_err(|err| format!("Could not replace your database: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::db_encryption::{decrypt_database, encrypt_database, is_encrypted_db, is_encryption_supported};
+    use crate::setup::get_db_conn;
+
+    #[test]
+    fn test_is_encrypted_db() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trusty.db");
+        assert!(!is_encrypted_db(&path));
+        get_db_conn(&path).execute("CREATE TABLE notes (note_id INTEGER);", ()).unwrap();
+        assert!(!is_encrypted_db(&path));
+
+        fs::write(&path, [7u8; 64]).unwrap();
+        assert!(is_encrypted_db(&path));
+    }
+
+    #[test]
+    fn test_convert_without_sqlcipher() {
+        if is_encryption_supported() {
+            return
+        }
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("trusty.db");
+        get_db_conn(&path).execute("CREATE TABLE notes (note_id INTEGER);", ()).unwrap();
+
+        assert!(encrypt_database(&path, "correct horse").unwrap_err().contains("--features sqlcipher"));
+        assert!(decrypt_database(&path).is_err());
+        assert!(!is_encrypted_db(&path));
+    }
+}
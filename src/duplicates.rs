@@ -0,0 +1,118 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::note::{Note, NoteId};
+use crate::utils::{hash_text, word_set, word_similarity};
+
+// notes that share at least this much of their words are reported as near duplicates
+const NEAR_DUPLICATE_THRESHOLD: f64 = 0.8;
+
+#[derive(Debug, PartialEq)]
+pub enum DuplicatePolicy {
+    Allow,
+    Warn,
+    Skip
+}
+
+impl DuplicatePolicy {
+    /**
+    * Reads the duplicate_policy config value, anything unknown falls back to warn
+    */
+    pub fn from_config(value: &str) -> DuplicatePolicy {
+        match value.trim().to_lowercase().as_str() {
+            "allow" => DuplicatePolicy::Allow,
+            "skip" => DuplicatePolicy::Skip,
+            _ => DuplicatePolicy::Warn
+        }
+    }
+}
+
+pub struct NearDuplicate {
+    pub note_id: NoteId,
+    pub other_note_id: NoteId,
+    pub similarity: f64
+}
+
+pub struct DuplicateReport {
+    // each group holds the ids of notes with identical bodies
+    pub exact: Vec<Vec<NoteId>>,
+    pub near: Vec<NearDuplicate>
+}
+
+pub fn find_duplicates(notes: &[Note]) -> DuplicateReport {
+    let mut groups: HashMap<String, Vec<NoteId>> = HashMap::new();
+    for note in notes {
+        groups.entry(hash_text(&note.body)).or_default().push(note.id);
+    }
+
+    let mut exact = groups.into_values().filter(|ids| ids.len() > 1).collect::<Vec<Vec<NoteId>>>();
+    exact.sort();
+
+    // each body is split into words once instead of once per pair
+    let word_sets = notes.iter().map(|note| word_set(&note.body)).collect::<Vec<HashSet<String>>>();
+    let mut near = vec![];
+    for (index, note) in notes.iter().enumerate() {
+        for (other_index, other) in notes.iter().enumerate().skip(index + 1) {
+            if note.body.eq(&other.body) {
+                continue
+            }
+            let similarity = word_similarity(&word_sets[index], &word_sets[other_index]);
+            if similarity >= NEAR_DUPLICATE_THRESHOLD {
+                near.push(NearDuplicate {
+                    note_id: note.id,
+                    other_note_id: other.id,
+                    similarity,
+                });
+            }
+        }
+    }
+
+    DuplicateReport { exact, near }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::duplicates::{DuplicatePolicy, find_duplicates};
+    use chrono::Utc;
+
+    use crate::note::{ContentId, Note, NoteId};
+
+    fn make_note(note_id: usize, body: &str) -> Note {
+        Note {
+            id: NoteId(note_id),
+            content_id: ContentId::from(format!("content-{}", note_id)),
+            title: "".to_string(),
+            body: body.to_string(),
+            protected: false,
+            trashed: false,
+            created: Utc::now(),
+            updated: Utc::now(),
+            due: None,
+            content_type: None,
+            data_key: None,
+        }
+    }
+
+    #[test]
+    fn test_duplicate_policy_from_config() {
+        assert_eq!(DuplicatePolicy::from_config("Skip"), DuplicatePolicy::Skip);
+        assert_eq!(DuplicatePolicy::from_config("allow"), DuplicatePolicy::Allow);
+        assert_eq!(DuplicatePolicy::from_config("warn"), DuplicatePolicy::Warn);
+        assert_eq!(DuplicatePolicy::from_config("foo"), DuplicatePolicy::Warn);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let notes = vec![
+            make_note(1, "{\"status\": \"ok\", \"count\": 1}"),
+            make_note(2, "something else entirely"),
+            make_note(3, "{\"status\": \"ok\", \"count\": 1}"),
+            make_note(4, "one two three four five six seven eight nine ten"),
+            make_note(5, "one two three four five six seven eight nine ten eleven"),
+        ];
+        let report = find_duplicates(&notes);
+        assert_eq!(report.exact, vec![vec![NoteId(1), NoteId(3)]]);
+        assert_eq!(report.near.len(), 1);
+        assert_eq!(report.near[0].note_id, NoteId(4));
+        assert_eq!(report.near[0].other_note_id, NoteId(5));
+    }
+}
@@ -15,5 +15,24 @@ pub enum Errors {
     KeyValInsertErr = 514,
     KeyValSelectErr = 515,
     KeyValUpdateErr = 516,
-    InitDBErr = 517
+    InitDBErr = 517,
+    MigrateDBErr = 518,
+    DueDateErr = 519,
+    ChecklistItemErr = 520,
+    MergeNoteErr = 521,
+    NoteIdListErr = 522,
+    BackupErr = 523,
+    RestoreBackupErr = 524,
+    ArchiveErr = 525,
+    SyncErr = 526,
+    GitSyncErr = 527,
+    ServeErr = 528,
+    RpcErr = 529,
+    ContentTypeErr = 530,
+    DateParseErr = 531,
+    PickerErr = 532,
+    QueryParseErr = 533,
+    GrepErr = 534,
+    RotateKeyErr = 535,
+    DbEncryptionErr = 536
 }
\ No newline at end of file
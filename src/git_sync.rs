@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use uuid::Uuid;
+
+use crate::setup::PathOperations;
+use crate::sql::{get_config_value, get_current_timestamp};
+use crate::store::SqliteStore;
+use crate::sync::{delete_note, read_notes, read_tombstones, Side, SyncChange, SyncChangeKind, SyncNote, SyncReport, write_note};
+
+const NOTES_DIR: &str = "notes";
+const NOTE_EXTENSION: &str = "txt";
+const HEADER_END: &str = "---";
+
+/**
+* Writes a note as a file with a small header, protected notes are written exactly as they are stored (encrypted)
+*/
+pub(crate) fn format_note_file(note: &SyncNote) -> String {
+    format!("title: {}\nprotected: {}\ntrashed: {}\ncreated: {}\nupdated: {}\ndue: {}\ncontent_type: {}\ndata_key: {}\n{}\n{}",
+            note.title, note.protected, note.trashed, note.created, note.updated, note.due.as_deref().unwrap_or(""),
+            note.content_type.as_deref().unwrap_or(""), note.data_key.as_deref().unwrap_or(""), HEADER_END, note.body)
+}
+
+pub(crate) fn parse_note_file(content_id: &str, text: &str) -> Option<SyncNote> {
+    let mut note = SyncNote {
+        content_id: content_id.to_string(),
+        title: "".to_string(),
+        body: "".to_string(),
+        protected: false,
+        trashed: false,
+        created: "".to_string(),
+        updated: "".to_string(),
+        due: None,
+        content_type: None,
+        data_key: None,
+    };
+
+    let mut rest = text;
+    loop {
+        let (line, remaining) = rest.split_once('\n')?;
+        rest = remaining;
+        if line.eq(HEADER_END) {
+            break
+        }
+        let (key, value) = line.split_once(": ").unwrap_or((line.trim_end_matches(':'), ""));
+        match key {
+            "title" => note.title = value.to_string(),
+            "protected" => note.protected = value.eq("true"),
+            "trashed" => note.trashed = value.eq("true"),
+            "created" => note.created = value.to_string(),
+            "updated" => note.updated = value.to_string(),
+            "due" => note.due = if value.is_empty() { None } else { Some(value.to_string()) },
+            "content_type" => note.content_type = if value.is_empty() { None } else { Some(value.to_string()) },
+            "data_key" => note.data_key = if value.is_empty() { None } else { Some(value.to_string()) },
+            _ => {}
+        }
+    }
+    note.body = rest.to_string();
+
+    Some(note)
+}
+
+/**
+* Summarizes the changes for a commit, the subject names the note when only one note changed
+*/
+pub fn format_commit_message(changes: &[SyncChange]) -> String {
+    let describe = |kind: &SyncChangeKind| match kind {
+        SyncChangeKind::Added => "Add",
+        SyncChangeKind::Updated => "Update",
+        SyncChangeKind::Trashed => "Trash",
+        SyncChangeKind::Restored => "Restore",
+        SyncChangeKind::Deleted => "Delete",
+        SyncChangeKind::ConflictCopy => "Add conflict copy of"
+    };
+
+    if changes.len() == 1 {
+        return format!("{} note: {}", describe(&changes[0].kind), changes[0].title)
+    }
+
+    let mut counts: Vec<(&str, usize)> = vec![];
+    for change in changes {
+        let kind = describe(&change.kind);
+        match counts.iter_mut().find(|(count_kind, _)| count_kind.eq(&kind)) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((kind, 1))
+        }
+    }
+    let subject = counts.iter().map(|(kind, count)| format!("{} {}", kind, count)).collect::<Vec<String>>().join(", ");
+    let body = changes.iter().map(|change| format!("{}: {}", describe(&change.kind), change.title)).collect::<Vec<String>>().join("\n");
+
+    format!("Sync notes ({})\n\n{}", subject, body)
+}
+
+fn run_git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()
+        .map_err(|err| format!("Could not run git: {}", err))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+}
+
+/**
+* Runs a git command that creates commits, a tRusty identity is used on machines where git has not been set up
+*/
+fn run_git_with_identity(dir: &Path, args: &[&str]) -> Result<String, String> {
+    let mut all_args = vec![];
+    if run_git(dir, &["config", "user.email"]).is_err() {
+        all_args.extend(["-c", "user.name=tRusty", "-c", "user.email=trusty@localhost"]);
+    }
+    all_args.extend(args);
+    run_git(dir, &all_args)
+}
+
+fn commit(dir: &Path, message: &str) -> Result<(), String> {
+    run_git_with_identity(dir, &["commit", "--quiet", "--no-verify", "-m", message]).map(|_| ())
+}
+
+fn has_staged_changes(dir: &Path) -> Result<bool, String> {
+    run_git(dir, &["add", "--all"])?;
+    Ok(!run_git(dir, &["status", "--porcelain"])?.trim().is_empty())
+}
+
+fn get_note_path(dir: &Path, content_id: &str) -> PathBuf {
+    dir.join(NOTES_DIR).join(format!("{}.{}", content_id, NOTE_EXTENSION))
+}
+
+/**
+* Reads every note file in the working tree. A file that can not be read fails the whole sync, otherwise
+* the note it belongs to would look deleted and be removed from the vault.
+*/
+fn read_note_files(dir: &Path) -> Result<HashMap<String, SyncNote>, String> {
+    let notes_dir = dir.join(NOTES_DIR);
+    let entries = fs::read_dir(&notes_dir).map_err(|err| format!("Could not read {}: {}", notes_dir.display(), err))?;
+    let mut notes = HashMap::new();
+    for entry in entries {
+        let path = entry.map_err(|err| format!("Could not read {}: {}", notes_dir.display(), err))?.path();
+        if !path.extension().map(|ext| ext.eq(NOTE_EXTENSION)).unwrap_or(false) {
+            continue
+        }
+        let content_id = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let text = fs::read_to_string(&path).map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+        let note = parse_note_file(&content_id, &text).ok_or(format!("{} is not a tRusty note, its header does not end with a {} line. \
+        Fix or remove the file and sync again, nothing was changed.", path.display(), HEADER_END))?;
+        notes.insert(content_id, note);
+    }
+
+    Ok(notes)
+}
+
+/**
+* Returns the working tree, it is cloned from the git_remote config value (or created) the first time
+*/
+fn open_work_tree(cpo: &dyn PathOperations, store: &SqliteStore) -> Result<(PathBuf, Option<String>), String> {
+    let default_dir = cpo.get_trusty_dir().join("git");
+    let dir = PathBuf::from(get_config_value(store, "git_sync_dir", &default_dir.to_string_lossy()));
+    let remote = get_config_value(store, "git_remote", "");
+    let remote = if remote.trim().is_empty() { None } else { Some(remote.trim().to_string()) };
+
+    if !dir.join(".git").exists() {
+        fs::create_dir_all(&dir).map_err(|err| format!("Could not create {}: {}", dir.display(), err))?;
+        match &remote {
+            Some(remote) => run_git(&dir, &["clone", "--quiet", remote, "."])?,
+            None => run_git(&dir, &["init", "--quiet"])?
+        };
+    }
+    fs::create_dir_all(dir.join(NOTES_DIR)).map_err(|err| err.to_string())?;
+
+    Ok((dir, remote))
+}
+
+/**
+* Writes the vault to the working tree, only notes deleted from this vault are removed from it
+*/
+fn export_notes(store: &SqliteStore, dir: &Path) -> Result<Vec<SyncChange>, String> {
+    let (notes, tombstones) = store.with_transaction(|tx| Ok((read_notes(tx, Side::Local)?, read_tombstones(tx, Side::Local)?)))
+        .map_err(|err| err.to_string())?;
+    let files = read_note_files(dir)?;
+    let mut report = SyncReport { local: vec![], remote: vec![], last_sync: None, skipped_protected: 0 };
+
+    for note in notes.values() {
+        let old = files.get(&note.content_id);
+        if old.map(|old| old.same_state(note) && old.updated.eq(&note.updated)).unwrap_or(false) {
+            continue
+        }
+        fs::write(get_note_path(dir, &note.content_id), format_note_file(note)).map_err(|err| err.to_string())?;
+        report.record(Side::Remote, note, note.change_from(old));
+    }
+    for (content_id, file) in &files {
+        if !notes.contains_key(content_id) && tombstones.contains_key(content_id) {
+            fs::remove_file(get_note_path(dir, content_id)).map_err(|err| err.to_string())?;
+            report.record(Side::Remote, file, SyncChangeKind::Deleted);
+        }
+    }
+
+    Ok(report.remote)
+}
+
+/**
+* Pulls remote changes, when both sides edited a note the remote version is kept and this version
+* becomes a new conflict note
+*/
+fn pull(store: &SqliteStore, dir: &Path, report: &mut SyncReport) -> Result<(), String> {
+    let branch = run_git(dir, &["symbolic-ref", "--short", "HEAD"])?.trim().to_string();
+    if run_git(dir, &["ls-remote", "--heads", "origin", &branch])?.trim().is_empty() {
+        // nothing has been pushed yet
+        return Ok(())
+    }
+    let pull_error = match run_git_with_identity(dir, &["pull", "--quiet", "--no-rebase", "--no-edit", "--allow-unrelated-histories", "origin", &branch]) {
+        Ok(_) => return Ok(()),
+        Err(err) => err
+    };
+
+    let conflicts = run_git(dir, &["diff", "--name-only", "--diff-filter=U"])?;
+    if conflicts.trim().is_empty() {
+        return Err(pull_error)
+    }
+    let now = get_current_timestamp(store);
+    for path in conflicts.lines() {
+        let content_id = Path::new(path).file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let ours = run_git(dir, &["show", &format!(":2:{}", path)]).ok().and_then(|text| parse_note_file(&content_id, &text));
+        let theirs = run_git(dir, &["show", &format!(":3:{}", path)]).ok().and_then(|text| parse_note_file(&content_id, &text));
+        match (ours, theirs) {
+            (Some(ours), Some(theirs)) => {
+                fs::write(dir.join(path), format_note_file(&theirs)).map_err(|err| err.to_string())?;
+                let conflict_note = SyncNote {
+                    content_id: Uuid::new_v4().to_string(),
+                    title: if ours.protected { ours.title.to_string() } else { format!("{} (conflict copy {})", ours.title, ours.updated) },
+                    updated: now.to_string(),
+                    ..ours
+                };
+                fs::write(get_note_path(dir, &conflict_note.content_id), format_note_file(&conflict_note)).map_err(|err| err.to_string())?;
+                report.record(Side::Remote, &conflict_note, SyncChangeKind::ConflictCopy);
+            }
+            // a note that was edited on one side and deleted on the other is kept
+            (Some(note), None) | (None, Some(note)) => {
+                fs::write(dir.join(path), format_note_file(&note)).map_err(|err| err.to_string())?;
+            }
+            (None, None) => {
+                return Err(format!("Could not resolve the conflict in {}", path))
+            }
+        }
+    }
+
+    has_staged_changes(dir)?;
+    let conflict_count = report.remote.iter().filter(|change| change.kind == SyncChangeKind::ConflictCopy).count();
+    commit(dir, &format!("Merge remote notes with {} conflict copies", conflict_count))
+}
+
+/**
+* Applies the working tree to the vault in one transaction
+*/
+fn import_notes(store: &SqliteStore, dir: &Path, report: &mut SyncReport) -> Result<(), String> {
+    let files = read_note_files(dir)?;
+    let now = get_current_timestamp(store);
+    let conflict_ids = report.remote.iter()
+        .filter(|change| change.kind == SyncChangeKind::ConflictCopy)
+        .map(|change| change.content_id.to_string())
+        .collect::<Vec<String>>();
+    let changes = store.with_transaction(|tx| {
+        let mut changes = SyncReport { local: vec![], remote: vec![], last_sync: None, skipped_protected: 0 };
+        let notes = read_notes(tx, Side::Local)?;
+        for file in files.values() {
+            let old = notes.get(&file.content_id);
+            if old.map(|old| old.same_state(file)).unwrap_or(false) {
+                continue
+            }
+            write_note(tx, Side::Local, file)?;
+            let kind = if conflict_ids.contains(&file.content_id) { SyncChangeKind::ConflictCopy } else { file.change_from(old) };
+            changes.record(Side::Local, file, kind);
+        }
+        // every file was read above, so a missing file was deleted from the repository
+        for note in notes.values() {
+            if !files.contains_key(&note.content_id) {
+                delete_note(tx, Side::Local, &note.content_id, &now)?;
+                changes.record(Side::Local, note, SyncChangeKind::Deleted);
+            }
+        }
+        Ok(changes.local)
+    }).map_err(|err| format!("Could not apply the notes repository, nothing was changed: {}", err))?;
+    report.local = changes;
+
+    Ok(())
+}
+
+/**
+* Mirrors the vault to a git working tree with one file per note, commits local changes, pulls and applies remote
+* changes to the vault and pushes the result to the git_remote config value (when one is set)
+*/
+pub fn git_sync(cpo: &dyn PathOperations, store: &SqliteStore) -> Result<SyncReport, String> {
+    let (dir, remote) = open_work_tree(cpo, store)?;
+    let mut report = SyncReport { local: vec![], remote: export_notes(store, &dir)?, last_sync: None, skipped_protected: 0 };
+    if has_staged_changes(&dir)? {
+        commit(&dir, &format_commit_message(&report.remote))?;
+    }
+
+    if remote.is_some() {
+        pull(store, &dir, &mut report)?;
+    }
+    import_notes(store, &dir, &mut report)?;
+
+    if remote.is_some() && !run_git(&dir, &["log", "--oneline", "-1"]).unwrap_or_default().is_empty() {
+        run_git(&dir, &["push", "--quiet", "origin", "HEAD"])?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::git_sync::{format_commit_message, format_note_file, parse_note_file};
+    use crate::sync::{SyncChange, SyncChangeKind, SyncNote};
+
+    #[test]
+    fn test_note_file_round_trip() {
+        let note = SyncNote {
+            content_id: "abc".to_string(),
+            title: "foo".to_string(),
+            body: "---\ntitle: not a header\n".to_string(),
+            protected: false,
+            trashed: true,
+            created: "2024-07-01 22:56:27".to_string(),
+            updated: "2024-07-02 22:56:27".to_string(),
+            due: None,
+            content_type: None,
+            data_key: None,
+        };
+        let text = format_note_file(&note);
+        assert!(text.starts_with("title: foo\nprotected: false\ntrashed: true\n"));
+        assert_eq!(parse_note_file("abc", &text).unwrap(), note);
+        let due_note = SyncNote { due: Some("2024-07-03 09:00:00".to_string()), ..note };
+        assert_eq!(parse_note_file("abc", &format_note_file(&due_note)).unwrap(), due_note);
+        let json_note = SyncNote { content_type: Some("json".to_string()), ..due_note };
+        assert_eq!(parse_note_file("abc", &format_note_file(&json_note)).unwrap(), json_note);
+        let protected_note = SyncNote { protected: true, data_key: Some("wrapped key".to_string()), ..json_note };
+        assert_eq!(parse_note_file("abc", &format_note_file(&protected_note)).unwrap(), protected_note);
+        assert!(parse_note_file("abc", "title: no header end").is_none());
+    }
+
+    #[test]
+    fn test_format_commit_message() {
+        let change = |title: &str, kind: SyncChangeKind| SyncChange { content_id: "".to_string(), title: title.to_string(), kind };
+        assert_eq!(format_commit_message(&[change("foo", SyncChangeKind::Added)]), "Add note: foo");
+        let message = format_commit_message(&[
+            change("foo", SyncChangeKind::Added),
+            change("bar", SyncChangeKind::Updated),
+            change("baz", SyncChangeKind::Added),
+        ]);
+        assert_eq!(message, "Sync notes (Add 2, Update 1)\n\nAdd: foo\nUpdate: bar\nAdd: baz");
+    }
+}
@@ -0,0 +1,146 @@
+use regex::Regex;
+
+use crate::note::Note;
+use crate::security::unseal_note;
+
+pub struct GrepLine {
+    pub number: usize,
+    pub text: String,
+    // byte ranges of the matches, context lines have none
+    pub matches: Vec<(usize, usize)>,
+    pub is_match: bool
+}
+
+pub struct GrepResult {
+    pub note_id: usize,
+    pub title: String,
+    pub protected: bool,
+    pub lines: Vec<GrepLine>
+}
+
+/**
+* Finds the lines of a text that match, with the lines around them. Line numbers start at 1.
+* @context - how many lines before and after a match to keep
+*/
+pub fn grep_text(regex: &Regex, text: &str, context: usize) -> Vec<GrepLine> {
+    let lines: Vec<&str> = text.lines().collect();
+    let matched: Vec<bool> = lines.iter().map(|line| regex.is_match(line)).collect();
+
+    let mut results = vec![];
+    for (index, line) in lines.iter().enumerate() {
+        let start = index.saturating_sub(context);
+        let stop = (index + context).min(lines.len().saturating_sub(1));
+        if !matched[start..=stop].contains(&true) {
+            continue
+        }
+
+        results.push(GrepLine {
+            number: index + 1,
+            text: line.to_string(),
+            matches: if matched[index] { regex.find_iter(line).map(|found| (found.start(), found.end())).filter(|(start, end)| start < end).collect() } else { vec![] },
+            is_match: matched[index],
+        });
+    }
+
+    results
+}
+
+/**
+* Searches the bodies of notes, protected notes are decrypted with the boss key or left out without one
+*/
+pub fn grep_notes(notes: Vec<Note>, regex: &Regex, context: usize, boss_key: Option<&str>) -> Vec<GrepResult> {
+    let mut results = vec![];
+    for note in notes {
+        let (title, body) = match (note.protected, boss_key) {
+            (false, _) => (note.title, note.body),
+            (true, Some(key)) => match unseal_note(key, note.data_key.as_deref(), &note.title, &note.body) {
+                Some(decrypted) => decrypted,
+                None => continue
+            },
+            (true, None) => continue
+        };
+
+        let lines = grep_text(regex, &body, context);
+        if !lines.is_empty() {
+            results.push(GrepResult { note_id: note.id.0, title, protected: note.protected, lines });
+        }
+    }
+    results.sort_by_key(|result| result.note_id);
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use regex::Regex;
+
+    use crate::grep::{grep_notes, grep_text};
+    use crate::note::{ContentId, Note, NoteId};
+    use crate::security::encrypt_text;
+
+    fn make_note(note_id: usize, title: &str, body: &str, protected: bool) -> Note {
+        Note {
+            id: NoteId(note_id),
+            content_id: ContentId::from(format!("content-{}", note_id)),
+            title: title.to_string(),
+            body: body.to_string(),
+            protected,
+            trashed: false,
+            created: Utc::now(),
+            updated: Utc::now(),
+            due: None,
+            content_type: None,
+            data_key: None,
+        }
+    }
+
+    #[test]
+    fn test_grep_text() {
+        let text = "one\ntwo deploy\nthree\nfour\nfive\nsix DEPLOY deploy\nseven";
+        let regex = Regex::new("(?i)deploy").unwrap();
+
+        let lines = grep_text(&regex, text, 0);
+        assert_eq!(lines.iter().map(|line| line.number).collect::<Vec<usize>>(), vec![2, 6]);
+        assert_eq!(lines[0].matches, vec![(4, 10)]);
+        assert_eq!(lines[1].matches, vec![(4, 10), (11, 17)]);
+
+        let lines = grep_text(&regex, text, 1);
+        assert_eq!(lines.iter().map(|line| line.number).collect::<Vec<usize>>(), vec![1, 2, 3, 5, 6, 7]);
+        assert!(!lines[0].is_match && lines[0].matches.is_empty());
+
+        // overlapping context is only printed once
+        assert_eq!(grep_text(&regex, text, 3).len(), 7);
+        assert!(grep_text(&Regex::new("nothing").unwrap(), text, 2).is_empty());
+        assert!(grep_text(&regex, "", 2).is_empty());
+    }
+
+    #[test]
+    fn test_grep_text_empty_matches() {
+        let lines = grep_text(&Regex::new("^").unwrap(), "a\nb", 0);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.is_match && line.matches.is_empty()));
+    }
+
+    #[test]
+    fn test_grep_notes() {
+        let key = "boss key";
+        let notes = || vec![
+            make_note(7, "Deploy", "kubectl apply", false),
+            make_note(2, &encrypt_text(key, "Secret"), &encrypt_text(key, "kubectl token"), true),
+            make_note(3, "Groceries", "eggs", false),
+        ];
+        let regex = Regex::new("kubectl").unwrap();
+
+        let results = grep_notes(notes(), &regex, 0, None);
+        assert_eq!(results.iter().map(|result| result.note_id).collect::<Vec<usize>>(), vec![7]);
+
+        // protected notes are decrypted with the boss key and sorted with the rest
+        let results = grep_notes(notes(), &regex, 0, Some(key));
+        assert_eq!(results.iter().map(|result| (result.note_id, result.title.as_str())).collect::<Vec<(usize, &str)>>(), vec![(2, "Secret"), (7, "Deploy")]);
+        assert!(results[0].protected);
+        assert_eq!(results[0].lines[0].text, "kubectl token");
+
+        assert!(grep_notes(notes(), &regex, 0, Some("wrong key")).iter().all(|result| !result.protected));
+    }
+}
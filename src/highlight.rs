@@ -0,0 +1,161 @@
+use regex::Regex;
+use termcolor::{Color, ColorSpec};
+
+use crate::content_type::ContentType;
+use crate::markdown::{render_markdown, Span};
+
+fn fg(color: Color) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_fg(Some(color));
+    spec
+}
+
+fn dimmed() -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_dimmed(true);
+    spec
+}
+
+fn bold(color: Option<Color>) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_fg(color).set_bold(true);
+    spec
+}
+
+/**
+* Colors the parts of a line matched by the named groups of the regex, the rest of the line is plain
+* @styles - the style of each named group
+*/
+fn highlight_tokens(line: &str, re: &Regex, styles: &[(&str, ColorSpec)]) -> Vec<Span> {
+    let mut spans = vec![];
+    let mut last = 0;
+    for caps in re.captures_iter(line) {
+        let whole = caps.get(0).unwrap();
+        if whole.as_str().is_empty() {
+            continue
+        }
+        if whole.start() > last {
+            spans.push(Span { text: line[last..whole.start()].to_string(), spec: ColorSpec::new() });
+        }
+        let spec = styles.iter().find(|(name, _)| caps.name(name).is_some()).map(|(_, spec)| spec.clone()).unwrap_or_default();
+        spans.push(Span { text: whole.as_str().to_string(), spec });
+        last = whole.end();
+    }
+    if last < line.len() {
+        spans.push(Span { text: line[last..].to_string(), spec: ColorSpec::new() });
+    }
+
+    spans
+}
+
+fn highlight_lines(body: &str, re: &Regex, styles: &[(&str, ColorSpec)]) -> Vec<Vec<Span>> {
+    body.lines().map(|line| highlight_tokens(line, re, styles)).collect()
+}
+
+fn style_line(line: &str, spec: ColorSpec) -> Vec<Span> {
+    vec![Span { text: line.to_string(), spec }]
+}
+
+/**
+* Turns a note into lines of styled text for the terminal, text notes are rendered as markdown
+*/
+pub(crate) fn highlight(content_type: ContentType, body: &str) -> Vec<Vec<Span>> {
+    let string = r#""(?:[^"\\]|\\.)*""#;
+    let number = r"-?\b\d+(?:\.\d+)?(?:[eE][+-]?\d+)?\b";
+    match content_type {
+        ContentType::Text => render_markdown(body),
+        ContentType::Json => {
+            let re = Regex::new(&format!(r"(?P<key>{string}\s*:)|(?P<string>{string})|(?P<number>{number})|(?P<literal>\b(?:true|false|null)\b)")).unwrap();
+            highlight_lines(body, &re, &[("key", fg(Color::Blue)), ("string", fg(Color::Green)), ("number", fg(Color::Cyan)), ("literal", fg(Color::Magenta))])
+        }
+        ContentType::Yaml => {
+            let re = Regex::new(&format!(r"(?P<comment>(?:^|\s)#.*$)|(?P<key>^\s*(?:-\s+)?[\w.\-]+:)|(?P<string>{string}|'[^']*')|(?P<number>{number})|(?P<literal>\b(?:true|false|null|yes|no)\b|^---$)")).unwrap();
+            highlight_lines(body, &re, &[("comment", dimmed()), ("key", fg(Color::Blue)), ("string", fg(Color::Green)), ("number", fg(Color::Cyan)), ("literal", fg(Color::Magenta))])
+        }
+        ContentType::Sh => {
+            let re = Regex::new(&format!(r"(?P<comment>(?:^|\s)#.*$)|(?P<prompt>^\$ )|(?P<string>{string}|'[^']*')|(?P<variable>\$\{{?\w+\}}?)|(?P<keyword>\b(?:if|then|else|elif|fi|for|while|until|do|done|case|esac|function|in|export|local|return|set)\b)")).unwrap();
+            highlight_lines(body, &re, &[("comment", dimmed()), ("prompt", dimmed()), ("string", fg(Color::Green)), ("variable", fg(Color::Cyan)), ("keyword", fg(Color::Magenta))])
+        }
+        ContentType::Rust => {
+            let keywords = "as|async|await|const|crate|dyn|else|enum|fn|for|if|impl|in|let|loop|match|mod|move|mut|pub|ref|return|self|Self|static|struct|super|trait|type|unsafe|use|where|while";
+            let re = Regex::new(&format!(r"(?P<comment>//.*$)|(?P<string>{string})|(?P<macro>\b\w+!)|(?P<keyword>\b(?:{keywords})\b)|(?P<type>\b[A-Z]\w*\b)|(?P<number>{number})")).unwrap();
+            highlight_lines(body, &re, &[("comment", dimmed()), ("string", fg(Color::Green)), ("macro", fg(Color::Blue)), ("keyword", fg(Color::Magenta)), ("type", fg(Color::Yellow)), ("number", fg(Color::Cyan))])
+        }
+        ContentType::Diff => body.lines().map(|line| {
+            let spec = if line.starts_with("+++") || line.starts_with("---") || line.starts_with("diff ") || line.starts_with("index ") {
+                bold(None)
+            } else if line.starts_with('+') {
+                fg(Color::Green)
+            } else if line.starts_with('-') {
+                fg(Color::Red)
+            } else if line.starts_with("@@") {
+                fg(Color::Cyan)
+            } else {
+                ColorSpec::new()
+            };
+            style_line(line, spec)
+        }).collect(),
+        ContentType::Log => {
+            let re = Regex::new(r"(?P<timestamp>^\[?\d{4}-\d{2}-\d{2}[ T][\d:.,]+\w*\]?)|(?P<error>\b(?:ERROR|FATAL|CRITICAL|panicked|Traceback|Exception)\b)|(?P<warn>\b(?:WARN|WARNING)\b)|(?P<info>\bINFO\b)|(?P<debug>\b(?:DEBUG|TRACE)\b)|(?P<frame>^\s+at\s.*$)").unwrap();
+            highlight_lines(body, &re, &[("timestamp", dimmed()), ("error", bold(Some(Color::Red))), ("warn", fg(Color::Yellow)), ("info", fg(Color::Green)), ("debug", dimmed()), ("frame", dimmed())])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use termcolor::Color;
+
+    use crate::content_type::ContentType;
+    use crate::highlight::highlight;
+    use crate::markdown::Span;
+
+    fn find_span<'a>(lines: &'a [Vec<Span>], text: &str) -> &'a Span {
+        lines.iter().flatten().find(|span| span.text == text).unwrap()
+    }
+
+    #[test]
+    fn test_highlight_keeps_text() {
+        let bodies = [
+            (ContentType::Json, "{\n  \"foo\": [1, true, \"bar\"]\n}"),
+            (ContentType::Rust, "fn main() {\n    println!(\"hi\"); // greet\n}"),
+            (ContentType::Log, "2024-07-01 22:56:27 ERROR failed\n    at foo (bar.js:1:2)"),
+        ];
+        for (content_type, body) in bodies {
+            let text = highlight(content_type, body).iter()
+                .map(|line| line.iter().map(|span| span.text.to_string()).collect::<String>())
+                .collect::<Vec<String>>()
+                .join("\n");
+            assert_eq!(text, body);
+        }
+    }
+
+    #[test]
+    fn test_highlight_tokens() {
+        let json = highlight(ContentType::Json, "{\"foo\": [1, true, \"bar\"]}");
+        assert_eq!(find_span(&json, "\"foo\":").spec.fg(), Some(&Color::Blue));
+        assert_eq!(find_span(&json, "\"bar\"").spec.fg(), Some(&Color::Green));
+        assert_eq!(find_span(&json, "1").spec.fg(), Some(&Color::Cyan));
+        assert_eq!(find_span(&json, "true").spec.fg(), Some(&Color::Magenta));
+
+        let yaml = highlight(ContentType::Yaml, "name: trusty # app\nport: 7878");
+        assert_eq!(find_span(&yaml, "name:").spec.fg(), Some(&Color::Blue));
+        assert!(find_span(&yaml, " # app").spec.dimmed());
+
+        let sh = highlight(ContentType::Sh, "$ echo \"$HOME\" ${USER}");
+        assert!(find_span(&sh, "$ ").spec.dimmed());
+        assert_eq!(find_span(&sh, "${USER}").spec.fg(), Some(&Color::Cyan));
+
+        let rust = highlight(ContentType::Rust, "let x: Option<u8> = None;");
+        assert_eq!(find_span(&rust, "let").spec.fg(), Some(&Color::Magenta));
+        assert_eq!(find_span(&rust, "Option").spec.fg(), Some(&Color::Yellow));
+
+        let diff = highlight(ContentType::Diff, "--- a/foo\n+++ b/foo\n-old\n+new");
+        assert!(diff[0][0].spec.bold());
+        assert_eq!(diff[2][0].spec.fg(), Some(&Color::Red));
+        assert_eq!(diff[3][0].spec.fg(), Some(&Color::Green));
+
+        let log = highlight(ContentType::Log, "2024-07-01 22:56:27 WARN disk");
+        assert_eq!(find_span(&log, "WARN").spec.fg(), Some(&Color::Yellow));
+    }
+}
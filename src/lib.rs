@@ -4,4 +4,26 @@ pub mod render;
 pub mod security;
 pub mod sql;
 pub mod utils;
-pub mod cli;
\ No newline at end of file
+pub mod cli;
+pub mod checklist;
+pub mod duplicates;
+pub mod bulk;
+pub mod backup;
+pub mod archive;
+pub mod sync;
+pub mod git_sync;
+pub mod api;
+pub mod server;
+pub mod rpc;
+pub mod markdown;
+pub mod content_type;
+pub mod highlight;
+pub mod dates;
+pub mod completions;
+pub mod picker;
+pub mod query;
+pub mod grep;
+pub mod store;
+pub mod note;
+pub mod password;
+pub mod db_encryption;
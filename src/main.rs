@@ -5,41 +5,115 @@ mod render;
 mod utils;
 mod errors;
 mod security;
+mod checklist;
+mod duplicates;
+mod bulk;
+mod backup;
+mod archive;
+mod sync;
+mod git_sync;
+mod api;
+mod server;
+mod rpc;
+mod markdown;
+mod content_type;
+mod highlight;
+mod dates;
+mod completions;
+mod picker;
+mod query;
+mod grep;
+mod store;
+mod note;
+mod password;
+mod db_encryption;
+
+use std::io;
+use std::process::exit;
 
 use clap::Parser;
 use security::set_password;
-use crate::cli::{Cli, edit_note, edit_title, insert_note_from_std_in, open_note};
-use crate::render::{print_app_summary, print_dump, print_simple_note, TrustyPrinter, Printer};
-use crate::setup::{check_for_config, create_trusty_dir, TrustyPathOperations, PathOperations, get_home_dir, init_trusty_db};
-use crate::sql::{add_note, delete_note, dump_notes, empty_trash, get_note_by_id, get_note_from_menu_line, get_summary, list_note_titles, restore_note, trash_note};
+use crate::checklist::get_open_checklist_items;
+use crate::backup::list_backups;
+use crate::bulk::BulkAction;
+use crate::duplicates::find_duplicates;
+use crate::cli::{Cli, backup_notes, change_password_with_prompt, decrypt_database_file, edit_note, encrypt_database_with_prompt, export_archive_to_file, import_archive_from_file, edit_title, filter_since, get_list_options, grep_and_print_notes, bulk_update_notes, insert_note_from_std_in, open_note, pick_and_use_note, pin_note, print_note, restore_notes_from_backup, rotate_key, unlock_database, select_note_ids, serve_api, set_content_type, validate_note, toggle_note_checklist_item};
+use crate::errors::Errors;
+use crate::note::{MAX_TITLE_LENGTH, NoteId};
+use crate::git_sync::git_sync;
+use crate::rpc::run_rpc;
+use crate::sync::sync_databases;
+use crate::render::{print_agenda, print_app_summary, print_backups, print_bulk_report, print_dump, print_duplicate_report, print_ics, print_note_completions, print_note_history, print_notifications, print_open_checklist_items, print_simple_note, print_sync_report, TrustyPrinter, Printer};
+use crate::setup::{check_for_config, create_trusty_dir, TrustyPathOperations, PathOperations, get_home_dir, init_trusty_db, migrate_trusty_db};
+use crate::sql::{add_note, delete_note, dump_matching_notes, empty_trash, get_due_notes, get_notes_from_menu_lines, get_config_value, get_current_timestamp, get_note_history, get_summary, list_note_completions, list_note_titles, list_unprotected_notes, merge_notes, set_config_value, set_note_due};
 use crate::utils::slice_text;
-use crate::security::{protect_note, recovery_reset_password, unprotect_note};
+use crate::security::recovery_reset_password;
+use crate::completions::write_completions;
+use crate::store::SqliteStore;
+use crate::db_encryption::{db_key_from_env, is_encrypted_db, set_db_key};
 
 fn main() {
+    // read the args
+    let args = Cli::parse();
+
+    if args.completions.is_some() {
+        write_completions(args.completions.unwrap(), &mut io::stdout()).unwrap();
+        return
+    }
+
     // check for a trusty home directory, if it doesn't exist show setup prompt
     let cpo = TrustyPathOperations {};
     let cr_print = TrustyPrinter {};
     let home_dir = get_home_dir();
-    let conf_loc = match check_for_config(&home_dir) {
+    let config_path = check_for_config(&home_dir);
+
+    // completion scripts run this on every tab, it must never start the setup prompt
+    if args.complete_notes.is_some() {
+        let db_path = cpo.get_trusty_db_path();
+        // an encrypted database can only be completed with TRUSTY_DB_KEY set
+        if is_encrypted_db(&db_path) {
+            match db_key_from_env(&db_path) {
+                Some(key) => set_db_key(Some(&key)),
+                None => return
+            }
+        }
+        if config_path.is_some() {
+            migrate_trusty_db(&cpo);
+            let trashed = args.complete_notes.as_deref() == Some("trashed");
+            print_note_completions(&cr_print, list_note_completions(&SqliteStore::open(&cpo), trashed));
+        }
+        return
+    }
+
+    let is_new_vault = config_path.is_none();
+    let conf_loc = match config_path {
         None => {
             create_trusty_dir(&cpo);
             init_trusty_db(&cpo);
-            set_password(false, None);
             cpo.get_trusty_db_path()
         }
         Some(conf_path) => {
+            unlock_database(&cpo);
+            migrate_trusty_db(&cpo);
             conf_path
         }
     };
-
-    // read the args
-    let args = Cli::parse();
+    // every command below uses this one connection
+    let store = SqliteStore::open(&cpo);
+    if is_new_vault {
+        set_password(&store, false, None);
+    }
 
     let title = args.title.as_deref();
     let note = args.note.as_deref();
     let quick_note = args.quick.as_deref();
     let input = args.input;
     let find = args.find;
+    let rich = args.rich;
+    let pretty = args.pretty;
+    let validate = args.validate;
+    let set_type = args.set_type;
+    let content_type = args.content_type;
     let find_from = args.find_from;
     let edit = args.edit;
     let open = args.open;
@@ -53,133 +127,355 @@ fn main() {
     let summary = args.summary;
     let encrypted = args.encrypt;
     let recover = args.recover;
+    let rotate_key_flag = args.rotate_key;
+    let change_password_flag = args.change_password;
     let unprotect = args.unprotect;
     let protect = args.protect;
     let dump_protected = args.dump_protected;
+    let due = args.due;
+    let date = args.date.as_deref();
+    let agenda = args.agenda;
+    let notify = args.notify;
+    let ics = args.ics;
+    let todos = args.todos;
+    let toggle = args.toggle;
+    let item = args.item;
+    let duplicates = args.duplicates;
+    let merge = args.merge;
+    let into = args.into;
+    let history = args.history;
+    let config = args.config;
+    let backup = args.backup;
+    let backups = args.backups;
+    let restore_backup = args.restore_backup;
+    let export_archive = args.export_archive;
+    let import_archive = args.import_archive;
+    let import_mode = args.import_mode.as_deref();
+    let sync = args.sync;
+    let git_sync_flag = args.git_sync;
+    let serve = args.serve;
+    let socket = args.socket;
+    let rpc = args.rpc;
+    let pin = args.pin;
+    let unpin = args.unpin;
+    let list_options = get_list_options(&store, args.compact.is_some(), args.with_trash.is_some(), args.since.as_deref(), args.query.as_deref());
+
+    let search = args.search.as_deref();
 
     let should_encrypt_note = encrypted.unwrap_or(false);
 
+    if args.pick.is_some() {
+        pick_and_use_note(&store, args.pick.as_deref().unwrap());
+        return
+    }
+
+    if args.grep.is_some() {
+        grep_and_print_notes(&store, args.grep.as_deref().unwrap(), args.context.unwrap_or(0), args.grep_protected.is_some(), &list_options.query);
+        return
+    }
+
     if find_from.is_some() {
-        let note = get_note_from_menu_line(&cpo);
-        print_simple_note(&cr_print, note);
+        for note in get_notes_from_menu_lines(&store) {
+            print_simple_note(&cr_print, note);
+        }
         return
     }
 
     // reset password flow
     if recover.is_some() {
         let recovery_code = recover.unwrap();
-        recovery_reset_password(&recovery_code);
+        recovery_reset_password(&store, &recovery_code);
+        return
+    }
+
+    if args.encrypt_db.is_some() {
+        encrypt_database_with_prompt(&cpo, &store);
         return
     }
 
-    if protect.is_some() {
-        let note_id = protect.unwrap_or(0);
-        protect_note(note_id);
+    if args.decrypt_db.is_some() {
+        decrypt_database_file(&cpo);
         return
     }
 
-    if unprotect.is_some() {
-        let note_id = unprotect.unwrap_or(0);
-        unprotect_note(note_id);
+    if change_password_flag.is_some() {
+        change_password_with_prompt(&store, args.new_recovery_code.is_some());
+        return
+    }
+
+    if rotate_key_flag.is_some() {
+        rotate_key(&store);
+        return
+    }
+
+    if let Some(spec) = protect {
+        let note_ids = select_note_ids(&store, &spec, search, &list_options.query);
+        print_bulk_report(&cr_print, bulk_update_notes(&store, BulkAction::Protect, &note_ids));
+        return
+    }
+
+    if let Some(spec) = unprotect {
+        let note_ids = select_note_ids(&store, &spec, search, &list_options.query);
+        print_bulk_report(&cr_print, bulk_update_notes(&store, BulkAction::Unprotect, &note_ids));
         return
     }
 
     if summary.is_some() {
-        let summary = get_summary(&cpo);
-        print_app_summary(&cr_print, summary);
+        let summary = get_summary(&store);
+        print_app_summary(&cr_print, summary, &list_options.time);
         return
     }
 
     if input.is_some() {
         let title_val = title.unwrap_or("Untitled");
-        insert_note_from_std_in(title_val, should_encrypt_note);
+        insert_note_from_std_in(&store, title_val, should_encrypt_note);
         return
     }
 
     // if there is a title and note param insert a proper note
     // @todo this could replace the quick note command if we unwrap+or for the title
-    if title.is_some() && note.is_some() {
-        add_note(&cpo, title.unwrap(), note.unwrap(), should_encrypt_note);
+    if let (Some(title), Some(note)) = (title, note) {
+        add_note(&store, title, note, should_encrypt_note);
+        return
+    }
+
+    if let Some(id) = pin {
+        pin_note(&store, id, true);
+        return
+    }
+
+    if let Some(id) = unpin {
+        pin_note(&store, id, false);
+        return
+    }
+
+    if let Some(id) = validate {
+        validate_note(&store, id);
+        return
+    }
+
+    if let (Some(id), Some(content_type)) = (set_type, content_type) {
+        set_content_type(&store, id, &content_type);
         return
     }
 
-    if find.is_some() {
-        let note = get_note_by_id(&cpo, find.unwrap());
-        print_simple_note(&cr_print, note);
+    if let Some(id) = find {
+        let markdown = rich.is_some() || get_config_value(&store, "rich_notes", "false").eq("true");
+        print_note(&store, id, markdown, pretty.is_some());
         return
     }
 
     // add an untitled quick note, this needs to stay near the bottom
     if quick_note.is_some() && title.is_none() && note.is_none() {
         let note = quick_note.unwrap();
-        let title = slice_text(0, 128, note);
-        add_note(&cpo, title.as_str(), note, should_encrypt_note);
+        let title = slice_text(0, MAX_TITLE_LENGTH, note);
+        add_note(&store, title.as_str(), note, should_encrypt_note);
         return
     }
 
     if edit.is_some() {
         if all.is_some() {
-            edit_title(None);
+            edit_title(&store, None);
         }
-        edit_note();
+        edit_note(&store);
         return
     }
 
     if open.is_some() {
         let note_id = open.unwrap();
         if all.is_some() {
-            edit_title(Some(note_id));
+            edit_title(&store, Some(note_id));
         }
-        open_note(&cpo, note_id, should_encrypt_note);
+        open_note(&store, note_id, should_encrypt_note);
         return
     }
 
-    if delete.is_some() {
-        let note_id = delete.unwrap();
-        delete_note(&cpo, note_id, false);
+    if let Some(spec) = delete {
+        let note_ids = select_note_ids(&store, &spec, search, &list_options.query);
+        print_bulk_report(&cr_print, bulk_update_notes(&store, BulkAction::Delete, &note_ids));
         return
     }
 
     if force_delete.is_some() {
         let note_id = force_delete.unwrap();
-        delete_note(&cpo, note_id, true);
+        delete_note(&store, NoteId(note_id), true);
         return
     }
 
     if clean.is_some() {
-        empty_trash(&TrustyPathOperations {});
-        list_note_titles(&cpo, &cr_print);
+        empty_trash(&store);
+        list_note_titles(&store, &cr_print, &list_options);
+        return
+    }
+
+    if let Some(spec) = trash {
+        let note_ids = select_note_ids(&store, &spec, search, &list_options.query);
+        print_bulk_report(&cr_print, bulk_update_notes(&store, BulkAction::Trash, &note_ids));
+        list_note_titles(&store, &cr_print, &list_options);
+        return
+    }
+
+    if let Some(spec) = restore {
+        let note_ids = select_note_ids(&store, &spec, search, &list_options.query);
+        print_bulk_report(&cr_print, bulk_update_notes(&store, BulkAction::Restore, &note_ids));
+        return
+    }
+
+    if let Some(note_id) = due {
+        if set_note_due(&store, NoteId(note_id), date) {
+            match date {
+                Some(date) => cr_print.println(format!("Note: {} is due {}", note_id, date)),
+                None => cr_print.println(format!("Note: {} due date cleared", note_id))
+            }
+        } else {
+            cr_print.print_error(format!("Could not set a due date for note: {}, check the note ID and date format (YYYY-MM-DD HH:MM).", note_id));
+            exit(Errors::DueDateErr as i32);
+        }
+        return
+    }
+
+    if agenda.is_some() {
+        let notes = get_due_notes(&store, None);
+        print_agenda(&cr_print, notes);
+        return
+    }
+
+    if notify.is_some() {
+        let notes = get_due_notes(&store, Some(24));
+        print_notifications(&cr_print, notes);
+        return
+    }
+
+    if ics.is_some() {
+        let notes = get_due_notes(&store, None);
+        print_ics(&cr_print, notes, &get_current_timestamp(&store));
+        return
+    }
+
+    if todos.is_some() {
+        let notes = list_unprotected_notes(&store);
+        print_open_checklist_items(&cr_print, get_open_checklist_items(notes));
+        return
+    }
+
+    if let (Some(note_id), Some(index)) = (toggle, item) {
+        toggle_note_checklist_item(&store, note_id, index);
+        cr_print.println(format!("Note: {} checklist item {} toggled", note_id, index));
+        return
+    }
+
+    if duplicates.is_some() {
+        let notes = list_unprotected_notes(&store);
+        print_duplicate_report(&cr_print, find_duplicates(&notes));
+        return
+    }
+
+    if let (Some(source_id), Some(target_id)) = (merge, into) {
+        if merge_notes(&store, NoteId(source_id), NoteId(target_id)) {
+            cr_print.println(format!("Note: {} merged into note: {}", source_id, target_id));
+        } else {
+            cr_print.print_error(format!("Could not merge note: {} into note: {}, both notes must exist and be unprotected.", source_id, target_id));
+            exit(Errors::MergeNoteErr as i32);
+        }
+        return
+    }
+
+    if let Some(note_id) = history {
+        print_note_history(&cr_print, get_note_history(&store, note_id));
         return
     }
 
-    if trash.is_some() {
-        let note_id = trash.unwrap();
-        trash_note(&cpo, note_id);
-        list_note_titles(&cpo, &cr_print);
+    if backup.is_some() {
+        backup_notes(&cpo, &store);
         return
     }
 
-    if restore.is_some() {
-        let note_id = restore.unwrap();
-        restore_note(&cpo, note_id);
-        cr_print.println(format!("Note: {} restored", note_id));
+    if backups.is_some() {
+        print_backups(&cr_print, &list_backups(&cpo));
+        return
+    }
+
+    if let Some(backup_name) = restore_backup {
+        restore_notes_from_backup(&cpo, &backup_name);
+        return
+    }
+
+    if let Some(path) = export_archive {
+        export_archive_to_file(&store, &path, &list_options.query);
+        return
+    }
+
+    if let Some(path) = import_archive {
+        import_archive_from_file(&cpo, &store, &path, import_mode);
+        return
+    }
+
+    if let Some(other_db) = sync {
+        match sync_databases(&cpo, std::path::Path::new(&other_db)) {
+            Ok(report) => {
+                print_sync_report(&cr_print, &report, "Other database");
+            }
+            Err(err) => {
+                cr_print.print_error(err);
+                exit(Errors::SyncErr as i32)
+            }
+        }
+        return
+    }
+
+    if git_sync_flag.is_some() {
+        match git_sync(&cpo, &store) {
+            Ok(report) => {
+                print_sync_report(&cr_print, &report, "Git repository");
+            }
+            Err(err) => {
+                cr_print.print_error(err);
+                exit(Errors::GitSyncErr as i32)
+            }
+        }
+        return
+    }
+
+    if serve.is_some() {
+        serve_api(&store, serve.as_deref(), socket.as_deref());
+        return
+    }
+
+    if rpc.is_some() {
+        if let Err(err) = run_rpc(&store, std::io::stdin().lock(), std::io::stdout().lock()) {
+            cr_print.print_error(format!("JSON-RPC stopped: {}", err));
+            exit(Errors::RpcErr as i32)
+        }
+        return
+    }
+
+    if let Some(config_arg) = config {
+        match config_arg.split_once('=') {
+            Some((key, value)) => {
+                set_config_value(&store, key.trim(), value.trim());
+                cr_print.println(format!("{} = {}", key.trim(), value.trim()));
+            }
+            None => {
+                cr_print.println(get_config_value(&store, config_arg.trim(), ""));
+            }
+        }
         return
     }
 
     if dump.is_some() {
-        let notes = dump_notes(&cpo,false);
-        print_dump(&cr_print, notes);
+        let notes = filter_since(dump_matching_notes(&store, false, &list_options.query), &list_options.since);
+        print_dump(&cr_print, notes, &list_options.time);
         return
     }
 
     if dump_protected.is_some() {
-        let notes = dump_notes(&cpo,true);
-        print_dump(&cr_print, notes);
+        let notes = filter_since(dump_matching_notes(&store, true, &list_options.query), &list_options.since);
+        print_dump(&cr_print, notes, &list_options.time);
         return
     }
 
 
     // if there is no input at all show the menu
     // @todo pass flag encrypt message here
-    list_note_titles(&cpo, &cr_print)
+    list_note_titles(&store, &cr_print, &list_options)
 }
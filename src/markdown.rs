@@ -0,0 +1,281 @@
+use std::io;
+
+use regex::Regex;
+use termcolor::{Color, ColorSpec, WriteColor};
+
+/**
+* A piece of a rendered line that is printed with one set of terminal colors
+*/
+#[derive(Debug, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub spec: ColorSpec
+}
+
+impl Span {
+    pub(crate) fn new(text: &str, spec: &ColorSpec) -> Span {
+        Span { text: text.to_string(), spec: spec.clone() }
+    }
+}
+
+fn fg(color: Color) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_fg(Some(color));
+    spec
+}
+
+fn dimmed() -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_dimmed(true);
+    spec
+}
+
+fn heading_spec(level: usize) -> ColorSpec {
+    let mut spec = match level {
+        1 => fg(Color::Magenta),
+        2 => fg(Color::Cyan),
+        _ => fg(Color::Blue)
+    };
+    spec.set_bold(true).set_underline(level == 1);
+    spec
+}
+
+/**
+* Finds the next position of the pattern at or after start
+*/
+fn find_closing(chars: &[char], start: usize, pattern: &[char]) -> Option<usize> {
+    (start..chars.len()).find(|&i| chars[i..].starts_with(pattern))
+}
+
+fn is_word_char(chars: &[char], i: Option<usize>) -> bool {
+    i.and_then(|i| chars.get(i)).map(|c| c.is_alphanumeric()).unwrap_or(false)
+}
+
+/**
+* Styles emphasis, strong emphasis, inline code and links, everything else is kept as is
+* @base - the style of the surrounding text, ex: italic in a block quote
+*/
+fn parse_inline(text: &str, base: &ColorSpec) -> Vec<Span> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut spans = vec![];
+    let mut plain = String::new();
+    let flush = |plain: &mut String, spans: &mut Vec<Span>| {
+        if !plain.is_empty() {
+            spans.push(Span::new(plain, base));
+            plain.clear();
+        }
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '`' {
+            if let Some(end) = find_closing(&chars, i + 1, &['`']) {
+                flush(&mut plain, &mut spans);
+                let mut spec = base.clone();
+                spec.set_fg(Some(Color::Yellow));
+                spans.push(Span::new(&chars[i + 1..end].iter().collect::<String>(), &spec));
+                i = end + 1;
+                continue
+            }
+        }
+
+        if (c == '*' || c == '_') && chars.get(i + 1) == Some(&c) {
+            if let Some(end) = find_closing(&chars, i + 2, &[c, c]).filter(|&end| end > i + 2) {
+                flush(&mut plain, &mut spans);
+                let mut spec = base.clone();
+                spec.set_bold(true);
+                spans.extend(parse_inline(&chars[i + 2..end].iter().collect::<String>(), &spec));
+                i = end + 2;
+                continue
+            }
+        }
+
+        // an underscore inside a word is not emphasis, ex: snake_case
+        let opens = chars.get(i + 1).map(|next| !next.is_whitespace()).unwrap_or(false) && !(c == '_' && is_word_char(&chars, i.checked_sub(1)));
+        if (c == '*' || c == '_') && opens {
+            let closing = (i + 1..chars.len()).find(|&end| {
+                chars[end] == c && end > i + 1 && !chars[end - 1].is_whitespace() && !(c == '_' && is_word_char(&chars, Some(end + 1)))
+            });
+            if let Some(end) = closing {
+                flush(&mut plain, &mut spans);
+                let mut spec = base.clone();
+                spec.set_italic(true);
+                spans.extend(parse_inline(&chars[i + 1..end].iter().collect::<String>(), &spec));
+                i = end + 1;
+                continue
+            }
+        }
+
+        if c == '[' {
+            let link = find_closing(&chars, i + 1, &[']', '('])
+                .and_then(|label_end| find_closing(&chars, label_end + 2, &[')']).map(|url_end| (label_end, url_end)));
+            if let Some((label_end, url_end)) = link {
+                flush(&mut plain, &mut spans);
+                let label = chars[i + 1..label_end].iter().collect::<String>();
+                let url = chars[label_end + 2..url_end].iter().collect::<String>();
+                let mut spec = base.clone();
+                spec.set_fg(Some(Color::Blue)).set_underline(true);
+                spans.extend(parse_inline(&label, &spec));
+                if !url.eq(&label) {
+                    spans.push(Span::new(&format!(" ({})", url), &dimmed()));
+                }
+                i = url_end + 1;
+                continue
+            }
+        }
+
+        plain.push(c);
+        i += 1;
+    }
+    flush(&mut plain, &mut spans);
+
+    spans
+}
+
+fn is_rule(line: &str) -> bool {
+    let marks = line.chars().filter(|c| !c.is_whitespace()).collect::<Vec<char>>();
+    marks.len() >= 3 && ['-', '*', '_'].contains(&marks[0]) && marks.iter().all(|c| c == &marks[0])
+}
+
+/**
+* Turns a markdown note into lines of styled text, code blocks are kept exactly as written
+*/
+pub(crate) fn render_markdown(body: &str) -> Vec<Vec<Span>> {
+    let heading_re = Regex::new(r"^(#{1,6})\s+(.*?)(?:\s+#+)?\s*$").unwrap();
+    let list_re = Regex::new(r"^(\s*)([-*+]|\d+[.)])\s+(.*)$").unwrap();
+    let checkbox_re = Regex::new(r"^\[([ xX])\]\s+(.*)$").unwrap();
+    let fence_re = Regex::new(r"^\s*(```|~~~)\s*(\S*)").unwrap();
+
+    let mut lines = vec![];
+    let mut fence: Option<String> = None;
+    for line in body.lines() {
+        if let Some(open) = &fence {
+            if line.trim_start().starts_with(open.as_str()) {
+                lines.push(vec![Span::new("╰─", &dimmed())]);
+                fence = None;
+            } else {
+                lines.push(vec![Span::new("│ ", &dimmed()), Span::new(line, &fg(Color::Yellow))]);
+            }
+            continue
+        }
+
+        if let Some(caps) = fence_re.captures(line) {
+            fence = Some(caps[1].to_string());
+            let label = if caps[2].is_empty() { "╭─".to_string() } else { format!("╭─ {}", &caps[2]) };
+            lines.push(vec![Span::new(&label, &dimmed())]);
+            continue
+        }
+
+        if let Some(caps) = heading_re.captures(line) {
+            lines.push(parse_inline(&caps[2], &heading_spec(caps[1].len())));
+            continue
+        }
+
+        if is_rule(line) {
+            lines.push(vec![Span::new(&"─".repeat(40), &dimmed())]);
+            continue
+        }
+
+        if line.trim_start().starts_with('>') {
+            let trimmed = line.trim_start();
+            let depth = trimmed.chars().take_while(|c| c == &'>' || c == &' ').filter(|c| c == &'>').count();
+            let text = trimmed.trim_start_matches(['>', ' ']);
+            let mut spec = ColorSpec::new();
+            spec.set_italic(true);
+            let mut spans = vec![Span::new(&"│ ".repeat(depth), &dimmed())];
+            spans.extend(parse_inline(text, &spec));
+            lines.push(spans);
+            continue
+        }
+
+        if let Some(caps) = list_re.captures(line) {
+            let marker = if caps[2].chars().all(|c| c.is_ascii_digit() || c == '.' || c == ')') { caps[2].to_string() } else { "•".to_string() };
+            let mut spans = vec![Span::new(&caps[1], &ColorSpec::new())];
+            match checkbox_re.captures(&caps[3]) {
+                Some(task) => {
+                    let checked = !task[1].eq(" ");
+                    spans.push(Span::new(if checked { "☑ " } else { "☐ " }, &fg(Color::Green)));
+                    let mut spec = ColorSpec::new();
+                    spec.set_dimmed(checked).set_strikethrough(checked);
+                    spans.extend(parse_inline(&task[2], &spec));
+                }
+                None => {
+                    spans.push(Span::new(&format!("{} ", marker), &fg(Color::Cyan)));
+                    spans.extend(parse_inline(&caps[3], &ColorSpec::new()));
+                }
+            }
+            lines.push(spans);
+            continue
+        }
+
+        lines.push(parse_inline(line, &ColorSpec::new()));
+    }
+
+    lines
+}
+
+pub(crate) fn write_lines(out: &mut dyn WriteColor, lines: Vec<Vec<Span>>) -> io::Result<()> {
+    for line in lines {
+        for span in line {
+            out.set_color(&span.spec)?;
+            write!(out, "{}", span.text)?;
+        }
+        out.reset()?;
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use termcolor::{Buffer, Color};
+
+    use crate::markdown::{render_markdown, Span, write_lines};
+
+    fn line_text(line: &[Span]) -> String {
+        line.iter().map(|span| span.text.to_string()).collect()
+    }
+
+    #[test]
+    fn test_render_markdown_blocks() {
+        let lines = render_markdown("# Title\n> quote\n- item\n2. second\n  - [ ] todo\n- [x] done\n---\n```rust\nlet x = *y;\n```\nplain");
+        let text = lines.iter().map(|line| line_text(line)).collect::<Vec<String>>();
+        assert_eq!(text, vec!["Title", "│ quote", "• item", "2. second", "  ☐ todo", "☑ done", "─".repeat(40).as_str(), "╭─ rust", "│ let x = *y;", "╰─", "plain"]);
+        assert!(lines[0][0].spec.bold());
+        assert!(lines[0][0].spec.underline());
+        assert!(lines[1][1].spec.italic());
+        assert!(lines[5][2].spec.strikethrough());
+        assert!(!lines[4][2].spec.strikethrough());
+        assert_eq!(lines[8][1].spec.fg(), Some(&Color::Yellow));
+    }
+
+    #[test]
+    fn test_render_markdown_inline() {
+        let lines = render_markdown("a **bold** and *em* with `code` and [docs](https://example.com) in snake_case_name");
+        let line = &lines[0];
+        assert_eq!(line_text(line), "a bold and em with code and docs (https://example.com) in snake_case_name");
+        assert!(line.iter().find(|span| span.text == "bold").unwrap().spec.bold());
+        assert!(line.iter().find(|span| span.text == "em").unwrap().spec.italic());
+        assert_eq!(line.iter().find(|span| span.text == "code").unwrap().spec.fg(), Some(&Color::Yellow));
+        assert!(line.iter().find(|span| span.text == "docs").unwrap().spec.underline());
+
+        // unmatched markers are kept
+        assert_eq!(line_text(&render_markdown("2 * 3 = 6 and **open")[0]), "2 * 3 = 6 and **open");
+        assert_eq!(line_text(&render_markdown("[not a link]")[0]), "[not a link]");
+    }
+
+    #[test]
+    fn test_write_lines() {
+        let mut plain = Buffer::no_color();
+        write_lines(&mut plain, render_markdown("## Hello *there*")).unwrap();
+        assert_eq!(String::from_utf8(plain.into_inner()).unwrap(), "Hello there\n");
+
+        let mut colored = Buffer::ansi();
+        write_lines(&mut colored, render_markdown("## Hello")).unwrap();
+        let text = String::from_utf8(colored.into_inner()).unwrap();
+        assert!(text.contains("\x1b[1m"));
+        assert!(text.contains("Hello"));
+    }
+}
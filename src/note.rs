@@ -0,0 +1,255 @@
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::content_type::ContentType;
+use crate::security::seal_note;
+use crate::utils::{make_text_single_line, slice_text};
+
+// titles are cut to this many characters when a note is saved
+pub const MAX_TITLE_LENGTH: usize = 128;
+
+/**
+* The id a note is looked up by, ex: tru -f 3
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct NoteId(pub usize);
+
+// keeps the width and alignment of the format, ex: {:9}
+impl fmt::Display for NoteId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for NoteId {
+    type Err = ParseIntError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        text.trim().parse().map(NoteId)
+    }
+}
+
+impl From<usize> for NoteId {
+    fn from(id: usize) -> Self {
+        NoteId(id)
+    }
+}
+
+/**
+* The id of a note's body, it stays the same when a note is synced or archived
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ContentId(String);
+
+impl ContentId {
+    pub fn generate() -> ContentId {
+        ContentId(Uuid::new_v4().to_string())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ContentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for ContentId {
+    fn from(id: String) -> Self {
+        ContentId(id)
+    }
+}
+
+impl From<&str> for ContentId {
+    fn from(id: &str) -> Self {
+        ContentId(id.to_string())
+    }
+}
+
+/**
+* A note as it is stored, protected notes keep their encrypted title and body
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    pub id: NoteId,
+    pub content_id: ContentId,
+    pub title: String,
+    pub body: String,
+    pub protected: bool,
+    pub trashed: bool,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub due: Option<DateTime<Utc>>,
+    // None for protected notes, their type is detected after they are decrypted
+    pub content_type: Option<ContentType>,
+    // the key a protected note is encrypted with, wrapped with the boss key. None for notes protected with the boss key itself
+    #[serde(default)]
+    pub data_key: Option<String>
+}
+
+impl Note {
+    pub fn builder() -> NoteBuilder {
+        NoteBuilder::default()
+    }
+}
+
+/**
+* A note that has not been saved yet, see NoteStore::create_note
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewNote {
+    pub title: String,
+    pub body: String,
+    pub protected: bool,
+    pub due: Option<DateTime<Utc>>,
+    pub content_type: Option<ContentType>,
+    pub data_key: Option<String>
+}
+
+#[derive(Default)]
+pub struct NoteBuilder {
+    title: Option<String>,
+    body: String,
+    boss_key: Option<String>,
+    due: Option<DateTime<Utc>>,
+    content_type: Option<ContentType>
+}
+
+impl NoteBuilder {
+    pub fn title(mut self, title: &str) -> NoteBuilder {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> NoteBuilder {
+        self.body = body.to_string();
+        self
+    }
+
+    /**
+    * Encrypts the title and body with a new data key wrapped with the boss key when the note is built
+    */
+    pub fn protect(mut self, boss_key: &str) -> NoteBuilder {
+        self.boss_key = Some(boss_key.to_string());
+        self
+    }
+
+    pub fn due(mut self, due: DateTime<Utc>) -> NoteBuilder {
+        self.due = Some(due);
+        self
+    }
+
+    /**
+    * Without a content type it is detected from the body
+    */
+    pub fn content_type(mut self, content_type: ContentType) -> NoteBuilder {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    /**
+    * Like a quick note the title is the start of the body when it is not set, notes need a title or a body
+    */
+    pub fn build(self) -> Result<NewNote, String> {
+        let title = make_text_single_line(self.title.as_deref().unwrap_or(&self.body));
+        if title.trim().is_empty() && self.body.trim().is_empty() {
+            return Err("A note needs a title or a body.".to_string())
+        }
+        let title = slice_text(0, MAX_TITLE_LENGTH, &title);
+
+        Ok(match self.boss_key {
+            Some(boss_key) => {
+                let (title, body, data_key) = seal_note(&boss_key, &title, &self.body);
+                NewNote {
+                    title,
+                    body,
+                    protected: true,
+                    due: self.due,
+                    content_type: None,
+                    data_key: Some(data_key),
+                }
+            }
+            None => NewNote {
+                title,
+                body: self.body,
+                protected: false,
+                due: self.due,
+                content_type: self.content_type,
+                data_key: None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use crate::content_type::ContentType;
+    use crate::note::{ContentId, Note, NoteId};
+    use crate::security::unseal_note;
+
+    #[test]
+    fn test_note_ids() {
+        assert_eq!(" 42 ".parse::<NoteId>(), Ok(NoteId(42)));
+        assert!("forty two".parse::<NoteId>().is_err());
+        assert_eq!(NoteId(7).to_string(), "7");
+        assert_eq!(format!("{:3}|{:<3}|", NoteId(7), NoteId(8)), "  7|8  |");
+        assert_ne!(ContentId::generate(), ContentId::generate());
+        assert_eq!(ContentId::from("abc").as_str(), "abc");
+    }
+
+    #[test]
+    fn test_note_builder() {
+        let due = Utc.with_ymd_and_hms(2024, 7, 1, 9, 0, 0).unwrap();
+        let note = Note::builder().title("Deploy").body("kubectl apply").content_type(ContentType::Sh).due(due).build().unwrap();
+        assert_eq!((note.title.as_str(), note.body.as_str(), note.protected), ("Deploy", "kubectl apply", false));
+        assert_eq!((note.due, note.content_type), (Some(due), Some(ContentType::Sh)));
+
+        // the title comes from the body like a quick note
+        let note = Note::builder().body("first line\nsecond line").build().unwrap();
+        assert_eq!(note.title, "first line second line");
+        assert_eq!(Note::builder().title(&"a".repeat(200)).build().unwrap().title.len(), 128);
+        assert!(Note::builder().body("  ").build().is_err());
+
+        let note = Note::builder().title("Secret").body("token").protect("boss key").build().unwrap();
+        assert!(note.protected && note.content_type.is_none());
+        let unsealed = unseal_note("boss key", note.data_key.as_deref(), &note.title, &note.body);
+        assert_eq!(unsealed, Some(("Secret".to_string(), "token".to_string())));
+    }
+
+    #[test]
+    fn test_note_serde() {
+        let time = Utc.with_ymd_and_hms(2024, 7, 1, 22, 56, 27).unwrap();
+        let note = Note {
+            id: NoteId(3),
+            content_id: ContentId::from("abc"),
+            title: "Groceries".to_string(),
+            body: "eggs".to_string(),
+            protected: false,
+            trashed: false,
+            created: time,
+            updated: time,
+            due: None,
+            content_type: Some(ContentType::Text),
+            data_key: None,
+        };
+
+        let json = serde_json::to_value(&note).unwrap();
+        assert_eq!(json["id"], 3);
+        assert_eq!(json["content_id"], "abc");
+        assert_eq!(json["created"], "2024-07-01T22:56:27Z");
+        assert_eq!(json["content_type"], "text");
+        assert_eq!(serde_json::from_value::<Note>(json).unwrap(), note);
+    }
+}
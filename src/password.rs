@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::store::NoteStore;
+
+// used when the config table does not set a policy
+const DEFAULT_MIN_LENGTH: usize = 8;
+const DEFAULT_MIN_STRENGTH: PasswordStrength = PasswordStrength::Fair;
+
+// a password that contains one of these is guessed early, whatever else it has
+const COMMON_PASSWORDS: [&str; 12] = ["password", "123456", "12345678", "qwerty", "letmein", "iloveyou", "admin", "welcome", "monkey", "dragon", "abc123", "trustno1"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PasswordStrength {
+    VeryWeak,
+    Weak,
+    Fair,
+    Strong,
+    VeryStrong
+}
+
+impl PasswordStrength {
+    /**
+    * Reads the password_min_strength config value, ex: fair or very_strong
+    */
+    pub fn from_config(value: &str) -> Option<PasswordStrength> {
+        match value.trim().to_lowercase().replace(['-', ' '], "_").as_str() {
+            "very_weak" => Some(PasswordStrength::VeryWeak),
+            "weak" => Some(PasswordStrength::Weak),
+            "fair" => Some(PasswordStrength::Fair),
+            "strong" => Some(PasswordStrength::Strong),
+            "very_strong" => Some(PasswordStrength::VeryStrong),
+            _ => None
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PasswordStrength::VeryWeak => "very weak",
+            PasswordStrength::Weak => "weak",
+            PasswordStrength::Fair => "fair",
+            PasswordStrength::Strong => "strong",
+            PasswordStrength::VeryStrong => "very strong"
+        }
+    }
+
+    fn from_bits(bits: f64) -> PasswordStrength {
+        match bits {
+            bits if bits < 28.0 => PasswordStrength::VeryWeak,
+            bits if bits < 36.0 => PasswordStrength::Weak,
+            bits if bits < 60.0 => PasswordStrength::Fair,
+            bits if bits < 80.0 => PasswordStrength::Strong,
+            _ => PasswordStrength::VeryStrong
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrengthEstimate {
+    pub strength: PasswordStrength,
+    // a rough guess of how many bits an attacker has to guess
+    pub bits: f64,
+    pub feedback: Vec<String>
+}
+
+/**
+* Passwords are compared after NFKC normalization so that the same passphrase typed on another keyboard or OS still works
+*/
+pub fn normalize_password(password: &str) -> String {
+    password.nfkc().collect()
+}
+
+/**
+* Estimates how hard a password is to guess from its length, the kinds of characters it uses and common patterns
+*/
+pub fn estimate_strength(password: &str) -> StrengthEstimate {
+    let password = normalize_password(password);
+    let chars = password.chars().collect::<Vec<char>>();
+    let mut feedback = vec![];
+
+    let pools = [
+        (chars.iter().any(|c| c.is_ascii_lowercase()), 26.0),
+        (chars.iter().any(|c| c.is_ascii_uppercase()), 26.0),
+        (chars.iter().any(|c| c.is_ascii_digit()), 10.0),
+        (chars.iter().any(|c| c.is_ascii_punctuation() || c.is_whitespace()), 33.0),
+        (chars.iter().any(|c| !c.is_ascii()), 100.0),
+    ];
+    let pool_size = pools.iter().filter(|(used, _)| *used).map(|(_, size)| size).sum::<f64>();
+    let mut bits = if chars.is_empty() { 0.0 } else { chars.len() as f64 * pool_size.log2() };
+
+    if pools.iter().filter(|(used, _)| *used).count() < 2 {
+        feedback.push("Mix in other kinds of characters, like spaces, digits or punctuation.".to_string());
+    }
+    let unique_chars = chars.iter().collect::<HashSet<&char>>().len();
+    if unique_chars * 2 < chars.len() {
+        bits /= 2.0;
+        feedback.push("Avoid repeating the same characters.".to_string());
+    }
+    let lowercase = password.to_lowercase();
+    if COMMON_PASSWORDS.iter().any(|common| lowercase.contains(common)) {
+        bits = bits.min(20.0);
+        feedback.push("Avoid common passwords and words like password.".to_string());
+    }
+    if chars.len() < 12 {
+        feedback.push("Longer is stronger, try a passphrase of a few words.".to_string());
+    }
+
+    StrengthEstimate { strength: PasswordStrength::from_bits(bits), bits, feedback }
+}
+
+/**
+* The minimum a new password has to meet, set with the password_min_length and password_min_strength config values
+*/
+#[derive(Debug, PartialEq)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub min_strength: PasswordStrength
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy { min_length: DEFAULT_MIN_LENGTH, min_strength: DEFAULT_MIN_STRENGTH }
+    }
+}
+
+impl PasswordPolicy {
+    /**
+    * Values that can not be read fall back to the defaults
+    */
+    pub fn from_store(store: &dyn NoteStore) -> PasswordPolicy {
+        let min_length = store.get_value("config", "password_min_length").and_then(|value| value.trim().parse().ok());
+        let min_strength = store.get_value("config", "password_min_strength").as_deref().and_then(PasswordStrength::from_config);
+
+        PasswordPolicy {
+            min_length: min_length.unwrap_or(DEFAULT_MIN_LENGTH),
+            min_strength: min_strength.unwrap_or(DEFAULT_MIN_STRENGTH),
+        }
+    }
+
+    /**
+    * Returns the strength of a password that meets the policy, or why it does not
+    */
+    pub fn check(&self, password: &str) -> Result<StrengthEstimate, String> {
+        let password = normalize_password(password);
+        if password.trim().is_empty() {
+            return Err("Password may not be blank.".to_string())
+        }
+        if password.chars().count() < self.min_length {
+            return Err(format!("Password must be at least {} characters.", self.min_length))
+        }
+        let estimate = estimate_strength(&password);
+        if estimate.strength < self.min_strength {
+            return Err(format!("Password is {}, it needs to be at least {}. {}", estimate.strength.label(), self.min_strength.label(), estimate.feedback.join(" ")).trim_end().to_string())
+        }
+
+        Ok(estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::password::{estimate_strength, normalize_password, PasswordPolicy, PasswordStrength};
+    use crate::store::{MemoryStore, NoteStore};
+
+    #[test]
+    fn test_normalize_password() {
+        // a precomposed é and e with a combining accent are the same passphrase
+        assert_eq!(normalize_password("caf\u{e9}"), normalize_password("cafe\u{301}"));
+        // full width letters are typed by some input methods
+        assert_eq!(normalize_password("ｐａｓｓ"), "pass");
+    }
+
+    #[test]
+    fn test_estimate_strength() {
+        assert_eq!(estimate_strength("").strength, PasswordStrength::VeryWeak);
+        assert_eq!(estimate_strength("abc").strength, PasswordStrength::VeryWeak);
+        assert_eq!(estimate_strength("Foobar123").strength, PasswordStrength::Fair);
+        assert_eq!(estimate_strength("correct horse battery staple").strength, PasswordStrength::VeryStrong);
+        assert_eq!(estimate_strength("ça sent le café 🛟").strength, PasswordStrength::VeryStrong);
+
+        let estimate = estimate_strength("Password123456!");
+        assert_eq!(estimate.strength, PasswordStrength::VeryWeak);
+        assert!(estimate.feedback.iter().any(|feedback| feedback.contains("common passwords")));
+        assert!(estimate_strength("aaaaaaaaaaaaaaaa").feedback.iter().any(|feedback| feedback.contains("repeating")));
+    }
+
+    #[test]
+    fn test_password_policy() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.check("Foobar123").is_ok());
+        assert!(policy.check("mañana por la mañana").is_ok());
+        assert_eq!(policy.check("   ").unwrap_err(), "Password may not be blank.");
+        assert_eq!(policy.check("Fo0!").unwrap_err(), "Password must be at least 8 characters.");
+        assert!(policy.check("password1").unwrap_err().starts_with("Password is very weak, it needs to be at least fair."));
+
+        let store = MemoryStore::new();
+        assert_eq!(PasswordPolicy::from_store(&store), PasswordPolicy::default());
+        store.save_values("config", &[("password_min_length", "12"), ("password_min_strength", "very_strong")], true);
+        let policy = PasswordPolicy::from_store(&store);
+        assert_eq!(policy, PasswordPolicy { min_length: 12, min_strength: PasswordStrength::VeryStrong });
+        assert!(policy.check("Foobar123456").is_err());
+        assert!(policy.check("correct horse battery staple").is_ok());
+        assert_eq!(PasswordStrength::from_config("Very Strong"), Some(PasswordStrength::VeryStrong));
+        assert_eq!(PasswordStrength::from_config("foo"), None);
+    }
+}
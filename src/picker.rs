@@ -0,0 +1,347 @@
+use std::io;
+use std::io::{IsTerminal, Write};
+
+use crossterm::{cursor, execute, queue, terminal};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+
+use crate::utils::display_width;
+
+// a matched character, the start of a word and a run of matched characters are worth more
+const MATCH_SCORE: i64 = 16;
+const WORD_START_BONUS: i64 = 10;
+const CONSECUTIVE_BONUS: i64 = 8;
+// longer lines only have their start searched
+const MAX_LINE_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PickAction {
+    Print,
+    Open,
+    Edit
+}
+
+impl PickAction {
+    pub fn parse(value: &str) -> Option<PickAction> {
+        match value.trim().to_lowercase().as_str() {
+            "print" => Some(PickAction::Print),
+            "open" => Some(PickAction::Open),
+            "edit" => Some(PickAction::Edit),
+            _ => None
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            PickAction::Print => "print",
+            PickAction::Open => "open",
+            PickAction::Edit => "edit"
+        }
+    }
+}
+
+/**
+* A note the picker can find, protected notes have no body so only their (hidden) title is listed
+*/
+pub struct PickerNote {
+    pub id: usize,
+    pub title: String,
+    pub body: Option<String>
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PickerMatch {
+    // the position of the note in the picker notes
+    pub index: usize,
+    pub score: i64,
+    // the matched characters of the title
+    pub positions: Vec<usize>,
+    // the best matching body line and its matched characters, when the title does not match as well
+    pub snippet: Option<(String, Vec<usize>)>
+}
+
+fn lower(ch: char) -> char {
+    ch.to_lowercase().next().unwrap_or(ch)
+}
+
+/**
+* Scores the characters of a term found in order, ex: "gst" in "Get Started"
+*/
+fn match_term(term: &[char], text: &[char]) -> Option<(i64, Vec<usize>)> {
+    let first = *term.first()?;
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in (0..text.len()).filter(|&i| lower(text[i]) == first) {
+        let mut positions = vec![start];
+        let mut next = start + 1;
+        for ch in &term[1..] {
+            match (next..text.len()).find(|&i| lower(text[i]) == *ch) {
+                Some(i) => {
+                    positions.push(i);
+                    next = i + 1;
+                }
+                None => break
+            }
+        }
+        if positions.len() < term.len() {
+            // later starts can't find more characters
+            break
+        }
+
+        let mut score = 0;
+        for (n, &i) in positions.iter().enumerate() {
+            score += MATCH_SCORE;
+            if i == 0 || !text[i - 1].is_alphanumeric() {
+                score += WORD_START_BONUS;
+            }
+            if n > 0 {
+                let gap = (i - positions[n - 1] - 1) as i64;
+                score += if gap == 0 { CONSECUTIVE_BONUS } else { -gap.min(MATCH_SCORE - 1) };
+            }
+        }
+        if best.as_ref().map(|(best_score, _)| score > *best_score).unwrap_or(true) {
+            best = Some((score, positions));
+        }
+    }
+
+    best
+}
+
+/**
+* Every word of the query has to match, ex: "rel notes" matches "Release notes" and "Notes for the release"
+*/
+pub fn fuzzy_match(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let text = text.chars().take(MAX_LINE_CHARS).collect::<Vec<char>>();
+    let mut score = 0;
+    let mut positions = vec![];
+    for term in query.split_whitespace() {
+        let term = term.chars().map(lower).collect::<Vec<char>>();
+        let (term_score, term_positions) = match_term(&term, &text)?;
+        score += term_score;
+        positions.extend(term_positions);
+    }
+    positions.sort_unstable();
+    positions.dedup();
+
+    Some((score, positions))
+}
+
+/**
+* Ranks notes by how well their title or a line of their body matches, title matches count double.
+* An empty query keeps every note in its original order.
+*/
+pub fn rank_notes(query: &str, notes: &[PickerNote]) -> Vec<PickerMatch> {
+    let mut matches = vec![];
+    for (index, note) in notes.iter().enumerate() {
+        if query.trim().is_empty() {
+            matches.push(PickerMatch { index, score: 0, positions: vec![], snippet: None });
+            continue
+        }
+        let title = fuzzy_match(query, &note.title).map(|(score, positions)| (score * 2, positions));
+        let line = note.body.as_deref().unwrap_or_default().lines()
+            .filter_map(|line| fuzzy_match(query, line.trim()).map(|(score, positions)| (score, line.trim().to_string(), positions)))
+            .max_by_key(|(score, _, _)| *score);
+
+        let found = match (title, line) {
+            (Some((title_score, positions)), Some((line_score, _, _))) if title_score >= line_score => PickerMatch { index, score: title_score, positions, snippet: None },
+            (Some((title_score, positions)), None) => PickerMatch { index, score: title_score, positions, snippet: None },
+            (_, Some((line_score, line, positions))) => PickerMatch { index, score: line_score, positions: vec![], snippet: Some((line, positions)) },
+            (None, None) => continue
+        };
+        matches.push(found);
+    }
+    // a stable sort keeps the original order for equal scores
+    matches.sort_by_key(|found| std::cmp::Reverse(found.score));
+
+    matches
+}
+
+/**
+* Puts the terminal back when the picker closes, even after an error
+*/
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stderr(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stderr(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/**
+* Prints text with its matched characters highlighted, text that does not fit in the width is cut off
+*/
+fn queue_highlighted(out: &mut impl Write, text: &str, positions: &[usize], width: usize, dimmed: bool) -> io::Result<usize> {
+    let mut used = 0;
+    for (i, ch) in text.chars().enumerate() {
+        let ch_width = display_width(&ch.to_string());
+        if used + ch_width > width {
+            break
+        }
+        used += ch_width;
+        if positions.contains(&i) {
+            queue!(out, SetForegroundColor(Color::Yellow), SetAttribute(Attribute::Bold), Print(ch), SetAttribute(Attribute::NormalIntensity), ResetColor)?;
+        } else if dimmed {
+            queue!(out, SetAttribute(Attribute::Dim), Print(ch), SetAttribute(Attribute::NormalIntensity))?;
+        } else {
+            queue!(out, Print(ch))?;
+        }
+    }
+
+    Ok(used)
+}
+
+// some terminals (ex: a pty without a size) report 0 columns or rows
+fn picker_size() -> io::Result<(usize, usize)> {
+    match terminal::size()? {
+        (0, _) | (_, 0) => Ok((80, 24)),
+        (columns, rows) => Ok((columns as usize, rows as usize))
+    }
+}
+
+fn draw(out: &mut impl Write, query: &str, notes: &[PickerNote], matches: &[PickerMatch], selected: usize, offset: usize, action: PickAction) -> io::Result<()> {
+    let (width, rows) = picker_size()?;
+    queue!(out, terminal::Clear(terminal::ClearType::All), cursor::MoveTo(0, 0), Print(format!("> {}", query)))?;
+    queue!(out, cursor::MoveTo(0, 1), SetAttribute(Attribute::Dim),
+        Print(format!("  {}/{}  enter: {}  ctrl-o: open  ctrl-e: edit  esc: cancel", matches.len(), notes.len(), action.as_str())),
+        SetAttribute(Attribute::Reset))?;
+
+    for (row, found) in matches.iter().enumerate().skip(offset).take(rows.saturating_sub(2)) {
+        let note = &notes[found.index];
+        queue!(out, cursor::MoveTo(0, (row - offset + 2) as u16))?;
+        let prefix = format!("{} {:>5} ", if row == selected { "▶" } else { " " }, note.id);
+        if row == selected {
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+        }
+        queue!(out, Print(&prefix))?;
+        let mut used = display_width(&prefix);
+        used += queue_highlighted(out, &note.title, &found.positions, width.saturating_sub(used), false)?;
+        if let Some((line, positions)) = &found.snippet {
+            if used + 3 < width {
+                queue!(out, SetAttribute(Attribute::Dim), Print(" · "), SetAttribute(Attribute::NormalIntensity))?;
+                queue_highlighted(out, line, positions, width - used - 3, true)?;
+            }
+        }
+        queue!(out, SetAttribute(Attribute::Reset))?;
+    }
+    queue!(out, cursor::MoveTo(display_width(&format!("> {}", query)) as u16, 0))?;
+
+    out.flush()
+}
+
+/**
+* Shows the picker on the terminal (stderr, so the standard output can still be piped)
+* @action - what enter does, ctrl-o and ctrl-e always open or edit
+* Returns the ID of the chosen note and what to do with it, or None when the picker is cancelled
+*/
+pub fn pick_note(notes: &[PickerNote], action: PickAction) -> io::Result<Option<(usize, PickAction)>> {
+    if !io::stderr().is_terminal() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "The note picker needs a terminal."))
+    }
+    let _guard = TerminalGuard::enter()?;
+    let mut out = io::stderr();
+    let mut query = String::new();
+    let mut matches = rank_notes(&query, notes);
+    let mut selected = 0;
+    let mut offset = 0;
+
+    loop {
+        let visible = picker_size()?.1.saturating_sub(2).max(1);
+        if selected < offset {
+            offset = selected;
+        } else if selected >= offset + visible {
+            offset = selected + 1 - visible;
+        }
+        draw(&mut out, &query, notes, &matches, selected, offset, action)?;
+
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            _ => continue
+        };
+        let chosen = |pick: PickAction| matches.get(selected).map(|found| (notes[found.index].id, pick));
+        match key {
+            KeyEvent { code: KeyCode::Esc, .. } => return Ok(None),
+            KeyEvent { code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL, .. } => return Ok(None),
+            KeyEvent { code: KeyCode::Enter, .. } => return Ok(chosen(action)),
+            KeyEvent { code: KeyCode::Char('o'), modifiers: KeyModifiers::CONTROL, .. } => return Ok(chosen(PickAction::Open)),
+            KeyEvent { code: KeyCode::Char('e'), modifiers: KeyModifiers::CONTROL, .. } => return Ok(chosen(PickAction::Edit)),
+            KeyEvent { code: KeyCode::Up, .. } | KeyEvent { code: KeyCode::Char('p'), modifiers: KeyModifiers::CONTROL, .. } => {
+                selected = selected.saturating_sub(1);
+            }
+            KeyEvent { code: KeyCode::Down, .. } | KeyEvent { code: KeyCode::Char('n'), modifiers: KeyModifiers::CONTROL, .. } => {
+                selected = (selected + 1).min(matches.len().saturating_sub(1));
+            }
+            KeyEvent { code: KeyCode::Backspace, .. } => {
+                query.pop();
+                matches = rank_notes(&query, notes);
+                selected = 0;
+            }
+            KeyEvent { code: KeyCode::Char(ch), modifiers, .. } if !modifiers.contains(KeyModifiers::CONTROL) => {
+                query.push(ch);
+                matches = rank_notes(&query, notes);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::picker::{fuzzy_match, PickAction, PickerNote, rank_notes};
+
+    fn make_notes() -> Vec<PickerNote> {
+        vec![
+            PickerNote { id: 1, title: "Get Started with tRusty".to_string(), body: Some("Welcome to tRusty the CLI notes app.".to_string()) },
+            PickerNote { id: 2, title: "Groceries".to_string(), body: Some("eggs\nmilk\nstarter for sourdough".to_string()) },
+            PickerNote { id: 3, title: "🔒 ENCRYPTED".to_string(), body: None },
+            PickerNote { id: 4, title: "Release checklist".to_string(), body: Some("- [ ] tag the release".to_string()) },
+        ]
+    }
+
+    #[test]
+    fn test_fuzzy_match() {
+        let (score, positions) = fuzzy_match("gst", "Get Started").unwrap();
+        assert_eq!(positions, vec![0, 4, 5]);
+        assert!(score > 0);
+        assert_eq!(fuzzy_match("STAR", "Get Started").unwrap().1, vec![4, 5, 6, 7]);
+        assert!(fuzzy_match("xyz", "Get Started").is_none());
+        assert!(fuzzy_match("started get", "Get Started").is_some());
+        // consecutive characters and word starts rank higher
+        assert!(fuzzy_match("rel", "Release").unwrap().0 > fuzzy_match("rel", "Remote logs").unwrap().0);
+    }
+
+    #[test]
+    fn test_rank_notes() {
+        let notes = make_notes();
+        assert_eq!(rank_notes("", &notes).iter().map(|found| found.index).collect::<Vec<usize>>(), vec![0, 1, 2, 3]);
+
+        let matches = rank_notes("start", &notes);
+        assert_eq!(matches[0].index, 0);
+        assert_eq!(matches[0].snippet, None);
+        // body matches show the line that matched
+        let groceries = matches.iter().find(|found| found.index == 1).unwrap();
+        assert_eq!(groceries.snippet.as_ref().unwrap().0, "starter for sourdough");
+
+        let matches = rank_notes("milk", &notes);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].index, 1);
+
+        assert_eq!(rank_notes("encrypted", &notes)[0].index, 2);
+        assert!(rank_notes("zzz", &notes).is_empty());
+    }
+
+    #[test]
+    fn test_parse_pick_action() {
+        assert_eq!(PickAction::parse("Edit"), Some(PickAction::Edit));
+        assert_eq!(PickAction::parse("print"), Some(PickAction::Print));
+        assert_eq!(PickAction::parse("delete"), None);
+    }
+}
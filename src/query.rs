@@ -0,0 +1,386 @@
+use chrono::{DateTime, Duration, Local, TimeZone};
+use regex::Regex;
+use rusqlite::ToSql;
+use rusqlite::types::Value;
+
+use crate::content_type::ContentType;
+use crate::dates::{parse_since, to_sqlite_timestamp};
+
+const FIELDS: &str = "tag, title, body, type, protected, trashed, pinned, due, created, updated, size";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le
+}
+
+impl Op {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<="
+        }
+    }
+}
+
+/**
+* A note filter like `tag:infra updated:>2026-01-01 protected:no title:"deploy" size:>10k`. Terms are ANDed
+* together and a leading - negates a term. Text, title, body and tag terms never match protected notes.
+* The query is turned into a SQL condition over the notes and content tables, values are always bound parameters.
+*/
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    conditions: Vec<String>,
+    params: Vec<(String, Value)>,
+    filters_trash: bool
+}
+
+impl Query {
+    /**
+    * Parses a query, dates are in local time like --since
+    */
+    pub fn parse(text: &str) -> Result<Query, String> {
+        Query::parse_at(text, Local::now())
+    }
+
+    pub fn parse_at<Tz: TimeZone>(text: &str, now: DateTime<Tz>) -> Result<Query, String> {
+        let mut query = Query::default();
+        for token in tokenize(text)? {
+            query.add_term(&token, &now)?;
+        }
+
+        Ok(query)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    /**
+    * True when the query has a trashed: term, lists that leave out the trash should include it then
+    */
+    pub fn filters_trash(&self) -> bool {
+        self.filters_trash
+    }
+
+    /**
+    * The SQL condition to add to a WHERE clause, an empty query matches every note
+    */
+    pub fn condition(&self) -> String {
+        if self.conditions.is_empty() {
+            return "TRUE".to_string()
+        }
+
+        self.conditions.join(" AND ")
+    }
+
+    pub fn params(&self) -> Vec<(&str, &dyn ToSql)> {
+        self.params.iter().map(|(name, value)| (name.as_str(), value as &dyn ToSql)).collect()
+    }
+
+    fn bind(&mut self, value: Value) -> String {
+        let name = format!(":q{}", self.params.len());
+        self.params.push((name.clone(), value));
+        name
+    }
+
+    fn add_term<Tz: TimeZone>(&mut self, token: &str, now: &DateTime<Tz>) -> Result<(), String> {
+        let (negated, term) = match token.strip_prefix('-') {
+            Some(term) if !term.is_empty() => (true, term),
+            _ => (false, token)
+        };
+
+        let condition = match split_field(term) {
+            Some((field, value)) => self.field_condition(term, &field, &value, now)?,
+            None => {
+                let name = self.bind(Value::Text(unquote(term)));
+                format!("notes.protected IS FALSE AND (instr(lower(notes.title), lower({0})) > 0 OR instr(lower(content.body), lower({0})) > 0)", name)
+            }
+        };
+
+        // NULL columns (ex: a note without a due date) never match, negated or not
+        self.conditions.push(match negated {
+            true => format!("NOT COALESCE(({}), TRUE)", condition),
+            false => format!("COALESCE(({}), FALSE)", condition)
+        });
+        Ok(())
+    }
+
+    fn field_condition<Tz: TimeZone>(&mut self, term: &str, field: &str, value: &str, now: &DateTime<Tz>) -> Result<String, String> {
+        let (op, value) = split_op(value);
+        let value = unquote(value);
+        if value.is_empty() {
+            return Err(format!("Missing a value in: {}", term))
+        }
+        let no_op = |field: &str| match op {
+            Some(_) => Err(format!("{}: can't be compared with < or >, in: {}", field, term)),
+            None => Ok(())
+        };
+
+        match field {
+            "tag" => {
+                no_op(field)?;
+                let tag = value.trim_start_matches('#').to_lowercase();
+                if tag.is_empty() || !tag.chars().all(|ch| ch.is_ascii_alphanumeric() || "_-/".contains(ch)) {
+                    return Err(format!("Tags can only have letters, numbers, _, - and /, in: {}", term))
+                }
+                // a #tag starts after a space or punctuation and ends before one
+                let name = self.bind(Value::Text(format!("*[^a-z0-9_]#{}[^a-z0-9_/-]*", tag)));
+                Ok(format!("notes.protected IS FALSE AND (' ' || lower(notes.title) || ' ' || lower(content.body) || ' ') GLOB {}", name))
+            }
+            "title" | "body" => {
+                no_op(field)?;
+                let column = if field == "title" { "notes.title" } else { "content.body" };
+                let name = self.bind(Value::Text(value));
+                Ok(format!("notes.protected IS FALSE AND instr(lower({}), lower({})) > 0", column, name))
+            }
+            "type" => {
+                no_op(field)?;
+                let content_type = ContentType::parse(&value)
+                    .ok_or(format!("Unknown type: {}, use one of: text, json, yaml, sh, rust, diff, log", value))?;
+                let name = self.bind(Value::Text(content_type.as_str().to_string()));
+                Ok(format!("notes.content_type = {}", name))
+            }
+            "protected" | "trashed" | "pinned" => {
+                no_op(field)?;
+                self.filters_trash |= field == "trashed";
+                let name = self.bind(Value::Integer(parse_yes_no(field, &value, term)? as i64));
+                Ok(format!("COALESCE(notes.{}, FALSE) = {}", field, name))
+            }
+            "due" if op.is_none() && is_yes_no(&value) => {
+                Ok(match parse_yes_no(field, &value, term)? {
+                    true => "notes.due IS NOT NULL".to_string(),
+                    false => "notes.due IS NULL".to_string()
+                })
+            }
+            "due" | "created" | "updated" => {
+                let (start, end) = parse_time_range(&value, now)
+                    .ok_or(format!("Could not read the date: {}, ex: yesterday, '3 days ago', 12h or 2026-01-01, in: {}", value, term))?;
+                // a day covers every time on it, ex: updated:>2026-01-01 starts the next day
+                let is_day = start != end;
+                let bounds = match (op.unwrap_or(Op::Eq), is_day) {
+                    (Op::Eq, false) => vec![(Op::Ge, start)],
+                    (Op::Eq, true) => vec![(Op::Ge, start), (Op::Lt, end)],
+                    (Op::Gt, true) => vec![(Op::Ge, end)],
+                    (Op::Le, true) => vec![(Op::Lt, end)],
+                    (op, _) => vec![(op, start)]
+                };
+                let conditions = bounds.into_iter().map(|(op, time)| {
+                    format!("notes.{} {} {}", field, op.as_sql(), self.bind(Value::Text(time)))
+                }).collect::<Vec<String>>();
+                Ok(conditions.join(" AND "))
+            }
+            "size" => {
+                let size = parse_size(&value).ok_or(format!("Could not read the size: {}, ex: 512, 10k or 2m, in: {}", value, term))?;
+                let name = self.bind(Value::Integer(size));
+                Ok(format!("length(CAST(content.body AS BLOB)) {} {}", op.unwrap_or(Op::Eq).as_sql(), name))
+            }
+            _ => Err(format!("Unknown field: {}, use one of: {}", field, FIELDS))
+        }
+    }
+}
+
+/**
+* Splits a query on spaces, quoted text (ex: title:"deploy notes") stays together
+*/
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quote = None;
+    for ch in text.chars() {
+        match (quote, ch) {
+            (Some(open), ch) if ch == open => {
+                quote = None;
+                token.push(ch);
+            }
+            (Some(_), ch) => token.push(ch),
+            (None, '"' | '\'') => {
+                quote = Some(ch);
+                token.push(ch);
+            }
+            (None, ch) if ch.is_whitespace() => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            (None, ch) => token.push(ch)
+        }
+    }
+
+    if let Some(open) = quote {
+        return Err(format!("Missing a closing {} in: {}", open, token))
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    if tokens.is_empty() {
+        return Err("The query is empty, ex: tag:infra updated:>2026-01-01".to_string())
+    }
+
+    Ok(tokens)
+}
+
+// quoted text and links are search terms, ex: "tag:infra" searches for that text
+fn split_field(term: &str) -> Option<(String, String)> {
+    let (field, value) = term.split_once(':')?;
+    if field.is_empty() || !field.chars().all(|ch| ch.is_ascii_alphabetic()) || value.starts_with("//") {
+        return None
+    }
+
+    Some((field.to_lowercase(), value.to_string()))
+}
+
+fn split_op(value: &str) -> (Option<Op>, &str) {
+    for (prefix, op) in [(">=", Op::Ge), ("<=", Op::Le), (">", Op::Gt), ("<", Op::Lt), ("=", Op::Eq)] {
+        if let Some(rest) = value.strip_prefix(prefix) {
+            return (Some(op), rest)
+        }
+    }
+
+    (None, value)
+}
+
+fn unquote(text: &str) -> String {
+    for quote in ['"', '\''] {
+        if let Some(inner) = text.strip_prefix(quote).and_then(|text| text.strip_suffix(quote)) {
+            return inner.to_string()
+        }
+    }
+
+    text.to_string()
+}
+
+fn is_yes_no(value: &str) -> bool {
+    matches!(value.to_lowercase().as_str(), "yes" | "y" | "true" | "no" | "n" | "false")
+}
+
+fn parse_yes_no(field: &str, value: &str, term: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" | "y" | "true" => Ok(true),
+        "no" | "n" | "false" => Ok(false),
+        _ => Err(format!("{}: must be yes or no, in: {}", field, term))
+    }
+}
+
+/**
+* Dates cover the whole local day, other times (ex: 12h or 3 days ago) are a single point in time
+*/
+fn parse_time_range<Tz: TimeZone>(value: &str, now: &DateTime<Tz>) -> Option<(String, String)> {
+    let start = parse_since(value, now.clone())?;
+    let day_re = Regex::new(r"^(\d{4}-\d{2}-\d{2}|today|yesterday)$").unwrap();
+    let end = match day_re.is_match(&value.trim().to_lowercase()) {
+        true => start + Duration::days(1),
+        false => start
+    };
+
+    Some((to_sqlite_timestamp(start), to_sqlite_timestamp(end)))
+}
+
+/**
+* Sizes are bytes with an optional k or m suffix (1024 based), ex: 512, 10k, 2mb
+*/
+fn parse_size(value: &str) -> Option<i64> {
+    let value = value.trim().to_lowercase();
+    let caps = Regex::new(r"^(\d+)\s*(b|k|kb|m|mb)?$").unwrap().captures(&value)?;
+    let count = caps[1].parse::<i64>().ok()?;
+    let unit = match caps.get(2).map(|unit| unit.as_str()) {
+        Some("k" | "kb") => 1024,
+        Some("m" | "mb") => 1024 * 1024,
+        _ => 1
+    };
+
+    count.checked_mul(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use rusqlite::types::Value;
+
+    use crate::query::{parse_size, Query, tokenize};
+
+    fn parse(text: &str) -> Result<Query, String> {
+        Query::parse_at(text, Utc.with_ymd_and_hms(2026, 3, 10, 12, 0, 0).unwrap())
+    }
+
+    fn values(query: &Query) -> Vec<Value> {
+        query.params.iter().map(|(_, value)| value.clone()).collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize(r#"tag:infra  title:"deploy notes" -foo"#).unwrap(), vec!["tag:infra", "title:\"deploy notes\"", "-foo"]);
+        assert_eq!(tokenize("'a b'").unwrap(), vec!["'a b'"]);
+        assert_eq!(tokenize(r#"title:"deploy"#).unwrap_err(), "Missing a closing \" in: title:\"deploy");
+        assert!(tokenize("  ").is_err());
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let query = parse(r#"tag:#Infra protected:no title:"deploy" size:>10k"#).unwrap();
+        assert_eq!(query.condition(), "COALESCE((notes.protected IS FALSE AND (' ' || lower(notes.title) || ' ' || lower(content.body) || ' ') GLOB :q0), FALSE) \
+        AND COALESCE((COALESCE(notes.protected, FALSE) = :q1), FALSE) \
+        AND COALESCE((notes.protected IS FALSE AND instr(lower(notes.title), lower(:q2)) > 0), FALSE) \
+        AND COALESCE((length(CAST(content.body AS BLOB)) > :q3), FALSE)");
+        assert_eq!(values(&query), vec![Value::Text("*[^a-z0-9_]#infra[^a-z0-9_/-]*".to_string()), Value::Integer(0),
+                                        Value::Text("deploy".to_string()), Value::Integer(10240)]);
+        assert!(!query.filters_trash());
+        assert_eq!(query.params().len(), 4);
+
+        let query = parse("-trashed:yes due:no").unwrap();
+        assert_eq!(query.condition(), "NOT COALESCE((COALESCE(notes.trashed, FALSE) = :q0), TRUE) AND COALESCE((notes.due IS NULL), FALSE)");
+        assert!(query.filters_trash());
+
+        assert_eq!(Query::default().condition(), "TRUE");
+        assert!(Query::default().is_empty());
+    }
+
+    #[test]
+    fn test_parse_query_dates() {
+        let bounds = |text: &str| {
+            let query = parse(text).unwrap();
+            (query.condition(), values(&query))
+        };
+        let text = |value: &str| Value::Text(value.to_string());
+
+        let (condition, params) = bounds("updated:>2026-01-01");
+        assert_eq!(condition, "COALESCE((notes.updated >= :q0), FALSE)");
+        assert_eq!(params, vec![text("2026-01-02 00:00:00")]);
+
+        let (condition, params) = bounds("created:2026-01-01");
+        assert_eq!(condition, "COALESCE((notes.created >= :q0 AND notes.created < :q1), FALSE)");
+        assert_eq!(params, vec![text("2026-01-01 00:00:00"), text("2026-01-02 00:00:00")]);
+
+        assert_eq!(bounds("due:<=yesterday").1, vec![text("2026-03-10 00:00:00")]);
+        assert_eq!(bounds("updated:<12h").0, "COALESCE((notes.updated < :q0), FALSE)");
+        assert_eq!(bounds("updated:12h").1, vec![text("2026-03-10 00:00:00")]);
+    }
+
+    #[test]
+    fn test_parse_query_errors() {
+        assert_eq!(parse("color:red").unwrap_err(), "Unknown field: color, use one of: tag, title, body, type, protected, trashed, pinned, due, created, updated, size");
+        assert_eq!(parse("updated:>soon").unwrap_err(), "Could not read the date: soon, ex: yesterday, '3 days ago', 12h or 2026-01-01, in: updated:>soon");
+        assert_eq!(parse("size:>lots").unwrap_err(), "Could not read the size: lots, ex: 512, 10k or 2m, in: size:>lots");
+        assert_eq!(parse("protected:maybe").unwrap_err(), "protected: must be yes or no, in: protected:maybe");
+        assert_eq!(parse("tag:>infra").unwrap_err(), "tag: can't be compared with < or >, in: tag:>infra");
+        assert_eq!(parse("tag:in*fra").unwrap_err(), "Tags can only have letters, numbers, _, - and /, in: tag:in*fra");
+        assert_eq!(parse("title:").unwrap_err(), "Missing a value in: title:");
+        assert!(parse("type:xml").unwrap_err().starts_with("Unknown type: xml"));
+        // quoted and non field text is searched for
+        assert!(parse("\"color:red\" http://example.com").is_ok());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("10k"), Some(10240));
+        assert_eq!(parse_size("2MB"), Some(2097152));
+        assert_eq!(parse_size("1g"), None);
+    }
+}
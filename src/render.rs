@@ -1,17 +1,199 @@
 use std::io;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 
 #[cfg(test)]
 use mockall::*;
+use terminal_size::{terminal_size_of, Width};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream};
 
-use crate::sql::{NoteSummary, NoteView, SimpleNoteView, SummaryStats};
-use crate::utils::truncate_rich_text;
+use crate::backup::{BackupStatus, RestorePreview};
+use crate::bulk::BulkResult;
+use crate::checklist::OpenChecklistItem;
+use crate::content_type::ContentType;
+use crate::dates::{TimeDisplay, to_sqlite_timestamp};
+use crate::duplicates::DuplicateReport;
+use crate::grep::{GrepLine, GrepResult};
+use crate::highlight::highlight;
+use crate::markdown::{Span, write_lines};
+use crate::query::Query;
+use crate::sync::{SyncChange, SyncChangeKind, SyncReport};
+use crate::note::{Note, NoteId};
+use crate::sql::{DueNote, HistoryEntry, NoteSummary, SimpleNoteView, SummaryStats};
+use crate::utils::{display_width, escape_ics_text, fold_ics_line, make_text_single_line, truncate_rich_text};
 
-pub(crate) fn print_note_summary(printer: &dyn Printer, note: NoteSummary) {
-    let title = truncate_rich_text(&note.title, 45);
-    let text = format!("{:width$} | {} | {}", note.id, note.updated, title, width = 9);
-    printer.println(text);
-    printer.println(format!("{}+{}+{}", "-".repeat(10), "-".repeat(21), "-".repeat(47)));
+const ID_WIDTH: usize = 9;
+const DEFAULT_LIST_WIDTH: usize = 80;
+const MIN_LIST_WIDTH: usize = 50;
+const MIN_TITLE_WIDTH: usize = 20;
+
+/**
+* How the note list is laid out
+*/
+pub struct ListOptions {
+    // the title column gets whatever is left of the width after the other columns
+    pub width: usize,
+    // compact lists leave out the separator line after each note
+    pub compact: bool,
+    pub include_trashed: bool,
+    pub time: TimeDisplay,
+    // only notes updated at or after this UTC timestamp are listed
+    pub since: Option<String>,
+    // only notes that match are listed, an empty query matches every note
+    pub query: Query
+}
+
+impl ListOptions {
+    /**
+    * Fits the list to the terminal, piped output uses COLUMNS or 80 columns
+    */
+    pub fn for_stdout(compact: bool, include_trashed: bool, time: TimeDisplay, since: Option<String>, query: Query) -> ListOptions {
+        let width = terminal_size_of(io::stdout()).map(|(Width(width), _)| width as usize)
+            .or_else(|| std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()))
+            .unwrap_or(DEFAULT_LIST_WIDTH);
+
+        ListOptions { width: width.max(MIN_LIST_WIDTH), compact, include_trashed, time, since, query }
+    }
+}
+
+impl Default for ListOptions {
+    fn default() -> Self {
+        ListOptions { width: DEFAULT_LIST_WIDTH, compact: false, include_trashed: false, time: TimeDisplay::default(), since: None, query: Query::default() }
+    }
+}
+
+fn list_spec(color: Option<Color>, bold: bool, dimmed: bool) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_fg(color).set_bold(bold).set_dimmed(dimmed);
+    spec
+}
+
+/**
+* A menu row, the ID stays in the first 9 columns so that -g can read it back
+*/
+pub(crate) fn note_summary_spans(note: &NoteSummary, options: &ListOptions) -> Vec<Span> {
+    let separator = list_spec(None, false, true);
+    let updated = options.time.format(&to_sqlite_timestamp(note.updated));
+    let mut extras = vec![];
+    if note.tasks_total > 0 {
+        let color = if note.tasks_done == note.tasks_total { Some(Color::Green) } else { None };
+        extras.push(Span::new(&format!("☑ {}/{}", note.tasks_done, note.tasks_total), &list_spec(color, false, false)));
+    }
+    if let Some(due) = note.due {
+        // relative times already say when, ex: ❗ due 2 days ago
+        let due_time = options.time.format(&to_sqlite_timestamp(due)).trim().to_string();
+        extras.push(match (note.overdue, &options.time) {
+            (true, TimeDisplay::Relative) => Span::new(&format!("❗ due {}", due_time), &list_spec(Some(Color::Red), true, false)),
+            (true, _) => Span::new(&format!("❗ overdue {}", due_time), &list_spec(Some(Color::Red), true, false)),
+            (false, _) => Span::new(&format!("⏰ due {}", due_time), &list_spec(Some(Color::Yellow), false, false))
+        });
+    }
+
+    let mut icons = vec![];
+    if note.pinned {
+        icons.push(Span::new("📌 ", &list_spec(Some(Color::Cyan), false, false)));
+    }
+    if note.trashed {
+        icons.push(Span::new("🗑 ", &list_spec(Some(Color::Red), false, false)));
+    }
+    if note.protected {
+        icons.push(Span::new("🔒 ", &list_spec(Some(Color::Yellow), false, false)));
+    }
+    let mut title_spec = list_spec(None, note.pinned, note.trashed || note.protected);
+    title_spec.set_strikethrough(note.trashed);
+
+    let fixed_width = ID_WIDTH + 3 + display_width(&updated) + 3;
+    let extras_width = extras.iter().map(|extra| 3 + display_width(&extra.text)).sum::<usize>();
+    let icons_width = icons.iter().map(|icon| display_width(&icon.text)).sum::<usize>();
+    let title_width = options.width.saturating_sub(fixed_width + extras_width).max(MIN_TITLE_WIDTH);
+    let title = truncate_rich_text(&note.title, title_width.saturating_sub(icons_width));
+
+    let mut spans = vec![
+        Span::new(&format!("{:width$}", note.id, width = ID_WIDTH), &ColorSpec::new()),
+        Span::new(" | ", &separator),
+        Span::new(&updated, &list_spec(Some(Color::Blue), false, false)),
+        Span::new(" | ", &separator),
+    ];
+    spans.extend(icons);
+    if !extras.is_empty() {
+        // line the extras up in their own column
+        let padding = title_width.saturating_sub(icons_width + display_width(&title));
+        spans.push(Span::new(&format!("{}{}", title, " ".repeat(padding)), &title_spec));
+    } else {
+        spans.push(Span::new(&title, &title_spec));
+    }
+    for extra in extras {
+        spans.push(Span::new(" | ", &separator));
+        spans.push(extra);
+    }
+
+    spans
+}
+
+pub(crate) fn print_note_summary(printer: &dyn Printer, note: NoteSummary, options: &ListOptions) {
+    printer.println_styled(note_summary_spans(&note, options));
+    if !options.compact {
+        let updated_width = display_width(&options.time.format(&to_sqlite_timestamp(note.updated)));
+        let rest = options.width.saturating_sub(ID_WIDTH + updated_width + 5);
+        let line = format!("{}+{}+{}", "-".repeat(ID_WIDTH + 1), "-".repeat(updated_width + 2), "-".repeat(rest));
+        printer.println_styled(vec![Span::new(&line, &list_spec(None, false, true))]);
+    }
+}
+
+/**
+* A matching line with its line number, the matches are highlighted. Context lines use - instead of :
+*/
+pub(crate) fn grep_line_spans(line: &GrepLine, number_width: usize) -> Vec<Span> {
+    let number_spec = list_spec(Some(Color::Green), false, false);
+    let mut spans = vec![
+        Span::new(&format!("    {:>width$}", line.number, width = number_width), &number_spec),
+        Span::new(if line.is_match { ": " } else { "- " }, &list_spec(None, false, true)),
+    ];
+
+    let mut position = 0;
+    for (start, end) in &line.matches {
+        if *start > position {
+            spans.push(Span::new(&line.text[position..*start], &ColorSpec::new()));
+        }
+        spans.push(Span::new(&line.text[*start..*end], &list_spec(Some(Color::Red), true, false)));
+        position = *end;
+    }
+    if position < line.text.len() {
+        spans.push(Span::new(&line.text[position..], &ColorSpec::new()));
+    }
+
+    spans
+}
+
+/**
+* Prints the notes that matched a grep like a menu row (so that -g can read them back) followed by their lines
+*/
+pub(crate) fn print_grep_results(printer: &dyn Printer, results: Vec<GrepResult>) {
+    for (index, result) in results.iter().enumerate() {
+        if index > 0 {
+            printer.println("".to_string());
+        }
+
+        let mut header = vec![
+            Span::new(&format!("{:width$}", result.note_id, width = ID_WIDTH), &ColorSpec::new()),
+            Span::new(" | ", &list_spec(None, false, true)),
+        ];
+        if result.protected {
+            header.push(Span::new("🔒 ", &list_spec(Some(Color::Yellow), false, false)));
+        }
+        header.push(Span::new(&make_text_single_line(&result.title), &list_spec(None, true, false)));
+        printer.println_styled(header);
+
+        let number_width = result.lines.last().map(|line| line.number.to_string().len()).unwrap_or(1);
+        let mut previous = None;
+        for line in &result.lines {
+            // a gap between groups of lines, like grep
+            if previous.is_some_and(|previous| line.number > previous + 1) {
+                printer.println_styled(vec![Span::new(&format!("    {:>width$}", "--", width = number_width), &list_spec(None, false, true))]);
+            }
+            printer.println_styled(grep_line_spans(line, number_width));
+            previous = Some(line.number);
+        }
+    }
 }
 
 //#[cfg_attr(test, automock)]
@@ -19,6 +201,13 @@ pub(crate) fn print_note_summary(printer: &dyn Printer, note: NoteSummary) {
 pub trait Printer {
     fn println(&self, text: String) -> ();
     fn print_error(&self, text: String) -> ();
+
+    /**
+    * Prints one line of styled text, printers without colors print the plain text
+    */
+    fn println_styled(&self, spans: Vec<Span>) {
+        self.println(spans.into_iter().map(|span| span.text).collect())
+    }
 }
 
 pub struct TrustyPrinter {}
@@ -35,6 +224,25 @@ impl Printer for TrustyPrinter {
         let mut handle = stderr.lock();
         writeln!(&mut handle, "{}", text).unwrap();
     }
+
+    fn println_styled(&self, spans: Vec<Span>) {
+        let mut stdout = StandardStream::stdout(stdout_color_choice());
+        write_lines(&mut stdout, vec![spans]).unwrap();
+    }
+}
+
+/**
+* Colors are only used in a terminal, ColorChoice::Auto also honors NO_COLOR and TERM=dumb
+*/
+fn stdout_color_choice() -> ColorChoice {
+    if io::stdout().is_terminal() { ColorChoice::Auto } else { ColorChoice::Never }
+}
+
+// one note per line, the ID and title are separated by a tab for the shells
+pub(crate) fn print_note_completions(printer: &dyn Printer, notes: Vec<(NoteId, String)>) {
+    for (id, title) in notes {
+        printer.println(format!("{}\t{}", id, truncate_rich_text(&title.replace('\t', " "), 60)));
+    }
 }
 
 pub(crate) fn print_simple_note(printer: &dyn Printer, note: SimpleNoteView) {
@@ -44,17 +252,36 @@ pub(crate) fn print_simple_note(printer: &dyn Printer, note: SimpleNoteView) {
     printer.println(note.body);
 }
 
-pub(crate) fn print_dump(printer: &dyn Printer, notes: Vec<NoteView>) {
+/**
+* Highlights code and structured data notes, text notes are rendered as markdown when asked.
+* Piped output stays raw so scripts keep working.
+*/
+pub(crate) fn print_rich_note(note: SimpleNoteView, content_type: ContentType, markdown: bool) {
+    if !io::stdout().is_terminal() || (content_type == ContentType::Text && !markdown) {
+        return print_simple_note(&TrustyPrinter {}, note)
+    }
+    let mut stdout = StandardStream::stdout(stdout_color_choice());
+    write_lines(&mut stdout, highlight(content_type, &note.body)).unwrap();
+}
+
+pub(crate) fn print_dump(printer: &dyn Printer, notes: Vec<Note>, time: &TimeDisplay) {
     for note in notes {
-        printer.println(format!("{:width$} | {} | {} | {}", note.note_id, note.content_id, note.created, note.updated, width = 9));
+        let content_type = note.content_type.unwrap_or_else(|| ContentType::detect(&note.body));
+        printer.println(format!("{:width$} | {} | {} | {} | {}",
+                                note.id,
+                                note.content_id,
+                                time.format(&to_sqlite_timestamp(note.created)),
+                                time.format(&to_sqlite_timestamp(note.updated)),
+                                content_type.as_str(),
+                                width = 9));
         let lines = note.body.lines();
         for line in lines {
-            printer.println(format!("{:width$} | {} | {}", note.note_id, note.content_id, line, width = 9));
+            printer.println(format!("{:width$} | {} | {}", note.id, note.content_id, line, width = 9));
         }
     }
 }
 
-pub(crate) fn print_app_summary(printer: &dyn Printer, summary: SummaryStats) {
+pub(crate) fn print_app_summary(printer: &dyn Printer, summary: SummaryStats, time: &TimeDisplay) {
     let total = summary.db_stats.total;
     let trashed = summary.db_stats.trashed;
     let stale = summary.state_note_stats;
@@ -76,48 +303,381 @@ pub(crate) fn print_app_summary(printer: &dyn Printer, summary: SummaryStats) {
     printer.println(format!("{}", "Freshest Note"));
     printer.println(format!("Note ID   | Content ID                           | Updated           "));
     printer.println(format!("{:width$} | {} | {}",
-                                   fresh.note_id,
+                                   fresh.id,
                                    fresh.content_id,
-                                   fresh.updated,
+                                   time.format(&to_sqlite_timestamp(fresh.updated)),
                                    width = 9));
     printer.println(format!("Title: {}", fresh.title));
     printer.println(format!("{}", "=".repeat(80)));
     printer.println(format!("Note ID   | Content ID                           | Updated           "));
     printer.println(format!("{:width$} | {} | {}",
-                                   stale.note_id,
+                                   stale.id,
                                    stale.content_id,
-                                   stale.updated,
+                                   time.format(&to_sqlite_timestamp(stale.updated)),
                                    width = 9));
     printer.println(format!("Title: {}", stale.title));
     printer.println(format!("{}", "=".repeat(80)));
 }
 
+// due dates are listed in local time
+fn format_due(note: &DueNote) -> String {
+    TimeDisplay::default().format(&to_sqlite_timestamp(note.due))
+}
+
+pub(crate) fn print_agenda(printer: &dyn Printer, notes: Vec<DueNote>) {
+    let (overdue, upcoming): (Vec<DueNote>, Vec<DueNote>) = notes.into_iter().partition(|note| note.overdue);
+    printer.println(format!("Overdue ({})", overdue.len()));
+    printer.println("=".repeat(80));
+    for note in overdue {
+        printer.println(format!("{:width$} | {} | {}", note.note_id, format_due(&note), truncate_rich_text(&note.title, 45), width = 9));
+    }
+    printer.println(format!("Upcoming ({})", upcoming.len()));
+    printer.println("=".repeat(80));
+    for note in upcoming {
+        printer.println(format!("{:width$} | {} | {}", note.note_id, format_due(&note), truncate_rich_text(&note.title, 45), width = 9));
+    }
+}
+
+// tab separated so that cron jobs and timers can read each field, ex: notify-send "$title" "due $due"
+pub(crate) fn print_notifications(printer: &dyn Printer, notes: Vec<DueNote>) {
+    for note in notes {
+        let state = if note.overdue { "OVERDUE" } else { "UPCOMING" };
+        printer.println(format!("{}\t{}\t{}\t{}", state, note.note_id, format_due(&note), note.title));
+    }
+}
+
+fn format_ics_timestamp(utc_timestamp: &str) -> String {
+    // sqlite timestamps look like 2024-07-01 22:56:27 and are already UTC
+    format!("{}Z", utc_timestamp.replace(['-', ':'], "").replace(' ', "T"))
+}
+
+pub(crate) fn print_ics(printer: &dyn Printer, notes: Vec<DueNote>, generated_utc: &str) {
+    // calendar lines end with CRLF, println adds the LF
+    let print_line = |line: String| printer.println(format!("{}\r", fold_ics_line(&line)));
+    let stamp = format_ics_timestamp(generated_utc);
+    print_line("BEGIN:VCALENDAR".to_string());
+    print_line("VERSION:2.0".to_string());
+    print_line(format!("PRODID:-//tRusty//tru {}//EN", env!("CARGO_PKG_VERSION")));
+    for note in notes {
+        let due = format_ics_timestamp(&to_sqlite_timestamp(note.due));
+        print_line("BEGIN:VEVENT".to_string());
+        print_line(format!("UID:{}@trusty", note.content_id));
+        print_line(format!("DTSTAMP:{}", stamp));
+        print_line(format!("DTSTART:{}", due));
+        print_line(format!("DTEND:{}", due));
+        print_line(format!("SUMMARY:{}", escape_ics_text(&note.title)));
+        print_line(format!("DESCRIPTION:tru -f {}", note.note_id));
+        print_line("END:VEVENT".to_string());
+    }
+    print_line("END:VCALENDAR".to_string());
+}
+
+pub(crate) fn print_open_checklist_items(printer: &dyn Printer, items: Vec<OpenChecklistItem>) {
+    for open_item in items {
+        printer.println(format!("{:width$} | {:>4} | {:>4} | {} | {}",
+                                open_item.note_id,
+                                format!("#{}", open_item.item.index),
+                                format!("L{}", open_item.item.line),
+                                truncate_rich_text(&open_item.title, 20),
+                                open_item.item.text,
+                                width = 9));
+    }
+}
+
+pub(crate) fn print_duplicate_report(printer: &dyn Printer, report: DuplicateReport) {
+    printer.println(format!("Duplicate Notes ({})", report.exact.len()));
+    printer.println("=".repeat(80));
+    for ids in report.exact {
+        printer.println(ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(", "));
+    }
+    printer.println(format!("Near Duplicate Notes ({})", report.near.len()));
+    printer.println("=".repeat(80));
+    for near in report.near {
+        printer.println(format!("{}, {} ({:.0}% similar)", near.note_id, near.other_note_id, near.similarity * 100.0));
+    }
+}
+
+pub(crate) fn print_note_history(printer: &dyn Printer, history: Vec<HistoryEntry>) {
+    for entry in history {
+        printer.println(format!("{} | {} | created {} | updated {} | {}",
+                                entry.recorded,
+                                entry.event,
+                                entry.created,
+                                entry.updated,
+                                truncate_rich_text(&entry.title, 45)));
+    }
+}
+
+pub(crate) fn print_bulk_report(printer: &dyn Printer, results: Vec<BulkResult>) {
+    let failed = results.iter().filter(|result| !result.ok).count();
+    let succeeded = results.len() - failed;
+    for result in results {
+        let status = if result.ok { "✅" } else { "❌" };
+        printer.println(format!("{:width$} | {} | {}", result.note_id, status, result.message, width = 9));
+    }
+    printer.println(format!("{} succeeded, {} failed", succeeded, failed));
+}
+
+pub(crate) fn print_backup_status(printer: &dyn Printer, backup: &BackupStatus) {
+    let manifest = &backup.manifest;
+    let integrity = if backup.integrity.eq("ok") { "✅ ok".to_string() } else { format!("❌ {}", backup.integrity) };
+    let recovery = if backup.recovery_matches { "🛟 current recovery code" } else { "⚠️ different recovery code" };
+    printer.println(format!("{} | {} | {} notes | {} | {}", manifest.file_name, manifest.created, manifest.note_count, integrity, recovery));
+}
+
+pub(crate) fn print_backups(printer: &dyn Printer, backups: &[BackupStatus]) {
+    if backups.is_empty() {
+        printer.println("No backups found.".to_string());
+    }
+    for backup in backups {
+        print_backup_status(printer, backup);
+    }
+}
+
+pub(crate) fn print_restore_preview(printer: &dyn Printer, preview: &RestorePreview) {
+    let manifest = &preview.backup.manifest;
+    printer.println(format!("Restoring: {}", preview.backup.path.display()));
+    printer.println(format!("Backup created: {}, {} notes, last updated {}", manifest.created, manifest.note_count, manifest.last_updated));
+    printer.println(format!("This replaces: {} notes, last updated {}", preview.current_note_count, preview.current_last_updated));
+    if !preview.backup.recovery_matches {
+        printer.println("⚠️ This backup was made with a different recovery code, you will need that code to reset the password for its protected notes.".to_string());
+    }
+    if !preview.backup.integrity.eq("ok") {
+        printer.print_error(format!("The backup failed its integrity check: {}", preview.backup.integrity));
+    }
+}
+
+fn print_sync_changes(printer: &dyn Printer, heading: &str, changes: &[SyncChange]) {
+    printer.println(format!("{}: {} changes", heading, changes.len()));
+    for change in changes {
+        let kind = match change.kind {
+            SyncChangeKind::Added => "added",
+            SyncChangeKind::Updated => "updated",
+            SyncChangeKind::Trashed => "trashed",
+            SyncChangeKind::Restored => "restored",
+            SyncChangeKind::Deleted => "deleted",
+            SyncChangeKind::ConflictCopy => "conflict"
+        };
+        printer.println(format!("{:>9} | {}", kind, truncate_rich_text(&change.title, 60)));
+    }
+}
+
+/**
+* @other - names the other side of the sync, ex: "Other database"
+*/
+pub(crate) fn print_sync_report(printer: &dyn Printer, report: &SyncReport, other: &str) {
+    match &report.last_sync {
+        Some(last_sync) => printer.println(format!("Last synced: {}", last_sync)),
+        None => printer.println("First sync with this database.".to_string())
+    }
+    print_sync_changes(printer, "This vault", &report.local);
+    print_sync_changes(printer, other, &report.remote);
+    if report.skipped_protected > 0 {
+        printer.println(format!("Skipped {} protected notes, {} does not have the same password (boss key) as this vault. \
+        Unlock both once if either was made with an older tRusty.", report.skipped_protected, other.to_lowercase()));
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::render::{MockPrinter, print_app_summary, print_dump, print_note_summary, print_simple_note};
-    use crate::sql::{DBStats, LargeNoteSummary, NoteSummary, NoteView, SimpleNoteView, SummaryStats};
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    use chrono::{TimeZone, Utc};
+    use regex::Regex;
+    use termcolor::Color;
+
+    use crate::backup::{BackupManifest, BackupStatus, RestorePreview};
+    use crate::bulk::BulkResult;
+    use crate::checklist::{ChecklistItem, OpenChecklistItem};
+    use crate::content_type::ContentType;
+    use crate::duplicates::{DuplicateReport, NearDuplicate};
+    use crate::dates::TimeDisplay;
+    use crate::grep::{grep_text, GrepResult};
+    use crate::markdown::Span;
+    use crate::render::{format_ics_timestamp, grep_line_spans, ListOptions, MockPrinter, note_summary_spans, print_agenda, print_app_summary, print_backups, print_bulk_report, print_dump, print_duplicate_report, print_grep_results, print_ics, print_note_completions, print_note_summary, print_notifications, print_open_checklist_items, print_restore_preview, print_simple_note, print_sync_report};
+    use crate::sync::{SyncChange, SyncChangeKind, SyncReport};
+    use crate::note::{ContentId, Note, NoteId};
+    use crate::sql::{DBStats, DueNote, LargeNoteSummary, NoteSummary, SimpleNoteView, SummaryStats};
+    use crate::utils::{display_width, parse_menu_lines};
+
+    fn make_note(note_id: usize, content_id: &str, body: &str, content_type: Option<ContentType>) -> Note {
+        Note {
+            id: NoteId(note_id),
+            content_id: ContentId::from(content_id),
+            title: "".to_string(),
+            body: body.to_string(),
+            protected: false,
+            trashed: false,
+            created: Utc.with_ymd_and_hms(2024, 7, 1, 10, 0, 0).unwrap(),
+            updated: Utc.with_ymd_and_hms(2024, 7, 2, 10, 0, 0).unwrap(),
+            due: None,
+            content_type,
+            data_key: None,
+        }
+    }
+
+    fn make_note_summary() -> NoteSummary {
+        NoteSummary{
+            id: NoteId(1),
+            title: "Get Started with tRusty".to_string(),
+            updated: Utc.with_ymd_and_hms(2024, 7, 1, 22, 56, 27).unwrap(),
+            due: Some(Utc.with_ymd_and_hms(2024, 7, 2, 9, 0, 0).unwrap()),
+            overdue: false,
+            tasks_done: 1,
+            tasks_total: 3,
+            protected: false,
+            trashed: false,
+            pinned: false,
+        }
+    }
+
+    fn utc_list_options(width: usize, compact: bool) -> ListOptions {
+        ListOptions { width, compact, time: TimeDisplay::Utc, ..ListOptions::default() }
+    }
+
+    fn spans_text(spans: &[Span]) -> String {
+        spans.iter().map(|span| span.text.to_string()).collect()
+    }
 
     #[test]
     fn test_print_note_summary() {
-        let test_note_summary = NoteSummary{
-            id: 1,
-            title: "Get Started with tRusty".to_string(),
-            updated: "2024-07-01 22:56:27".to_string(),
+        let mut mock = MockPrinter::new();
+        mock.expect_println_styled().withf(|spans| spans_text(spans).starts_with("        1 | 2024-07-01 22:56:27 | Get Started")).times(1).return_const(());
+        mock.expect_println_styled().withf(|spans| spans_text(spans) == format!("{}+{}+{}", "-".repeat(10), "-".repeat(21), "-".repeat(47))).times(1).return_const(());
+        mock.expect_println().times(0).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+
+        print_note_summary(&mock, make_note_summary(), &utc_list_options(80, false));
+
+        let mut compact = MockPrinter::new();
+        compact.expect_println_styled().times(1).return_const(());
+        print_note_summary(&compact, make_note_summary(), &utc_list_options(120, true));
+    }
+
+    #[test]
+    fn test_print_grep_results() {
+        let regex = Regex::new("deploy").unwrap();
+        let results = vec![
+            GrepResult { note_id: 3, title: "Deploy\nnotes".to_string(), protected: false, lines: grep_text(&regex, "deploy\na\nb\nc\nredeploy", 1) },
+            GrepResult { note_id: 12, title: "Secret".to_string(), protected: true, lines: grep_text(&regex, "deploy keys", 0) },
+        ];
+        let lines = Arc::new(Mutex::new(vec![]));
+        let printed = lines.clone();
+        let mut mock = MockPrinter::new();
+        mock.expect_println_styled().returning(move |spans| printed.lock().unwrap().push(spans_text(&spans)));
+        mock.expect_println().withf(|text| text.is_empty()).times(1).return_const(());
+
+        print_grep_results(&mock, results);
+        assert_eq!(*lines.lock().unwrap(), vec!["        3 | Deploy notes", "    1: deploy", "    2- a", "    --", "    4- c", "    5: redeploy",
+                                               "       12 | 🔒 Secret", "    1: deploy keys"]);
+
+        // -g reads the notes back and skips their lines
+        assert_eq!(parse_menu_lines(&lines.lock().unwrap().join("\n")), Ok(vec![3, 12]));
+
+        let spans = grep_line_spans(&grep_text(&regex, "redeploy now", 0)[0], 2);
+        assert_eq!(spans.iter().map(|span| span.text.as_str()).collect::<Vec<&str>>(), vec!["     1", ": ", "re", "deploy", " now"]);
+        assert_eq!(spans[3].spec.fg(), Some(&Color::Red));
+    }
+
+    #[test]
+    fn test_note_summary_spans() {
+        let spans = note_summary_spans(&make_note_summary(), &utc_list_options(120, false));
+        let text = spans_text(&spans);
+        assert_eq!(display_width(&text), 120);
+        assert!(text.contains("| Get Started with tRusty "));
+        assert!(text.ends_with(" | ☑ 1/3 | ⏰ due 2024-07-02 09:00:00"));
+        assert_eq!(spans.last().unwrap().spec.fg(), Some(&Color::Yellow));
+
+        // the title gets the rest of the terminal
+        let mut note = make_note_summary();
+        note.title = "a".repeat(100);
+        note.due = None;
+        assert_eq!(display_width(&spans_text(&note_summary_spans(&note, &utc_list_options(80, false)))), 80);
+
+        let mut note = make_note_summary();
+        note.title = "ENCRYPTED".to_string();
+        note.protected = true;
+        note.pinned = true;
+        note.trashed = true;
+        note.due = None;
+        note.tasks_total = 0;
+        let spans = note_summary_spans(&note, &utc_list_options(80, false));
+        assert_eq!(spans_text(&spans), "        1 | 2024-07-01 22:56:27 | 📌 🗑 🔒 ENCRYPTED");
+        let title = spans.last().unwrap();
+        assert!(title.spec.bold());
+        assert!(title.spec.strikethrough());
+
+        let mut note = make_note_summary();
+        note.due = Some(Utc.with_ymd_and_hms(2020, 1, 1, 9, 0, 0).unwrap());
+        note.overdue = true;
+        let options = ListOptions { time: TimeDisplay::Relative, ..utc_list_options(120, false) };
+        let text = spans_text(&note_summary_spans(&note, &options));
+        assert!(text.contains(" years ago | Get Started"));
+        assert!(text.contains("| ❗ due ") && text.ends_with(" years ago"));
+    }
+
+    #[test]
+    fn test_print_open_checklist_items() {
+        let items = vec![OpenChecklistItem {
+            note_id: NoteId(12),
+            title: "Release".to_string(),
+            item: ChecklistItem { index: 2, line: 7, checked: false, text: "tag release".to_string() },
+        }];
+
+        let mut mock = MockPrinter::new();
+        mock.expect_println().withf(|text| text == "       12 |   #2 |   L7 | Release | tag release").times(1).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+        print_open_checklist_items(&mock, items);
+    }
+
+    #[test]
+    fn test_print_duplicate_report() {
+        let report = DuplicateReport {
+            exact: vec![vec![NoteId(1), NoteId(3), NoteId(4)]],
+            near: vec![NearDuplicate { note_id: NoteId(2), other_note_id: NoteId(5), similarity: 0.875 }],
         };
 
         let mut mock = MockPrinter::new();
-        mock.expect_println().times(2).return_const(());
+        mock.expect_println().withf(|text| text == "1, 3, 4").times(1).return_const(());
+        mock.expect_println().withf(|text| text == "2, 5 (88% similar)").times(1).return_const(());
+        mock.expect_println().times(4).return_const(());
         mock.expect_print_error().times(0).return_const(());
+        print_duplicate_report(&mock, report);
+    }
 
-        print_note_summary(&mock, test_note_summary);
+    #[test]
+    fn test_print_bulk_report() {
+        let results = vec![
+            BulkResult { note_id: 3, ok: true, message: "trashed".to_string() },
+            BulkResult { note_id: 4, ok: false, message: "not found".to_string() },
+        ];
+
+        let mut mock = MockPrinter::new();
+        mock.expect_println().withf(|text| text == "        4 | ❌ | not found").times(1).return_const(());
+        mock.expect_println().withf(|text| text == "1 succeeded, 1 failed").times(1).return_const(());
+        mock.expect_println().times(1).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+        print_bulk_report(&mock, results);
     }
-    
+
+    #[test]
+    fn test_print_note_completions() {
+        let mut mock = MockPrinter::new();
+        mock.expect_println().withf(|text| text == "2\tGroceries and things").times(1).return_const(());
+        mock.expect_println().withf(|text| text == "3\t🔒").times(1).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+
+        print_note_completions(&mock, vec![(NoteId(2), "Groceries\tand things".to_string()), (NoteId(3), "🔒".to_string())]);
+    }
+
     #[test]
     fn test_print_simple_note() {
         let test_note = SimpleNoteView{
+            note_id: NoteId(1),
             title: "".to_string(),
             body: "".to_string(),
-            content_id: "".to_string(),
+            content_id: ContentId::from(""),
             protected: false,
         };
 
@@ -129,27 +689,25 @@ mod tests {
 
     #[test]
     fn test_print_dump() {
-        let mock_data = vec![NoteView{
-            title: "foo".to_string(),
-            body: "foofoo".to_string(),
-            note_id: 0,
-            content_id: "".to_string(),
-            updated: "".to_string(),
-            created: "".to_string(),
-        }, NoteView{
-            title: "bar".to_string(),
-            body: "bar\r\nbar".to_string(),
-            note_id: 0,
-            content_id: "".to_string(),
-            updated: "".to_string(),
-            created: "".to_string(),
-        }];
+        let mock_data = vec![make_note(0, "", "foofoo", None), make_note(0, "", "bar\r\nbar", None)];
 
         let mut mock = MockPrinter::new();
         mock.expect_println().times(5).return_const(());
         mock.expect_print_error().times(0).return_const(());
 
-        print_dump(&mock, mock_data);
+        print_dump(&mock, mock_data, &TimeDisplay::Utc);
+    }
+
+    #[test]
+    fn test_print_dump_content_type() {
+        let mock_data = vec![make_note(3, "abc", "{\"foo\": 1}", None), make_note(4, "def", "bar", Some(ContentType::Sh))];
+
+        let mut mock = MockPrinter::new();
+        mock.expect_println().withf(|text| text == "        3 | abc | 2024-07-01 10:00:00 | 2024-07-02 10:00:00 | json").times(1).return_const(());
+        mock.expect_println().withf(|text| text == "        4 | def | 2024-07-01 10:00:00 | 2024-07-02 10:00:00 | sh").times(1).return_const(());
+        mock.expect_println().withf(|text| !text.contains("2024")).times(2).return_const(());
+
+        print_dump(&mock, mock_data, &TimeDisplay::Utc);
     }
 
     #[test]
@@ -157,33 +715,140 @@ mod tests {
         let mock_data = SummaryStats{
             db_stats: DBStats { total: 0, trashed: 0 },
             large_note_stats: LargeNoteSummary {
-                note_id: 0,
+                note_id: NoteId(0),
                 title: "".to_string(),
-                content_id: "".to_string(),
+                content_id: ContentId::from(""),
                 content_size: 0,
             },
-            state_note_stats: NoteView {
-                title: "".to_string(),
-                body: "".to_string(),
-                note_id: 0,
-                content_id: "".to_string(),
-                updated: "".to_string(),
-                created: "".to_string(),
-            },
-            fresh_note_stats: NoteView {
-                title: "".to_string(),
-                body: "".to_string(),
-                note_id: 0,
-                content_id: "".to_string(),
-                updated: "".to_string(),
-                created: "".to_string(),
-            },
+            state_note_stats: make_note(0, "", "", None),
+            fresh_note_stats: make_note(0, "", "", None),
         };
 
         let mut mock = MockPrinter::new();
         mock.expect_println().times(19).return_const(());
         mock.expect_print_error().times(0).return_const(());
 
-        print_app_summary(&mock, mock_data);
+        print_app_summary(&mock, mock_data, &TimeDisplay::Utc);
+    }
+
+    fn get_due_notes() -> Vec<DueNote> {
+        vec![DueNote {
+            note_id: NoteId(1),
+            title: "Pay rent".to_string(),
+            content_id: ContentId::from("abc"),
+            due: Utc.with_ymd_and_hms(2024, 7, 1, 13, 0, 0).unwrap(),
+            overdue: true,
+        }, DueNote {
+            note_id: NoteId(2),
+            title: "Renew certs, again".to_string(),
+            content_id: ContentId::from("def"),
+            due: Utc.with_ymd_and_hms(2024, 8, 1, 13, 0, 0).unwrap(),
+            overdue: false,
+        }]
+    }
+
+    #[test]
+    fn test_print_agenda() {
+        let mut mock = MockPrinter::new();
+        mock.expect_println().times(6).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+
+        print_agenda(&mock, get_due_notes());
+    }
+
+    #[test]
+    fn test_print_notifications() {
+        let mut mock = MockPrinter::new();
+        mock.expect_println().withf(|text| text.starts_with("OVERDUE\t1\t")).times(1).return_const(());
+        mock.expect_println().withf(|text| text.starts_with("UPCOMING\t2\t")).times(1).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+
+        print_notifications(&mock, get_due_notes());
+    }
+
+    #[test]
+    fn test_format_ics_timestamp() {
+        assert_eq!(format_ics_timestamp("2024-07-01 22:56:27"), "20240701T225627Z");
+    }
+
+    #[test]
+    fn test_print_ics() {
+        let mut mock = MockPrinter::new();
+        mock.expect_println().withf(|text| text == "SUMMARY:Renew certs\\, again\r").times(1).return_const(());
+        // due dates are exported as UTC
+        mock.expect_println().withf(|text| text == "DTSTART:20240701T130000Z\r").times(1).return_const(());
+        mock.expect_println().withf(|text| text.ends_with('\r')).times(18).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+
+        print_ics(&mock, get_due_notes(), "2024-07-01 22:56:27");
+    }
+
+    fn make_backup_status(integrity: &str, recovery_matches: bool) -> BackupStatus {
+        BackupStatus {
+            manifest: BackupManifest {
+                file_name: "trusty-20240701-225627123.db".to_string(),
+                created: "2024-07-01 22:56:27".to_string(),
+                recovery_fingerprint: "0123456789abcdef".to_string(),
+                note_count: 12,
+                last_updated: "2024-07-01 20:00:00".to_string(),
+                trusty_version: "1.0.5".to_string(),
+            },
+            path: PathBuf::from("/tmp/trusty-20240701-225627123.db"),
+            integrity: integrity.to_string(),
+            recovery_matches,
+        }
+    }
+
+    #[test]
+    fn test_print_backups() {
+        let mut mock = MockPrinter::new();
+        mock.expect_println().withf(|text| text.contains("12 notes | ✅ ok | 🛟")).times(1).return_const(());
+        mock.expect_println().withf(|text| text.contains("❌ database disk image is malformed | ⚠️")).times(1).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+
+        print_backups(&mock, &[make_backup_status("ok", true), make_backup_status("database disk image is malformed", false)]);
+
+        let mut mock_2 = MockPrinter::new();
+        mock_2.expect_println().withf(|text| text.eq("No backups found.")).times(1).return_const(());
+        print_backups(&mock_2, &[]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_print_restore_preview() {
+        let preview = RestorePreview {
+            backup: make_backup_status("ok", false),
+            current_note_count: 20,
+            current_last_updated: "2024-07-05 10:00:00".to_string(),
+        };
+
+        let mut mock = MockPrinter::new();
+        mock.expect_println().times(4).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+
+        print_restore_preview(&mock, &preview);
+    }
+
+    #[test]
+    fn test_print_sync_report() {
+        let report = SyncReport {
+            local: vec![SyncChange { content_id: "a".to_string(), title: "foo".to_string(), kind: SyncChangeKind::Added }],
+            remote: vec![
+                SyncChange { content_id: "b".to_string(), title: "bar".to_string(), kind: SyncChangeKind::Deleted },
+                SyncChange { content_id: "c".to_string(), title: "baz (conflict copy)".to_string(), kind: SyncChangeKind::ConflictCopy },
+            ],
+            last_sync: None,
+            skipped_protected: 2,
+        };
+
+        let mut mock = MockPrinter::new();
+        mock.expect_println().withf(|text| text.eq("First sync with this database.")).times(1).return_const(());
+        mock.expect_println().withf(|text| text.starts_with("Skipped 2 protected notes, other database does not have the same password")).times(1).return_const(());
+        mock.expect_println().withf(|text| text.eq("This vault: 1 changes")).times(1).return_const(());
+        mock.expect_println().withf(|text| text.eq("    added | foo")).times(1).return_const(());
+        mock.expect_println().withf(|text| text.eq("Other database: 2 changes")).times(1).return_const(());
+        mock.expect_println().withf(|text| text.starts_with("  deleted") || text.starts_with(" conflict")).times(2).return_const(());
+        mock.expect_print_error().times(0).return_const(());
+
+        print_sync_report(&mock, &report, "Other database");
+    }
+}
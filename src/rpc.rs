@@ -0,0 +1,94 @@
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::api::{ApiError, ApiResult, ApiSession};
+use crate::store::NoteStore;
+
+// error codes from the JSON-RPC 2.0 spec, errors from the notes API use their HTTP status as the code
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+fn rpc_error(id: &Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn get_id_param(params: &Value) -> Result<usize, ApiError> {
+    params["id"].as_u64().map(|id| id as usize).ok_or(ApiError::bad_request("An id param is required."))
+}
+
+fn get_str_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, ApiError> {
+    params[name].as_str().ok_or(ApiError::bad_request(&format!("A {} param is required.", name)))
+}
+
+/**
+* Returns None for an unknown method so it can be reported as method not found
+*/
+fn call_method(session: &mut ApiSession, method: &str, params: &Value) -> Option<ApiResult> {
+    let result = match method {
+        "list" => session.list(params["trashed"].as_bool().unwrap_or(false)),
+        "get" => get_id_param(params).and_then(|id| session.get(id)),
+        "create" => get_str_param(params, "body")
+            .and_then(|body| session.create(params["title"].as_str(), body, params["protected"].as_bool().unwrap_or(false))),
+        "update" => get_id_param(params).and_then(|id| session.update(id, params["title"].as_str(), params["body"].as_str())),
+        "trash" => get_id_param(params).and_then(|id| session.set_trashed(id, true)),
+        "restore" => get_id_param(params).and_then(|id| session.set_trashed(id, false)),
+        "search" => get_str_param(params, "text").and_then(|text| session.search(text)),
+        "unlock" => get_str_param(params, "password").and_then(|password| session.unlock(password)),
+        "lock" => session.lock(),
+        _ => return None
+    };
+
+    Some(result)
+}
+
+/**
+* Answers one line of JSON-RPC 2.0, notifications (requests without an id) get no response
+*/
+pub fn handle_rpc_line(session: &mut ApiSession, line: &str) -> Option<Value> {
+    let request = match serde_json::from_str::<Value>(line) {
+        Ok(request) => request,
+        Err(err) => return Some(rpc_error(&Value::Null, PARSE_ERROR, &format!("Parse error: {}", err)))
+    };
+    let id = request.get("id").cloned();
+    let reply_id = id.clone().unwrap_or(Value::Null);
+    let method = match request["method"].as_str() {
+        Some(method) if request["jsonrpc"] == "2.0" => method,
+        _ => return Some(rpc_error(&reply_id, INVALID_REQUEST, "Invalid request, ex: {\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"list\"}"))
+    };
+    let params = match request.get("params") {
+        None => json!({}),
+        Some(params) if params.is_object() => params.clone(),
+        Some(_) => return Some(rpc_error(&reply_id, INVALID_PARAMS, "Params must be an object."))
+    };
+
+    let response = match call_method(session, method, &params) {
+        Some(Ok(result)) => json!({"jsonrpc": "2.0", "id": reply_id, "result": result}),
+        Some(Err(err)) if err.status == 400 => rpc_error(&reply_id, INVALID_PARAMS, &err.message),
+        Some(Err(err)) => rpc_error(&reply_id, err.status as i64, &err.message),
+        None => rpc_error(&reply_id, METHOD_NOT_FOUND, &format!("Method not found: {}", method))
+    };
+
+    id.map(|_| response)
+}
+
+/**
+* Reads one request per line until the input is closed, each response is written on one line
+*/
+pub fn run_rpc(store: &dyn NoteStore, input: impl BufRead, mut output: impl Write) -> std::io::Result<()> {
+    let mut session = ApiSession::new(store);
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue
+        }
+        if let Some(response) = handle_rpc_line(&mut session, &line) {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+
+    Ok(())
+}
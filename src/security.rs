@@ -1,110 +1,129 @@
 use std::process::exit;
 use magic_crypt::{MagicCryptTrait, new_magic_crypt};
-use regex::Regex;
 use uuid::Uuid;
 use crate::render::{TrustyPrinter, Printer};
 use crate::errors::Errors;
 use crate::setup::{TrustyPathOperations, PathOperations};
-use crate::sql::{add_key_value, get_note_by_id, get_value_from_attr_table, NoteView, SimpleNoteView, update_key_value, update_note_by_note_id, update_protected_flag, update_title_by_content_id};
+use crate::note::{Note, NoteId};
+use crate::password::{normalize_password, PasswordPolicy};
+use crate::store::NoteStore;
+use crate::sql::{get_app_value, get_value_from_attr_table, save_key_values};
+use crate::utils::hash_text;
 
 /**
 * @compare_password - will compare what the user typed against the password saved in the database
 * @confirm_password - will ask for the password 2x to make sure you typed the same one
 * @fun - is passed the plain-text password as a parameter and the password should NEVER be used/seen outside the closure!!!!
+* A new password that fun rejects is "not accepted" rather than "incorrect", fun prints the reason
 */
-pub(crate) fn prompt_for_password<F>(mut fun: F, compare_password_to_db: bool, confirm_password: bool) -> bool where F: FnMut(&str) -> bool {
+pub(crate) fn prompt_for_password<F>(store: &dyn NoteStore, mut fun: F, compare_password_to_db: bool, confirm_password: bool) -> bool where F: FnMut(&str) -> bool {
+    let rejected = if compare_password_to_db { "Password incorrect" } else { "Password not accepted" };
     let mut attempts = 0;
     while attempts < 2  {
-        let password = rpassword::prompt_password("Enter password: ").unwrap();
+        let password = normalize_password(&rpassword::prompt_password("Enter password: ").unwrap());
         let password2 = if confirm_password {
-            rpassword::prompt_password("Enter your password again: ").unwrap()
+            normalize_password(&rpassword::prompt_password("Enter your password again: ").unwrap())
         } else {
             password.clone()
         };
 
         if password.eq(&password2) && validate_password(&password) {
-            if compare_password_to_db  {
-                if check_password(&password) {
-                    if fun(&password) {
-                        return true
-                    }
-                }
-            } else {
-                if fun(&password) {
-                    return true
-                }
+            let password_matches = !compare_password_to_db || check_password(store, &password);
+            if password_matches && fun(&password) {
+                return true
             }
         }
 
-        TrustyPrinter {}.println("Password incorrect, try again.".to_string());
+        TrustyPrinter {}.println(format!("{}, try again.", rejected));
         attempts += 1;
     }
 
-    TrustyPrinter {}.println("Password incorrect.".to_string());
+    TrustyPrinter {}.println(format!("{}.", rejected));
     false
 }
 
-pub(crate) fn decrypt_note(title: &str, note: &str) -> SimpleNoteView {
-    let mut unencrypted_title = "".to_string();
-    let mut unencrypted_note = "".to_string();
+/**
+* Returns the decrypted title and body
+*/
+pub(crate) fn decrypt_note(store: &dyn NoteStore, note: &Note) -> (String, String) {
+    decrypt_notes(store, std::slice::from_ref(note)).pop().unwrap_or_default()
+}
 
-    let handle_decrypt = | password: &str| -> bool {
-        let decrypted_boss_key = get_boss_key(password);
-        unencrypted_title = decrypt_text(&decrypted_boss_key, title);
-        unencrypted_note = decrypt_text(&decrypted_boss_key, note);
+/**
+* Returns the decrypted title and body of each note, the password is asked for once for all of them.
+* Notes that could not be decrypted have an empty title and body.
+*/
+pub(crate) fn decrypt_notes(store: &dyn NoteStore, notes: &[Note]) -> Vec<(String, String)> {
+    let mut decrypted = vec![];
 
-        return true
+    let handle_decrypt = | password: &str| -> bool {
+        let decrypted_boss_key = get_boss_key(store, password);
+        decrypted.clear();
+        for note in notes {
+            match unseal_note(&decrypted_boss_key, note.data_key.as_deref(), &note.title, &note.body) {
+                Some(title_and_body) => decrypted.push(title_and_body),
+                None => return false
+            }
+        }
+        true
     };
 
-    prompt_for_password(handle_decrypt, true, false);
+    prompt_for_password(store, handle_decrypt, true, false);
+    decrypted.resize(notes.len(), Default::default());
 
-    return SimpleNoteView {
-        title: unencrypted_title,
-        body: unencrypted_note,
-        content_id: "0".to_string(),
-        protected: true
-    }
+    decrypted
 }
 
-pub(crate) fn encrypt_note(title: &str, note: &str) -> SimpleNoteView {
+/**
+* Returns the title and body encrypted with the data key of a protected note
+*/
+pub(crate) fn encrypt_note(store: &dyn NoteStore, note_id: NoteId, title: &str, note: &str) -> (String, String) {
     let mut encrypted_title = "".to_string();
     let mut encrypted_body = "".to_string();
+    let wrapped_key = store.get_note(note_id).and_then(|note| note.data_key);
 
     let handle_encrypt = |password: &str| -> bool {
-        let decrypted_boss_key = get_boss_key(password);
-        encrypted_title = encrypt_text(&decrypted_boss_key, title);
-        encrypted_body = encrypt_text(&decrypted_boss_key, note);
-
-        return true
+        let decrypted_boss_key = get_boss_key(store, password);
+        match unwrap_data_key(&decrypted_boss_key, wrapped_key.as_deref()) {
+            Some(data_key) => {
+                encrypted_title = encrypt_text(&data_key, title);
+                encrypted_body = encrypt_text(&data_key, note);
+                true
+            }
+            None => false
+        }
     };
 
-    prompt_for_password(handle_encrypt, true, false);
+    prompt_for_password(store, handle_encrypt, true, false);
 
-    return SimpleNoteView {
-        title: encrypted_title,
-        body: encrypted_body,
-        content_id: "0".to_string(),
-        protected: true
-    }
+    (encrypted_title, encrypted_body)
 }
 
+/**
+* Any passphrase that is not blank can unlock notes, new passwords are also checked against the PasswordPolicy
+*/
 pub(crate) fn validate_password(password: &str) -> bool {
-    // password may not be blank, may be alphanumeric and at least 4 characters
-    if password.trim().is_empty() {
-        return false
-    }
-
-    let re = Regex::new(r"^[A-Za-z0-9]+$").unwrap();
-
-    if !re.is_match(password) {
-        return false
-    }
+    !password.trim().is_empty()
+}
 
-    if password.len() < 3 {
-        return false
+/**
+* Prints how strong a new password is with tips to make it stronger, returns false when it does not meet the policy
+*/
+pub(crate) fn report_password_strength(store: &dyn NoteStore, password: &str) -> bool {
+    let printer = TrustyPrinter {};
+    match PasswordPolicy::from_store(store).check(password) {
+        Ok(estimate) => {
+            printer.println(format!("Password strength: {}", estimate.strength.label()));
+            for feedback in estimate.feedback {
+                printer.println(format!("  {}", feedback));
+            }
+            true
+        }
+        Err(err) => {
+            printer.print_error(err);
+            false
+        }
     }
-
-    true
 }
 
 pub(crate) fn encrypt_text(key: &str, text: &str) -> String {
@@ -117,151 +136,295 @@ pub(crate) fn decrypt_text(key: &str, text: &str) -> String {
     mc.decrypt_base64_to_string(text).unwrap()
 }
 
-pub(crate) fn check_password(password: &str) -> bool {
-    let saved_encrypted_password = get_value_from_attr_table(&TrustyPathOperations {}, "app", "password");
+/**
+* Like decrypt_text but returns None for a wrong key or damaged text instead of panicking
+*/
+pub(crate) fn try_decrypt_text(key: &str, text: &str) -> Option<String> {
+    let mc = new_magic_crypt!(key, 256);
+    mc.decrypt_base64_to_string(text).ok()
+}
+
+/**
+* A random key for a single protected note, it is only saved wrapped (encrypted) with the boss key
+*/
+pub(crate) fn generate_data_key() -> String {
+    Uuid::new_v4().to_string()
+}
+
+pub(crate) fn wrap_data_key(boss_key: &str, data_key: &str) -> String {
+    encrypt_text(boss_key, data_key)
+}
+
+/**
+* Returns the key a protected note is encrypted with, notes protected before notes had their own keys use the boss key
+*/
+pub(crate) fn unwrap_data_key(boss_key: &str, wrapped_key: Option<&str>) -> Option<String> {
+    match wrapped_key {
+        Some(wrapped_key) => try_decrypt_text(boss_key, wrapped_key),
+        None => Some(boss_key.to_string())
+    }
+}
+
+/**
+* Encrypts a title and body with a new data key, returns them with the wrapped data key
+*/
+pub(crate) fn seal_note(boss_key: &str, title: &str, body: &str) -> (String, String, String) {
+    let data_key = generate_data_key();
+
+    (encrypt_text(&data_key, title), encrypt_text(&data_key, body), wrap_data_key(boss_key, &data_key))
+}
+
+/**
+* Decrypts the title and body of a protected note, returns None for a wrong key or a damaged note
+*/
+pub(crate) fn unseal_note(boss_key: &str, wrapped_key: Option<&str>, title: &str, body: &str) -> Option<(String, String)> {
+    let data_key = unwrap_data_key(boss_key, wrapped_key)?;
+
+    Some((try_decrypt_text(&data_key, title)?, try_decrypt_text(&data_key, body)?))
+}
+
+pub(crate) fn check_password(store: &dyn NoteStore, password: &str) -> bool {
+    let password = &normalize_password(password);
+    let saved_encrypted_password = get_value_from_attr_table(store, "app", "password");
     let encrypted_password = encrypt_text(password, password);
     encrypted_password.eq(&saved_encrypted_password.value)
 }
 
-pub(crate) fn recovery_reset_password(recovery_code: &str) {
-    let saved_code = get_value_from_attr_table(&TrustyPathOperations {}, "app", "recovery_code");
+pub(crate) fn recovery_reset_password(store: &dyn NoteStore, recovery_code: &str) {
+    let saved_code = get_value_from_attr_table(store, "app", "recovery_code");
     let encrypted_code = encrypt_text(recovery_code, recovery_code);
     let rec_code = Some(recovery_code.to_string());
     if saved_code.value.eq(&encrypted_code) {
-         set_password(true, rec_code)
+         set_password(store, true, rec_code)
     } else {
         TrustyPrinter {}.println("Invalid recovery key provided.".to_string());
     }
 }
 
 
-pub(crate) fn unprotect_note(note_id: usize) {
-    let note = get_note_by_id(&TrustyPathOperations {}, note_id);
-
-    if note.protected {
-        update_title_by_content_id(&TrustyPathOperations {}, &note.content_id, &note.title);
-        update_note_by_note_id(&TrustyPathOperations {}, note_id, &note.body);
-        update_protected_flag(&TrustyPathOperations {}, note_id, false);
-    } else {
-        TrustyPrinter {}.println(format!("Note: {} is not encrypted.", note_id));
-        exit(0);
-    }
-}
-
-pub(crate) fn get_boss_key(password: &str) -> String {
-    let boss_key = get_value_from_attr_table(&TrustyPathOperations {}, "app", "boss_key");
+pub(crate) fn get_boss_key(store: &dyn NoteStore, password: &str) -> String {
+    let password = &normalize_password(password);
+    let boss_key = get_value_from_attr_table(store, "app", "boss_key");
     let decrypted_boss_key = decrypt_text(password, &boss_key.value);
+    remember_boss_key_fingerprint(store, &decrypted_boss_key);
 
     decrypted_boss_key.to_string()
 }
 
-pub(crate) fn protect_note(note_id: usize) {
-    let note = get_note_by_id(&TrustyPathOperations {}, note_id);
-
-    if note.protected {
-        TrustyPrinter {}.println(format!("Note: {} is already encrypted", note_id))
-    } else {
-        let encrypted_note = encrypt_note(&note.title, &note.body);
-        update_title_by_content_id(&TrustyPathOperations {}, &note.content_id, &encrypted_note.title);
-        update_note_by_note_id(&TrustyPathOperations {}, note_id, &encrypted_note.body);
-        update_protected_flag(&TrustyPathOperations {}, note_id, true);
+/**
+* Identifies a boss key without revealing it, vaults with the same fingerprint can open each other's protected notes
+*/
+pub(crate) fn boss_key_fingerprint(boss_key: &str) -> String {
+    hash_text(boss_key)[0..16].to_string()
+}
 
-        TrustyPrinter {}.println(format!("Note: {} is now encrypted.", note_id));
+/**
+* Vaults made before the fingerprint was saved get one the next time they are unlocked
+*/
+fn remember_boss_key_fingerprint(store: &dyn NoteStore, boss_key: &str) {
+    if get_app_value(store, "boss_key_fingerprint").is_none() {
+        save_key_values(store, "app", &[("boss_key_fingerprint", &boss_key_fingerprint(boss_key))], true);
     }
 }
 
-pub(crate) fn decrypt_dump(notes: &Vec<NoteView>) -> Vec<NoteView> {
-    let mut decrypted_notes: Vec<NoteView> = vec![];
+/**
+* Returns the protected notes with their title and body decrypted, none of them when the password is wrong
+*/
+pub(crate) fn decrypt_dump(store: &dyn NoteStore, notes: &[Note]) -> Vec<Note> {
+    let mut decrypted_notes: Vec<Note> = vec![];
     let handle_decrypt = |password: &str| -> bool {
-        let boss_key = get_boss_key(password);
+        let boss_key = get_boss_key(store, password);
+        decrypted_notes.clear();
         for note in notes {
-            let decrypted_note = NoteView{
-                title: decrypt_text(&boss_key, &note.title),
-                body: decrypt_text(&boss_key, &note.body),
-                note_id: note.note_id,
-                content_id: note.content_id.to_string(),
-                updated: note.updated.to_string(),
-                created: note.created.to_string(),
+            let Some((title, body)) = unseal_note(&boss_key, note.data_key.as_deref(), &note.title, &note.body) else {
+                decrypted_notes.clear();
+                return false
             };
-
-            decrypted_notes.push(decrypted_note)
+            decrypted_notes.push(Note { title, body, ..note.clone() });
         }
 
-        return true
+        true
     };
 
-    prompt_for_password(handle_decrypt, true, false);
+    prompt_for_password(store, handle_decrypt, true, false);
+
+    decrypted_notes
+}
+
+/**
+* Saves a new password with a new boss key and returns the recovery code
+*/
+pub fn init_password(store: &dyn NoteStore, password: &str) -> Option<String> {
+    let password = &normalize_password(password);
+    let encrypted_password = encrypt_text(password, password);
+    let recovery_code = Uuid::new_v4().to_string();
+    let encrypted_recovery_code = encrypt_text(&recovery_code, &recovery_code);
+    let raw_boss_key = Uuid::new_v4().to_string();
+    let boss_key = encrypt_text(password, &raw_boss_key);
+    let recovery_boss_key = encrypt_text(&recovery_code, &raw_boss_key);
+    let fingerprint = boss_key_fingerprint(&raw_boss_key);
+
+    let keys = [
+        ("password", encrypted_password.as_str()),
+        ("recovery_code", encrypted_recovery_code.as_str()),
+        ("boss_key", boss_key.as_str()),
+        ("recovery_boss_key", recovery_boss_key.as_str()),
+        ("boss_key_fingerprint", fingerprint.as_str()),
+    ];
+    if save_key_values(store, "app", &keys, true) { Some(recovery_code) } else { None }
+}
+
+/**
+* Returns the decrypted boss key, or None if the password is wrong. Unlike get_boss_key this never prompts or exits.
+*/
+pub fn unlock_boss_key(store: &dyn NoteStore, password: &str) -> Option<String> {
+    let password = &normalize_password(password);
+    let saved_encrypted_password = get_app_value(store, "password")?;
+    if !encrypt_text(password, password).eq(&saved_encrypted_password) {
+        return None
+    }
+
+    let boss_key = try_decrypt_text(password, &get_app_value(store, "boss_key")?)?;
+    remember_boss_key_fingerprint(store, &boss_key);
+
+    Some(boss_key)
+}
+
+/**
+* Replaces the boss key and wraps the data key of every protected note with the new one, note ciphertexts are not changed.
+* The old recovery code only unlocks the old boss key so a new recovery code is returned.
+*/
+pub fn rotate_boss_key(store: &dyn NoteStore, password: &str) -> Result<String, String> {
+    let password = &normalize_password(password);
+    let old_boss_key = unlock_boss_key(store, password).ok_or("Password incorrect.".to_string())?;
+    let new_boss_key = Uuid::new_v4().to_string();
+
+    let mut data_keys = vec![];
+    for note in store.list_notes(false).into_iter().chain(store.list_notes(true)).filter(|note| note.protected) {
+        let data_key = unwrap_data_key(&old_boss_key, note.data_key.as_deref())
+            .ok_or(format!("Could not unwrap the key of note: {}", note.id))?;
+        data_keys.push((note.id, wrap_data_key(&new_boss_key, &data_key)));
+    }
+
+    let recovery_code = Uuid::new_v4().to_string();
+    let encrypted_recovery_code = encrypt_text(&recovery_code, &recovery_code);
+    let boss_key = encrypt_text(password, &new_boss_key);
+    let recovery_boss_key = encrypt_text(&recovery_code, &new_boss_key);
+    let fingerprint = boss_key_fingerprint(&new_boss_key);
+    let keys = [
+        ("recovery_code", encrypted_recovery_code.as_str()),
+        ("boss_key", boss_key.as_str()),
+        ("recovery_boss_key", recovery_boss_key.as_str()),
+        ("boss_key_fingerprint", fingerprint.as_str()),
+    ];
+    // the note keys and the boss key must always change together
+    if !store.save_data_keys(&data_keys, &keys) {
+        return Err("Could not save the new keys.".to_string())
+    }
+
+    Ok(recovery_code)
+}
+
+/**
+* Sets a new password with the recovery code, a new recovery code is returned because the old one has been used
+*/
+pub fn recover_password(store: &dyn NoteStore, recovery_code: &str, new_password: &str) -> Result<String, String> {
+    if !get_app_value(store, "recovery_code").is_some_and(|code| code.eq(&encrypt_text(recovery_code, recovery_code))) {
+        return Err("Invalid recovery key provided.".to_string())
+    }
+    let boss_key = get_app_value(store, "recovery_boss_key")
+        .and_then(|recovery_boss_key| try_decrypt_text(recovery_code, &recovery_boss_key))
+        .ok_or("Could not unlock the boss key with the recovery code.".to_string())?;
+
+    save_password(store, &boss_key, new_password, true).map(|recovery_code| recovery_code.unwrap_or_default())
+}
+
+/**
+* Sets a new password after checking the current one, the boss key stays the same so no notes change
+* @rotate_recovery_code - also replace the recovery code, the new one is returned
+*/
+pub fn change_password(store: &dyn NoteStore, password: &str, new_password: &str, rotate_recovery_code: bool) -> Result<Option<String>, String> {
+    let boss_key = unlock_boss_key(store, password).ok_or("Password incorrect.".to_string())?;
+
+    save_password(store, &boss_key, new_password, rotate_recovery_code)
+}
+
+/**
+* Locks the boss key with a new password and optionally a new recovery code, returns the new recovery code
+*/
+fn save_password(store: &dyn NoteStore, boss_key: &str, new_password: &str, rotate_recovery_code: bool) -> Result<Option<String>, String> {
+    let new_password = &normalize_password(new_password);
+    PasswordPolicy::from_store(store).check(new_password)?;
+    let encrypted_password = encrypt_text(new_password, new_password);
+    let new_boss_key = encrypt_text(new_password, boss_key);
+    let mut keys = vec![
+        ("password", encrypted_password),
+        ("boss_key", new_boss_key),
+    ];
+    let recovery_code = rotate_recovery_code.then(|| Uuid::new_v4().to_string());
+    if let Some(recovery_code) = &recovery_code {
+        keys.push(("recovery_code", encrypt_text(recovery_code, recovery_code)));
+        keys.push(("recovery_boss_key", encrypt_text(recovery_code, boss_key)));
+    }
+
+    let pairs = keys.iter().map(|(key, value)| (*key, value.as_str())).collect::<Vec<(&str, &str)>>();
+    // the password, recovery code and both copies of the boss key must always change together
+    if !save_key_values(store, "app", &pairs, false) {
+        return Err("Could not set password.".to_string())
+    }
 
-    return decrypted_notes
+    Ok(recovery_code)
 }
 
-pub(crate) fn set_password(update: bool, raw_recovery_code: Option<String>) {
-    let cpo = TrustyPathOperations {};
+pub(crate) fn set_password(store: &dyn NoteStore, update: bool, raw_recovery_code: Option<String>) {
     let cr_printer = TrustyPrinter {};
     if update {
         TrustyPrinter {}.println("Change your password".to_string());
         let rrc = &raw_recovery_code.unwrap().to_string();
         let update_password = |pw: &str| -> bool {
-            let encrypted_password = encrypt_text(pw, pw);
-            let recovery_code = Uuid::new_v4().to_string();
-            let encrypted_recovery_code = encrypt_text(&recovery_code, &recovery_code);
-            let old_encrypted_boss_key = get_value_from_attr_table(&cpo,"app", "recovery_boss_key");
-            let old_recovery_key = rrc;
-            let old_decrypted_boss_key = decrypt_text(&old_recovery_key, &old_encrypted_boss_key.value);
-            let new_boss_key = encrypt_text(pw, &old_decrypted_boss_key);
-            let new_recovery_boss_key = encrypt_text(&recovery_code, &old_decrypted_boss_key);
-
-            if update_key_value(&cpo,"app", "password", &encrypted_password) &&
-                update_key_value(&cpo,"app", "recovery_code", &encrypted_recovery_code) &&
-                update_key_value(&cpo,"app", "boss_key", &new_boss_key) &&
-                update_key_value(&cpo,"app", "recovery_boss_key", &new_recovery_boss_key) {
-                cr_printer.println("Password set".to_string());
-                cr_printer.println(format!("🛟 Recovery code generated: {}", recovery_code));
-                cr_printer.println("Save your recovery code and use it to change your password if you forget it...again.".to_string());
+            if !report_password_strength(store, pw) {
+                return false
+            }
+            match recover_password(store, rrc, pw) {
+                Ok(recovery_code) => {
+                    cr_printer.println("Password set".to_string());
+                    cr_printer.println(format!("🛟 Recovery code generated: {}", recovery_code));
+                    cr_printer.println("Save your recovery code and use it to change your password if you forget it...again.".to_string());
 
-                return true
-            } else {
-                cr_printer.print_error(format!("{}", "Could not set password."));
-                exit(Errors::SetPasswordErr as i32)
+                    true
+                }
+                Err(err) => {
+                    cr_printer.print_error(err);
+                    exit(Errors::SetPasswordErr as i32)
+                }
             }
         };
 
-        if prompt_for_password(update_password, false, true) {
-            return
-        } else {
-            cr_printer.print_error(format!("{}", "Invalid password."));
+        if !prompt_for_password(store, update_password, false, true) {
+            cr_printer.print_error("Invalid password.".to_string());
             exit(Errors::CreatePasswordErr as i32)
         }
     } else {
         let insert_password = |pw: &str| -> bool {
-            let encrypted_password = encrypt_text(pw, pw);
-            let recovery_code = Uuid::new_v4().to_string();
-            let encrypted_recovery_code = encrypt_text(&recovery_code, &recovery_code);
-            let raw_boss_key = Uuid::new_v4().to_string();
-            let boss_key = encrypt_text(pw, &raw_boss_key);
-            let recovery_boss_key = encrypt_text(&recovery_code, &raw_boss_key);
-
-            if add_key_value(&cpo,"app", "password", &encrypted_password) &&
-                add_key_value(&cpo,"app", "recovery_code", &encrypted_recovery_code) &&
-                add_key_value(&cpo,"app", "boss_key", &boss_key) &&
-                add_key_value(&cpo,"app", "recovery_boss_key", &recovery_boss_key) {
+            if !report_password_strength(store, pw) {
+                return false
+            }
+            if let Some(recovery_code) = init_password(store, pw) {
                 cr_printer.println("Password set".to_string());
                 cr_printer.println(format!("🛟 Recovery code generated: {}", recovery_code));
                 cr_printer.println("Save your recovery code and use it to change your password if you forget it.".to_string());
 
-                return true
+                true
             } else {
-                cr_printer.print_error(format!("{}", "Could not set password."));
+                cr_printer.print_error("Could not set password.".to_string());
                 exit(Errors::SetPasswordErr as i32)
             }
         };
 
-        cr_printer.println("Set up an alpha-numeric password so that you can encrypt things 🤐".to_string());
-        if prompt_for_password(insert_password, false, true) {
-            return
-        } else {
+        cr_printer.println("Set up a password so that you can encrypt things 🤐, a passphrase of a few words works well".to_string());
+        if !prompt_for_password(store, insert_password, false, true) {
             cr_printer.print_error(format!("Could not setup a password. You need to remove your tRusty config found here: {} to start over.",
-                                           &cpo.get_trusty_dir().display().to_string()));
+                                           &TrustyPathOperations {}.get_trusty_dir().display().to_string()));
             exit(Errors::CreatePasswordErr as i32)
         }
     }
@@ -269,14 +432,16 @@ pub(crate) fn set_password(update: bool, raw_recovery_code: Option<String>) {
 
 #[cfg(test)]
 mod tests {
-    use crate::security::{decrypt_text, encrypt_text, validate_password};
+    use crate::security::{decrypt_text, encrypt_text, seal_note, unseal_note, unwrap_data_key, validate_password, wrap_data_key};
 
     #[test]
     fn test_validate_password() {
         assert!(validate_password("Foobar"));
         assert!(validate_password("Foob123"));
-        assert!(!validate_password("Fo"));
-        assert!(!validate_password("🛟🛟Foo"));
+        assert!(validate_password("correct horse battery staple"));
+        assert!(validate_password("🛟🛟Foo"));
+        assert!(!validate_password(""));
+        assert!(!validate_password("   "));
     }
 
 
@@ -294,4 +459,21 @@ mod tests {
 
         assert_eq!(decrypted_text, val_1);
     }
+
+    #[test]
+    fn test_data_keys() {
+        let (title, body, wrapped_key) = seal_note("boss key", "Secret", "token");
+        assert_ne!(unwrap_data_key("boss key", Some(&wrapped_key)).unwrap(), "boss key");
+        assert_eq!(unseal_note("boss key", Some(&wrapped_key), &title, &body), Some(("Secret".to_string(), "token".to_string())));
+        assert_eq!(unseal_note("other key", Some(&wrapped_key), &title, &body), None);
+
+        // re-wrapping the data key leaves the note readable with the new boss key
+        let data_key = unwrap_data_key("boss key", Some(&wrapped_key)).unwrap();
+        let rewrapped_key = wrap_data_key("new boss key", &data_key);
+        assert_eq!(unseal_note("new boss key", Some(&rewrapped_key), &title, &body).unwrap().1, "token");
+
+        // notes protected before data keys were added are encrypted with the boss key
+        let legacy_body = encrypt_text("boss key", "old token");
+        assert_eq!(unseal_note("boss key", None, &encrypt_text("boss key", "Old"), &legacy_body).unwrap().1, "old token");
+    }
 }
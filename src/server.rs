@@ -0,0 +1,222 @@
+use std::net::SocketAddr;
+
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+use tiny_http::{Header, Request, Response, Server};
+use uuid::Uuid;
+
+use crate::api::{ApiError, ApiResult, ApiSession};
+use crate::sql::{get_config_value, set_config_value};
+use crate::store::NoteStore;
+
+pub const DEFAULT_SERVE_ADDR: &str = "127.0.0.1:7878";
+
+/**
+* Returns the token clients send as "Authorization: Bearer <token>", one is made the first time the server starts
+*/
+pub fn get_api_token(store: &dyn NoteStore) -> String {
+    let token = get_config_value(store, "api_token", "");
+    if !token.is_empty() {
+        return token
+    }
+    let token = Uuid::new_v4().simple().to_string();
+    set_config_value(store, "api_token", &token);
+
+    token
+}
+
+/**
+* The server only listens on this machine, notes should never be reachable from the network
+*/
+pub fn parse_serve_addr(addr: &str) -> Result<SocketAddr, String> {
+    let socket_addr = addr.trim().parse::<SocketAddr>().map_err(|_| format!("Invalid address: {}, ex: {}", addr, DEFAULT_SERVE_ADDR))?;
+    if !socket_addr.ip().is_loopback() {
+        return Err(format!("Only localhost addresses can be served, ex: {}", DEFAULT_SERVE_ADDR))
+    }
+
+    Ok(socket_addr)
+}
+
+pub fn start_server(addr: Option<&str>, socket: Option<&str>) -> Result<Server, String> {
+    match socket {
+        #[cfg(unix)]
+        Some(path) => {
+            use std::os::unix::fs::FileTypeExt;
+
+            let path = std::path::Path::new(path);
+            // a socket left behind by a server that did not shut down cleanly, anything else at the path is kept
+            match std::fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.file_type().is_socket() => {
+                    std::fs::remove_file(path).map_err(|err| format!("Could not remove old socket: {}", err))?;
+                }
+                Ok(_) => return Err(format!("{} already exists and is not a socket, choose another path.", path.display())),
+                Err(_) => {}
+            }
+            Server::http_unix(path).map_err(|err| format!("Could not listen on {}: {}", path.display(), err))
+        }
+        #[cfg(not(unix))]
+        Some(_) => Err("Unix sockets are not supported on this platform.".to_string()),
+        None => {
+            let socket_addr = parse_serve_addr(addr.unwrap_or(DEFAULT_SERVE_ADDR))?;
+            Server::http(socket_addr).map_err(|err| format!("Could not listen on {}: {}", socket_addr, err))
+        }
+    }
+}
+
+/**
+* Answers requests until the process is stopped, protected notes stay unlocked until POST /unlock is undone with POST /lock
+*/
+pub fn run_server(server: Server, store: &dyn NoteStore, token: &str) {
+    let mut session = ApiSession::new(store);
+    for mut request in server.incoming_requests() {
+        let mut body = String::new();
+        let (status, json) = match request.as_reader().read_to_string(&mut body) {
+            Ok(_) => {
+                let auth = request.headers().iter()
+                    .find(|header| header.field.equiv("Authorization"))
+                    .map(|header| header.value.to_string());
+                handle_request(&mut session, token, request.method().as_str(), request.url(), auth.as_deref(), &body)
+            }
+            Err(_) => error_response(ApiError::bad_request("The request body must be UTF-8 text."))
+        };
+        respond(request, status, json);
+    }
+}
+
+fn respond(request: Request, status: u16, json: Value) {
+    let header = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(json.to_string()).with_status_code(status).with_header(header);
+    // the client may have gone away, there is nobody left to tell
+    let _ = request.respond(response);
+}
+
+fn error_response(err: ApiError) -> (u16, Value) {
+    (err.status, json!({"error": err.message}))
+}
+
+/**
+* Decodes %XX escapes and + in query strings
+*/
+fn decode_query_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() && value.is_char_boundary(i + 3) && u8::from_str_radix(&value[i + 1..i + 3], 16).is_ok() => {
+                decoded.push(u8::from_str_radix(&value[i + 1..i + 3], 16).unwrap());
+                i += 2;
+            }
+            byte => decoded.push(byte)
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn get_query_value(query: &str, name: &str) -> Option<String> {
+    query.split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(key, _)| key.eq(&name))
+        .map(|(_, value)| decode_query_value(value))
+}
+
+fn parse_body(body: &str) -> Result<Value, ApiError> {
+    if body.trim().is_empty() {
+        return Ok(json!({}))
+    }
+    serde_json::from_str::<Value>(body).map_err(|err| ApiError::bad_request(&format!("Invalid JSON: {}", err)))
+}
+
+fn parse_id(id: &str) -> Result<usize, ApiError> {
+    id.parse::<usize>().map_err(|_| ApiError::bad_request(&format!("Invalid note id: {}", id)))
+}
+
+fn route(session: &mut ApiSession, method: &str, path: &str, query: &str, body: &str) -> ApiResult {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<&str>>();
+    match (method, segments.as_slice()) {
+        ("POST", ["unlock"]) => {
+            let json = parse_body(body)?;
+            let password = json["password"].as_str().ok_or(ApiError::bad_request("A password is required."))?;
+            session.unlock(password)
+        }
+        ("POST", ["lock"]) => session.lock(),
+        ("GET", ["notes"]) => {
+            let trashed = get_query_value(query, "trashed").map(|value| value.eq("true")).unwrap_or(false);
+            session.list(trashed)
+        }
+        ("POST", ["notes"]) => {
+            let json = parse_body(body)?;
+            let text = json["body"].as_str().ok_or(ApiError::bad_request("A note body is required."))?;
+            session.create(json["title"].as_str(), text, json["protected"].as_bool().unwrap_or(false))
+        }
+        ("GET", ["notes", id]) => session.get(parse_id(id)?),
+        ("PUT", ["notes", id]) => {
+            let json = parse_body(body)?;
+            session.update(parse_id(id)?, json["title"].as_str(), json["body"].as_str())
+        }
+        ("DELETE", ["notes", id]) => session.delete(parse_id(id)?),
+        ("POST", ["notes", id, "trash"]) => session.set_trashed(parse_id(id)?, true),
+        ("POST", ["notes", id, "restore"]) => session.set_trashed(parse_id(id)?, false),
+        ("GET", ["search"]) => session.search(&get_query_value(query, "q").unwrap_or_default()),
+        _ => Err(ApiError::new(404, &format!("No route for {} /{}", method, segments.join("/"))))
+    }
+}
+
+/**
+* Handles one request and returns the status code and the JSON response, this does not need a socket so it can be tested
+* @auth - the value of the Authorization header
+*/
+pub fn handle_request(session: &mut ApiSession, token: &str, method: &str, url: &str, auth: Option<&str>, body: &str) -> (u16, Value) {
+    let expected = format!("Bearer {}", token);
+    // compared in constant time so the token can not be guessed one character at a time
+    let authorized = auth.map(|value| value.trim().as_bytes().ct_eq(expected.as_bytes()).into()).unwrap_or(false);
+    if token.is_empty() || !authorized {
+        return error_response(ApiError::new(401, "A valid token is required, ex: Authorization: Bearer <token>"))
+    }
+
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    match route(session, method, path, query, body) {
+        Ok(json) => (200, json),
+        Err(err) => error_response(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::{decode_query_value, get_query_value, parse_serve_addr, start_server};
+
+    #[test]
+    fn test_parse_serve_addr() {
+        assert!(parse_serve_addr("127.0.0.1:7878").is_ok());
+        assert!(parse_serve_addr("[::1]:7878").is_ok());
+        assert!(parse_serve_addr("0.0.0.0:7878").is_err());
+        assert!(parse_serve_addr("192.168.1.2:7878").is_err());
+        assert!(parse_serve_addr("localhost").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start_server_keeps_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("trusty.db");
+        std::fs::write(&db_path, "notes").unwrap();
+        assert!(start_server(None, Some(&db_path.to_string_lossy())).is_err_and(|err| err.contains("is not a socket")));
+        assert_eq!(std::fs::read_to_string(&db_path).unwrap(), "notes");
+
+        // a socket left behind is replaced
+        let socket_path = dir.path().join("trusty.sock");
+        drop(std::os::unix::net::UnixListener::bind(&socket_path).unwrap());
+        assert!(start_server(None, Some(&socket_path.to_string_lossy())).is_ok());
+    }
+
+    #[test]
+    fn test_get_query_value() {
+        assert_eq!(decode_query_value("foo%20bar+baz%2"), "foo bar baz%2");
+        assert_eq!(get_query_value("q=foo%23bar&trashed=true", "q"), Some("foo#bar".to_string()));
+        assert_eq!(get_query_value("q=foo&trashed", "trashed"), Some("".to_string()));
+        assert_eq!(get_query_value("q=foo", "trashed"), None);
+    }
+}
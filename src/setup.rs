@@ -4,10 +4,12 @@ use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::string::ToString;
 use std::time::SystemTime;
-use rusqlite::Connection;
+use rusqlite::{Connection, named_params};
 use uuid::Uuid;
+use crate::db_encryption::apply_db_key;
 use crate::errors::Errors;
 use crate::render::{Printer, TrustyPrinter};
+use crate::sql::backfill_content_hashes;
 
 #[cfg(test)]
 use mockall::*;
@@ -118,7 +120,10 @@ pub fn create_trusty_dir(cpo: &dyn PathOperations) -> bool {
 }
 
 pub fn get_db_conn(db_path: &PathBuf) -> Connection {
-    Connection::open(db_path.as_path()).unwrap()
+    let conn = Connection::open(db_path.as_path()).unwrap();
+    // an encrypted database is opened with the key it was unlocked with this session
+    apply_db_key(&conn).unwrap();
+    conn
 }
 
 pub fn create_trusty_sys_tables(db_path: &PathBuf) {
@@ -173,6 +178,60 @@ pub(crate) fn populate_trusty_sys_tables(cpo: &dyn PathOperations) {
     TrustyPrinter {}.println(format!("{}", "Configurations added."));
 }
 
+// columns added after the initial release, existing databases get these on startup
+const COLUMN_MIGRATIONS: [(&str, &str, &str); 5] = [
+    ("notes", "due", "DATETIME"),
+    ("content", "hash", "NCHAR(64)"),
+    ("notes", "content_type", "VARCHAR(16)"),
+    ("notes", "pinned", "BOOLEAN DEFAULT FALSE"),
+    ("notes", "data_key", "TEXT"),
+];
+
+// tables and triggers added after the initial release
+const TABLE_MIGRATIONS: [&str; 3] = [
+    "CREATE TABLE IF NOT EXISTS history (history_id INTEGER PRIMARY KEY AUTOINCREMENT, content_id NCHAR(36), \
+    title TEXT, created DATETIME, updated DATETIME, event VARCHAR(36), recorded DATETIME);",
+    // deleted notes leave a tombstone so that a sync can delete them from the other database too
+    "CREATE TABLE IF NOT EXISTS tombstones (content_id NCHAR(36) PRIMARY KEY, deleted DATETIME);",
+    "CREATE TRIGGER IF NOT EXISTS record_tombstone AFTER DELETE ON notes BEGIN \
+    INSERT OR REPLACE INTO tombstones (content_id, deleted) VALUES (OLD.content_id, CURRENT_TIMESTAMP); END;",
+];
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> bool {
+    let sql = format!("SELECT COUNT(*) FROM pragma_table_info('{}') WHERE name = :column;", table);
+    let count: i32 = conn.query_row(&sql, named_params! {":column": column}, |row| row.get(0)).unwrap_or(0);
+
+    count > 0
+}
+
+pub fn migrate_trusty_db(cpo: &dyn PathOperations) {
+    let db_path = cpo.get_trusty_db_path();
+    let conn = get_db_conn(&db_path);
+
+    let needs_hashes = !has_column(&conn, "content", "hash");
+
+    for (table, column, column_type) in COLUMN_MIGRATIONS {
+        if !has_column(&conn, table, column) {
+            let sql = format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, column_type);
+            if conn.execute(&sql, ()).is_err() {
+                TrustyPrinter {}.print_error(format!("Could not add column {} to the {} table.", column, table));
+                exit(Errors::MigrateDBErr as i32)
+            }
+        }
+    }
+
+    for sql in TABLE_MIGRATIONS {
+        if conn.execute(sql, ()).is_err() {
+            TrustyPrinter {}.print_error("Could not create a table while updating the tRusty DB.".to_string());
+            exit(Errors::MigrateDBErr as i32)
+        }
+    }
+
+    if needs_hashes {
+        backfill_content_hashes(&conn);
+    }
+}
+
 pub fn init_trusty_db(cpo: &dyn PathOperations) -> bool {
     let db_path = cpo.get_trusty_db_path();
     let db_created = fs::File::create(db_path.as_path());
@@ -180,6 +239,7 @@ pub fn init_trusty_db(cpo: &dyn PathOperations) -> bool {
         Ok(_) => {
             create_trusty_sys_tables(&db_path);
             populate_trusty_sys_tables(cpo);
+            migrate_trusty_db(cpo);
         }
         Err(_) => {
             TrustyPrinter {}.println(format!("{}", "Could not create tRusty DB."));
@@ -1,43 +1,50 @@
 use std::process::exit;
 
+use chrono::{DateTime, Utc};
 use rusqlite::{Connection, named_params};
-use uuid::Uuid;
 
+use crate::checklist::count_checklist;
+use crate::dates::{parse_utc_timestamp, to_sqlite_timestamp};
 use crate::cli::read_from_std_in;
+use crate::duplicates::DuplicatePolicy;
+use crate::note::{ContentId, MAX_TITLE_LENGTH, Note, NoteId};
 use crate::errors::Errors;
-use crate::render::{TrustyPrinter, print_note_summary, Printer};
-use crate::security::{decrypt_dump, decrypt_note, encrypt_text, get_boss_key, prompt_for_password};
-use crate::setup::{TrustyPathOperations, get_db_conn, PathOperations};
-use crate::utils::{make_text_single_line, slice_text};
+use crate::picker::PickerNote;
+use crate::query::Query;
+use crate::render::{ListOptions, TrustyPrinter, print_note_summary, Printer};
+use crate::security::{decrypt_dump, decrypt_note, decrypt_notes, get_boss_key, prompt_for_password, seal_note};
+use crate::store::{key_value_table, NoteStore, SqliteStore};
+use crate::utils::{hash_text, make_text_single_line, parse_menu_line, parse_menu_lines, slice_text};
 
 #[derive(Debug)]
 pub struct NoteSummary {
-    pub id: i32,
+    pub id: NoteId,
     pub title: String,
-    pub updated: String,
+    // timestamps are UTC, they are converted when the list is printed
+    pub updated: DateTime<Utc>,
+    pub due: Option<DateTime<Utc>>,
+    pub overdue: bool,
+    pub tasks_done: usize,
+    pub tasks_total: usize,
+    pub protected: bool,
+    pub trashed: bool,
+    pub pinned: bool,
 }
 
 pub struct SimpleNoteView {
+    pub note_id: NoteId,
     pub title: String,
     pub body: String,
-    pub content_id: String,
+    pub content_id: ContentId,
     pub protected: bool
 }
 
-pub struct NoteView {
-    pub title: String,
-    pub body: String,
-    pub note_id: i32,
-    pub content_id: String,
-    pub updated: String,
-    pub created: String,
-}
-
 pub struct LargeNoteSummary {
-    pub note_id: i32,
+    pub note_id: NoteId,
     pub title: String,
-    pub content_id: String,
-    pub content_size: i32
+    pub content_id: ContentId,
+    // in characters
+    pub content_size: usize
 }
 
 pub struct DBStats {
@@ -48,8 +55,25 @@ pub struct DBStats {
 pub struct SummaryStats {
     pub db_stats: DBStats,
     pub large_note_stats: LargeNoteSummary,
-    pub state_note_stats: NoteView,
-    pub fresh_note_stats: NoteView
+    pub state_note_stats: Note,
+    pub fresh_note_stats: Note
+}
+
+pub struct DueNote {
+    pub note_id: NoteId,
+    pub title: String,
+    pub content_id: ContentId,
+    // UTC, shown in local time and exported to calendars as UTC
+    pub due: DateTime<Utc>,
+    pub overdue: bool
+}
+
+pub struct HistoryEntry {
+    pub title: String,
+    pub created: String,
+    pub updated: String,
+    pub event: String,
+    pub recorded: String
 }
 
 pub struct KeyValuePair {
@@ -57,160 +81,197 @@ pub struct KeyValuePair {
     pub value: String
 }
 
-pub fn add_note(cpo: &dyn PathOperations, title: &str, note: &str, protected: bool) -> bool {
+pub fn add_note(store: &dyn NoteStore, title: &str, note: &str, protected: bool) -> bool {
     if protected {
-        insert_encrypted_note(title, note);
+        insert_encrypted_note(store, title, note);
     } else {
+        // protected notes are not hashed so they are never checked for duplicates
+        let policy = DuplicatePolicy::from_config(&get_config_value(store, "duplicate_policy", "warn"));
+        if policy != DuplicatePolicy::Allow {
+            let duplicate_ids = store.find_duplicates(note);
+            if !duplicate_ids.is_empty() {
+                let ids = duplicate_ids.iter().map(|id| id.to_string()).collect::<Vec<String>>().join(", ");
+                if policy == DuplicatePolicy::Skip {
+                    TrustyPrinter {}.print_error(format!("Note not saved, it is a duplicate of note(s): {}", ids));
+                    return false
+                }
+                TrustyPrinter {}.print_error(format!("Warning: this note is a duplicate of note(s): {}", ids));
+            }
+        }
         let formatted_title = make_text_single_line(title);
-        let truncated_title = slice_text(0, 128, &formatted_title);
-        insert_note(cpo, &truncated_title, &note, false)
+        let truncated_title = slice_text(0, MAX_TITLE_LENGTH, &formatted_title);
+        if store.insert_note(&truncated_title, note, None).is_none() {
+            TrustyPrinter {}.print_error("Could not save note.".to_string());
+            return false
+        }
     }
 
     return true
 }
 
-pub(crate)  fn insert_note(cpo: &dyn PathOperations, title: &str, note: &str, protected: bool) {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    // create the new note id
-    let content_id = Uuid::new_v4().to_string();
-    let note_insert = "INSERT INTO notes (title, protected, created, updated, content_id) \
-    VALUES (:title, :protected, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, :content_id);";
-    let content_insert = "INSERT INTO content (content_id, body) VALUES (:content_id, :body);";
-
-    // The integrity of these 2 inserts needs to be guaranteed.
-    conn.execute(&content_insert, named_params! {
-        ":content_id": content_id,
-        ":body": note,
-    }).unwrap();
-
-    conn.execute(&note_insert, named_params! {
-        ":title": title,
-        ":protected": protected,
-        ":content_id": content_id,
-    }).unwrap();
-
-    let last_inserted_sql = "UPDATE app SET value = (SELECT last_insert_rowid()) WHERE key = 'last_touched';";
-    conn.execute(last_inserted_sql, ()).unwrap();
-}
-
-pub(crate) fn insert_encrypted_note(title: &str, note: &str) {
+pub(crate) fn insert_encrypted_note(store: &dyn NoteStore, title: &str, note: &str) {
     let encrypted_and_insert_note = | password: &str| -> bool {
         let formatted_title = make_text_single_line(title);
-        let decrypted_boss_key = get_boss_key(password);
-        let encrypted_title = encrypt_text(&decrypted_boss_key, &formatted_title);
-        let encrypt_note = encrypt_text(&decrypted_boss_key, note);
-        insert_note(&TrustyPathOperations {}, &encrypted_title, &encrypt_note, true);
+        let decrypted_boss_key = get_boss_key(store, password);
+        let (encrypted_title, encrypt_note, data_key) = seal_note(&decrypted_boss_key, &formatted_title, note);
+        if store.insert_note(&encrypted_title, &encrypt_note, Some(&data_key)).is_none() {
+            TrustyPrinter {}.print_error("Could not save note.".to_string());
+        }
 
         return true
     };
 
-    prompt_for_password(encrypted_and_insert_note, true, false);
-}
-
-pub fn list_note_titles(cpo: &dyn PathOperations, printer: &dyn Printer) {
-    let sql = "SELECT note_id, title, updated, protected FROM notes WHERE TRASHED IS FALSE ORDER BY updated;";
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let mut stmt = conn.prepare(sql).unwrap();
-    let results = stmt.query_map([], |row| {
+    prompt_for_password(store, encrypted_and_insert_note, true, false);
+}
+
+/**
+* Prints the menu, pinned notes are listed last so they stay next to the prompt
+*/
+pub fn list_note_titles(store: &SqliteStore, printer: &dyn Printer, options: &ListOptions) {
+    let sql = format!("SELECT note_id, title, updated, protected, due, due <= CURRENT_TIMESTAMP, content.body, trashed, pinned \
+    FROM notes LEFT JOIN content on notes.content_id = content.content_id \
+    WHERE (TRASHED IS FALSE OR :include_trashed) AND (:since IS NULL OR updated >= :since) AND {} \
+    ORDER BY pinned, updated;", options.query.condition());
+    let mut stmt = store.conn().prepare(&sql).unwrap();
+    // a trashed: term in the query decides which trashed notes are listed
+    let include_trashed = options.include_trashed || options.query.filters_trash();
+    let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":include_trashed", &include_trashed), (":since", &options.since)];
+    params.extend(options.query.params());
+    let results = stmt.query_map(params.as_slice(), |row| {
         let is_protected: bool = row.get(3).unwrap();
-        let title: String = if is_protected { "🔒 ENCRYPTED".to_string() } else {row.get(1).unwrap_or("NULL".to_string())};
+        let title: String = if is_protected { "ENCRYPTED".to_string() } else {row.get(1).unwrap_or("NULL".to_string())};
+        // checklists inside of protected notes stay hidden until the note is decrypted
+        let (tasks_done, tasks_total) = if is_protected { (0, 0) } else {
+            count_checklist(&row.get::<usize, Option<String>>(6)?.unwrap_or_default())
+        };
         Ok(NoteSummary {
-            id: row.get(0)?,
+            id: NoteId(row.get(0)?),
             title,
-            updated: row.get(2)?,
+            updated: parse_utc_timestamp(&row.get::<usize, String>(2)?).unwrap_or_default(),
+            due: row.get::<usize, Option<String>>(4)?.as_deref().and_then(parse_utc_timestamp),
+            overdue: row.get::<usize, Option<bool>>(5)?.unwrap_or(false),
+            tasks_done,
+            tasks_total,
+            protected: is_protected,
+            trashed: row.get(7)?,
+            pinned: row.get::<usize, Option<bool>>(8)?.unwrap_or(false),
         })
     }).unwrap();
 
     for res in results {
-        print_note_summary(printer, res.unwrap());
+        print_note_summary(printer, res.unwrap(), options);
     }
 }
 
-pub fn get_note_by_id(cpo: &dyn PathOperations, id: usize) -> SimpleNoteView {
-    let sql = "SELECT notes.title, content.body, notes.protected, notes.content_id FROM notes JOIN content on notes.content_id = content.content_id WHERE notes.note_id = :note_id;";
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let mut stmt = conn.prepare(sql).unwrap();
-    let result = match stmt.query_row(named_params! {":note_id": id as u32}, |row| {
-        let is_protected = row.get(2).unwrap();
-
-        let title: String = row.get(0).unwrap();
-        let note: String = row.get(1).unwrap();
-
-        return if is_protected {
-            let unencrypted_note = decrypt_note(&title, &note);
-
-            Ok(SimpleNoteView {
-                title: unencrypted_note.title,
-                body: unencrypted_note.body,
-                content_id: row.get(3)?,
-                protected: true
-            })
-        } else {
-            Ok(SimpleNoteView {
-                title,
-                body: note,
-                content_id: row.get(3)?,
-                protected: false
-            })
-        }
-    }) {
-        Ok(res) => {
-            update_last_touched(&TrustyPathOperations {}, id.to_string().as_str());
-            res
-        },
-        Err(_) => {
+/**
+* Note IDs and titles for shell completions, the titles of protected notes are never shown
+*/
+pub fn list_note_completions(store: &dyn NoteStore, trashed: bool) -> Vec<(NoteId, String)> {
+    store.list_notes(trashed).into_iter().map(|note| {
+        let title = if note.protected { "🔒".to_string() } else { make_text_single_line(&note.title) };
+        (note.id, title)
+    }).collect()
+}
+
+/**
+* Notes for the picker, newest first. Protected notes are listed without their title or body.
+*/
+pub fn list_picker_notes(store: &SqliteStore) -> Vec<PickerNote> {
+    let sql = "SELECT note_id, title, protected, content.body FROM notes JOIN content on notes.content_id = content.content_id \
+    WHERE trashed IS FALSE ORDER BY pinned DESC, updated DESC;";
+    let mut stmt = store.conn().prepare(sql).unwrap();
+    let results = stmt.query_map([], |row| {
+        let is_protected: bool = row.get(2)?;
+        Ok(match is_protected {
+            true => PickerNote { id: row.get(0)?, title: "🔒 ENCRYPTED".to_string(), body: None },
+            false => PickerNote { id: row.get(0)?, title: row.get::<usize, Option<String>>(1)?.unwrap_or_default(), body: row.get(3)? }
+        })
+    }).unwrap();
+
+    results.filter_map(|result| result.ok()).collect()
+}
+
+/**
+* Returns a note with its title and body decrypted, exits when the note does not exist
+*/
+pub fn get_note_by_id(store: &dyn NoteStore, id: NoteId) -> SimpleNoteView {
+    let note = get_note_or_exit(store, id);
+    store.set_last_touched(id);
+
+    to_simple_note_view(store, note)
+}
+
+fn get_note_or_exit(store: &dyn NoteStore, id: NoteId) -> Note {
+    match store.get_note(id) {
+        Some(note) => note,
+        None => {
             TrustyPrinter {}.print_error(format!("Could not find note for id: {}", id));
             exit(Errors::NoteIdErr as i32);
         }
-    };
+    }
+}
 
-    result
+fn to_simple_note_view(store: &dyn NoteStore, note: Note) -> SimpleNoteView {
+    let (title, body) = if note.protected { decrypt_note(store, &note) } else { (note.title.to_string(), note.body.to_string()) };
+
+    with_decrypted_text(note, title, body)
 }
 
-pub fn get_note_from_menu_line(cpo: &dyn PathOperations) -> SimpleNoteView {
-    let result = match read_from_std_in() {
-        None => {
-            TrustyPrinter {}.print_error(format!("{}", "No menu line specified, could not lookup record."));
-            exit(Errors::MenuLineErr as i32);
-        }
-        Some(ln) => {
-            let trimmed_ln = ln.trim();
-            if trimmed_ln.is_empty() {
-                TrustyPrinter {}.print_error(format!("{}", "Menu line input is empty, could not lookup record."));
-                exit(Errors::MenuLineEmptyErr as i32);
-            } else {
-                get_note_from_menu_line_by_id(cpo, ln.as_str())
-            }
+fn with_decrypted_text(note: Note, title: String, body: String) -> SimpleNoteView {
+    SimpleNoteView {
+        note_id: note.id,
+        title,
+        body,
+        content_id: note.content_id,
+        protected: note.protected
+    }
+}
+
+/**
+* Reads menu rows from the standard input, any line that starts with a note ID or a JSON line with an id works.
+* Each note is only returned once, ex: for several dump lines of the same note or --grep output.
+*/
+pub fn get_notes_from_menu_lines(store: &dyn NoteStore) -> Vec<SimpleNoteView> {
+    let text = read_from_std_in().unwrap_or_default();
+    if text.trim().is_empty() {
+        TrustyPrinter {}.print_error("Menu line input is empty, could not lookup record.".to_string());
+        exit(Errors::MenuLineEmptyErr as i32);
+    }
+
+    let ids = match parse_menu_lines(&text) {
+        Ok(ids) => ids,
+        Err(line) => {
+            TrustyPrinter {}.print_error(format!("Menu line input is malformed, please check your input: {}", line));
+            exit(Errors::MenuLineMalformedErr as i32);
         }
     };
-    result
+
+    let notes = ids.into_iter().map(|id| get_note_or_exit(store, NoteId(id))).collect::<Vec<Note>>();
+    // every protected note is decrypted with a single password prompt
+    let protected = notes.iter().filter(|note| note.protected).cloned().collect::<Vec<Note>>();
+    let mut decrypted = if protected.is_empty() { vec![] } else { decrypt_notes(store, &protected) }.into_iter();
+
+    notes.into_iter().map(|note| {
+        store.set_last_touched(note.id);
+        let (title, body) = if note.protected { decrypted.next().unwrap_or_default() } else { (note.title.to_string(), note.body.to_string()) };
+        with_decrypted_text(note, title, body)
+    }).collect()
 }
 
-pub fn get_note_from_menu_line_by_id(cpo: &dyn PathOperations, line: &str) -> SimpleNoteView {
-    let mut id_segment = &line[0..9];
-    id_segment = id_segment.trim();
-    let result = match id_segment.parse::<i32>(){
-        Ok(id) => {
-            get_note_by_id(cpo, id as usize)
-        }
-        Err(_) => {
+pub fn get_note_from_menu_line_by_id(store: &dyn NoteStore, line: &str) -> SimpleNoteView {
+    match parse_menu_line(line) {
+        Some(id) => get_note_by_id(store, NoteId(id)),
+        None => {
             TrustyPrinter {}.print_error(format!("{}", "Menu line input is malformed, please check your input."));
             exit(Errors::MenuLineMalformedErr as i32);
         }
-    };
-    result
+    }
 }
 
-pub fn update_last_touched(cpo: &dyn PathOperations, note_id:&str){
-    let sql = "UPDATE app SET value = :last_touched WHERE key = 'last_touched';";
-    match note_id.parse::<i32>() {
+pub fn update_last_touched(store: &dyn NoteStore, note_id:&str){
+    match note_id.parse::<NoteId>() {
         Ok(id) => {
-            let db_path = cpo.get_trusty_db_path();
-            let conn = get_db_conn(&db_path);
-            conn.execute(&sql, named_params! {":last_touched": id as usize}).unwrap();
+            store.set_last_touched(id);
         }
         Err(_) => {
             TrustyPrinter {}.print_error(format!("{}", "note ID is malformed, please check your input."));
@@ -219,45 +280,16 @@ pub fn update_last_touched(cpo: &dyn PathOperations, note_id:&str){
     }
 }
 
-pub fn get_last_touched_note(cpo: &dyn PathOperations) -> SimpleNoteView {
-    let sql = "SELECT notes.content_id, notes.title, notes.protected, content.body FROM notes JOIN content on notes.content_id = content.content_id \
-    WHERE notes.note_id = CAST((SELECT value FROM app WHERE key = 'last_touched') AS INTEGER); ";
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let mut stmt = conn.prepare(sql).unwrap();
-    let result = match stmt.query_row([], |row| {
-        let is_protected: bool = row.get(2).unwrap();
-        let title: String = row.get(1).unwrap();
-        let body: String = row.get(3).unwrap();
-        let content_id = row.get(0).unwrap();
-
-        if is_protected {
-            let decrypted_note = decrypt_note(&title, &body);
-
-            Ok(SimpleNoteView {
-                content_id,
-                title: decrypted_note.title,
-                body: decrypted_note.body,
-                protected: is_protected
-            })
-        } else {
-            Ok(SimpleNoteView {
-                content_id,
-                title: row.get(1)?,
-                body: row.get(3)?,
-                protected: is_protected
-            })
-        }
-    }) {
-        Ok(res) => {
-            res
+pub fn get_last_touched_note(store: &dyn NoteStore) -> SimpleNoteView {
+    match store.last_touched().and_then(|id| store.get_note(id)) {
+        Some(note) => {
+            to_simple_note_view(store, note)
         }
-        Err(_) => {
+        None => {
             TrustyPrinter {}.print_error(format!("{}", "Could not fetch the last touched note."));
             exit(Errors::LastTouchFetchErr as i32)
         }
-    };
-    result
+    }
 }
 
 pub fn update_note_ts_by_content_id(id: &str, conn: &Connection) -> bool {
@@ -276,341 +308,342 @@ pub fn update_note_ts_by_note_id(id: usize, conn: &Connection) -> bool {
     result > 0
 }
 
-pub fn update_note_by_content_id(cpo: &dyn PathOperations, id: &str, text: &str) -> bool {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let sql = "UPDATE content SET body = :body WHERE content_id = :content_id;";
-    let stmt = conn.prepare(sql);
-    stmt.unwrap().execute(named_params! {":content_id": id, ":body": &text}).unwrap();
-    update_note_ts_by_content_id(id, &conn)
-}
+pub fn update_note_by_content_id(store: &SqliteStore, id: &str, text: &str) -> bool {
+    let sql = "UPDATE content SET body = :body, \
+    hash = CASE WHEN (SELECT protected FROM notes WHERE content_id = :content_id) THEN NULL ELSE :hash END \
+    WHERE content_id = :content_id;";
+    let ts_sql = "UPDATE notes SET updated = CURRENT_TIMESTAMP WHERE content_id = :content_id;";
+    let result = store.with_transaction(|tx| {
+        tx.execute(sql, named_params! {":content_id": id, ":body": &text, ":hash": hash_text(text)})?;
+        tx.execute(ts_sql, named_params! {":content_id": id})
+    });
 
-pub fn update_note_by_note_id(cpo: &dyn PathOperations, id: usize, text: &str) -> bool {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let sql = "UPDATE content SET body = :body WHERE content_id = (SELECT content_id FROM notes WHERE note_id = :note_id);";
-    let stmt = conn.prepare(sql);
-    let result = stmt.unwrap().execute(named_params! {":note_id": id, ":body": &text}).unwrap();
-    update_note_ts_by_note_id(id, &conn);
+    result.unwrap_or(0) > 0
+}
 
-    result > 0
+pub fn update_note_by_note_id(store: &dyn NoteStore, id: usize, text: &str) -> bool {
+    store.update_body(NoteId(id), text)
 }
 
-pub fn update_title_by_content_id(cpo: &dyn PathOperations, id: &str, text: &str) -> bool {
+pub fn update_title_by_content_id(store: &SqliteStore, id: &str, text: &str) -> bool {
     let title = make_text_single_line(&text);
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
     let sql = "UPDATE notes SET title = :title, updated = CURRENT_TIMESTAMP WHERE content_id = :content_id;";
-    let stmt = conn.prepare(sql);
+    let stmt = store.conn().prepare(sql);
     let result = stmt.unwrap().execute(named_params! {":content_id": id, ":title": &title}).unwrap();
 
     result > 0
 }
 
-pub fn delete_note_by_id(cpo: &dyn PathOperations, id: usize, force: bool) -> bool {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let sql = match force {
-        true => {
-            "DELETE FROM notes WHERE note_id = :note_id;"
-        }
-        false => {
-            "DELETE FROM notes WHERE note_id = :note_id AND protected is FALSE;"
-        }
-    };
-    let stmt = conn.prepare(sql);
-    let result = stmt.unwrap().execute(named_params! {":note_id": id}).unwrap();
-
-    result > 0
+pub fn delete_note_by_id(store: &dyn NoteStore, id: usize, force: bool) -> bool {
+    store.delete_note(NoteId(id), force)
 }
 
-pub fn empty_trash(cpo: &dyn PathOperations) -> bool {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let sql = "DELETE FROM notes WHERE trashed is TRUE;";
-    let result = conn.execute(&sql, ()).unwrap();
+pub fn empty_trash(store: &dyn NoteStore) -> bool {
+    store.empty_trash() > 0
+}
 
-    result > 0
+pub fn set_note_trash(store: &dyn NoteStore, id: usize, trash_state: bool) -> bool {
+    store.set_trashed(NoteId(id), trash_state)
 }
 
-pub fn set_note_trash(cpo: &dyn PathOperations, id: usize, trash_state: bool) -> bool {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let sql = "UPDATE notes SET trashed = :trashed WHERE note_id = :note_id;";
-    let stmt = conn.prepare(sql);
-    let result = stmt.unwrap().execute(named_params! {":note_id": id, ":trashed": trash_state}).unwrap();
+pub fn set_note_pinned(store: &SqliteStore, id: NoteId, pinned: bool) -> bool {
+    let sql = "UPDATE notes SET pinned = :pinned WHERE note_id = :note_id;";
+    let result = store.conn().execute(sql, named_params! {":note_id": id.0, ":pinned": pinned}).unwrap();
 
     result > 0
 }
 
-pub fn dump_notes(cpo: &dyn PathOperations, protected: bool) -> Vec<NoteView> {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let sql = "SELECT note_id, title, created, updated, notes.content_id, content.body from \
-    notes JOIN content on notes.content_id = content.content_id WHERE protected is :protected;";
-    let mut stmt = conn.prepare(sql).unwrap();
+pub fn dump_notes(store: &SqliteStore, protected: bool) -> Vec<Note> {
+    dump_matching_notes(store, protected, &Query::default())
+}
 
-    let result_set = stmt.query_map(named_params! {":protected": protected},|row| {
-        Ok(NoteView{
-            note_id: row.get(0)?,
-            title: row.get(1)?,
-            created: row.get(2)?,
-            updated: row.get(3)?,
-            content_id: row.get(4)?,
-            body: row.get(5)?,
-        })
-    }).unwrap();
-
-    let mut results = vec![];
-    for result in result_set {
-        match result {
-            Ok(res) => {
-                results.push(res);
-            }
-            Err(_) => {
-                results.push(NoteView{
-                    title: "Error: could not fetch result".to_string(),
-                    body: "".to_string(),
-                    note_id: 0,
-                    content_id: "".to_string(),
-                    updated: "".to_string(),
-                    created: "".to_string(),
-                })
-            }
-        }
-    }
+/**
+* Dumps the protected or unprotected notes that match a query
+*/
+pub fn dump_matching_notes(store: &SqliteStore, protected: bool, query: &Query) -> Vec<Note> {
+    let filter = format!("WHERE notes.protected is :protected AND {}", query.condition());
+    let mut params: Vec<(&str, &dyn rusqlite::ToSql)> = vec![(":protected", &protected)];
+    params.extend(query.params());
+    let mut notes = store.query_notes(&filter, params.as_slice());
+    // dumps list the notes in the order they were added
+    notes.sort_by_key(|note| note.id);
 
     if protected {
-        return decrypt_dump(&results)
+        return decrypt_dump(store, &notes)
     }
 
-    results
+    notes
 }
 
-pub fn get_summary(cpo: &dyn PathOperations) -> SummaryStats {
-    let largest_note_sql = "SELECT note_id, title, content.content_id, \
-    MAX(length(body)) from content JOIN notes on content.content_id = notes.content_id;";
-    let stalest_note_sql = "SELECT note_id, title, content_id, MIN(updated) from notes;";
-    let freshest_note_sql = "SELECT note_id, title, content_id, MAX(updated) from notes;";
-    let total_trashed_sql = "SELECT (SELECT COUNT(note_id) from notes), \
-    (SELECT COUNT(note_id) from notes WHERE trashed is TRUE);";
-
-    let mut errors = vec![];
-
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let mut largest_stmt = conn.prepare(largest_note_sql).unwrap();
-    let largest_result = match largest_stmt.query_row([], |row| {
-        Ok(LargeNoteSummary{
-            note_id: row.get(0)?,
-            title: row.get(1)?,
-            content_id: row.get(2)?,
-            content_size: row.get(3)?,
-        })
-    }) {
-        Ok(data) => {
-            Some(data)
-        }
-        Err(_) => {
-            errors.push("Error querying to determine largest note for summary.");
-            None
-        }
-    };
-
-    let mut stalest_stmt = conn.prepare(stalest_note_sql).unwrap();
-    let stalest_result = match stalest_stmt.query_row([], |row| {
-        Ok(NoteView{
-            note_id: row.get(0)?,
-            title: row.get(1)?,
-            body: "".to_string(),
-            content_id: row.get(2)?,
-            updated: row.get(3)?,
-            created: "".to_string(),
-        })
-    }) {
-        Ok(data) => {
-            Some(data)
-        }
-        Err(_) => {
-            errors.push("Error querying oldest note for summary.");
-            None
-        }
-    };
-
-    let mut freshest_stmt = conn.prepare(freshest_note_sql).unwrap();
-    let freshest_result = match freshest_stmt.query_row([], |row| {
-        Ok(NoteView{
-            note_id: row.get(0)?,
-            title: row.get(1)?,
-            body: "".to_string(),
-            content_id: row.get(2)?,
-            updated: row.get(3)?,
-            created: "".to_string(),
-        })
-    }) {
-        Ok(data) => {
-            Some(data)
-        }
-        Err(_) => {
-            errors.push("Error querying oldest note for summary.");
-            None
-        }
-    };
-
-    let mut total_trashed_stmt = conn.prepare(total_trashed_sql).unwrap();
-    let total_trashed_result = match total_trashed_stmt.query_row([], |row| {
-        Ok(DBStats{
-            total: row.get(0)?,
-            trashed: row.get(1)?
-        })
-    }) {
-        Ok(data) => {
-            Some(data)
-        }
-        Err(_) => {
-            errors.push("Error querying stats.");
-            None
+pub fn get_summary(store: &dyn NoteStore) -> SummaryStats {
+    match store.summary() {
+        Some(summary) => summary,
+        None => {
+            TrustyPrinter {}.print_error("Error creating summary.".to_string());
+            exit(Errors::SummaryErr as i32);
         }
-    };
-
-    if errors.len() > 0 {
-        TrustyPrinter {}.print_error("Error creating summary.".to_string());
-        exit(Errors::SummaryErr as i32);
     }
-
-    SummaryStats {
-        db_stats: total_trashed_result.unwrap(),
-        large_note_stats: largest_result.unwrap(),
-        state_note_stats: stalest_result.unwrap(),
-        fresh_note_stats: freshest_result.unwrap(),
-    }
-
 }
 
-fn get_key_val_insert_sql(table: &str) -> String {
+pub(crate) fn get_key_val_insert_sql(table: &str) -> String {
     format!("INSERT INTO {} (key, value) VALUES (:key, :value);", table)
 }
 
-fn get_key_val_select_sql(table: &str) -> String {
+pub(crate) fn get_key_val_select_sql(table: &str) -> String {
     format!("SELECT value from {} WHERE key = :key;", table)
 }
 
-fn get_key_val_update_sql(table: &str) -> String {
+pub(crate) fn get_key_val_update_sql(table: &str) -> String {
     format!("UPDATE {} SET value = :value WHERE key = :key;", table)
 }
 
-pub fn get_value_from_attr_table(cpo: &dyn PathOperations, table: &str, key: &str) -> KeyValuePair {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-    let sql = match table.to_lowercase().as_str() {
-        "app" => {
-            get_key_val_select_sql(table)
+pub fn get_value_from_attr_table(store: &dyn NoteStore, table: &str, key: &str) -> KeyValuePair {
+    match store.get_value(table, key) {
+        Some(value) => {
+            KeyValuePair {
+                key: key.to_string(),
+                value
+            }
         },
-        "config" => {
-            get_key_val_select_sql(table)
-        }
-        _ => {
+        None => {
             TrustyPrinter {}.print_error(format!("{}", "Could not get select val sql."));
             exit(Errors::KeyValSelectErr as i32)
         }
+    }
+}
+
+pub fn add_key_value(store: &dyn NoteStore, table: &str, key: &str, value: &str) -> bool {
+    if key_value_table(table).is_none() {
+        TrustyPrinter {}.print_error(format!("{}", "Could not create key val."));
+        exit(Errors::KeyValInsertErr as i32)
+    }
+
+    store.save_values(table, &[(key, value)], true)
+}
+
+pub fn update_key_value(store: &dyn NoteStore, table: &str, key: &str, value: &str) -> bool {
+    if key_value_table(table).is_none() {
+        TrustyPrinter {}.print_error(format!("{}", "Could not update key val."));
+        exit(Errors::KeyValUpdateErr as i32)
+    }
+
+    store.save_values(table, &[(key, value)], false)
+}
+
+/**
+* Adds or updates several key values at once, none of them are saved if any of them fail
+* @insert - add new keys instead of updating existing ones
+*/
+pub fn save_key_values(store: &dyn NoteStore, table: &str, pairs: &[(&str, &str)], insert: bool) -> bool {
+    if key_value_table(table).is_none() {
+        TrustyPrinter {}.print_error("Could not save key vals.".to_string());
+        exit(if insert { Errors::KeyValInsertErr } else { Errors::KeyValUpdateErr } as i32)
+    }
+
+    store.save_values(table, pairs, insert)
+}
+
+pub fn update_protected_flag(store: &SqliteStore, note_id: NoteId, protected: bool) -> bool {
+   let sql = "UPDATE notes set protected = :protected WHERE note_id = :note_id;";
+    let body_sql = "SELECT body FROM content WHERE content_id = (SELECT content_id FROM notes WHERE note_id = :note_id);";
+    let hash_sql = "UPDATE content SET hash = :hash WHERE content_id = (SELECT content_id FROM notes WHERE note_id = :note_id);";
+
+    let result = store.with_transaction(|tx| {
+        let code = tx.execute(sql, named_params! {
+            ":note_id": note_id.0,
+            ":protected": protected
+        })?;
+
+        // encrypted bodies are never hashed, decrypted ones are hashed again for duplicate checks
+        let hash = if protected { None } else {
+            let body: Option<String> = tx.query_row(body_sql, named_params! {":note_id": note_id.0}, |row| row.get(0)).unwrap_or(None);
+            body.map(|body| hash_text(&body))
+        };
+        tx.execute(hash_sql, named_params! {":note_id": note_id.0, ":hash": hash})?;
+
+        Ok(code)
+    });
+
+    result.unwrap_or(0) > 0
+}
+
+/**
+* Hashes the body of every unprotected note that is missing a hash
+*/
+pub fn backfill_content_hashes(conn: &Connection) {
+    let sql = "SELECT content.content_id, content.body FROM content JOIN notes on notes.content_id = content.content_id \
+    WHERE content.hash IS NULL AND notes.protected IS FALSE;";
+    let mut stmt = conn.prepare(sql).unwrap();
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<usize, String>(0)?, row.get::<usize, Option<String>>(1)?.unwrap_or_default()))
+    }).unwrap().filter_map(|res| res.ok()).collect::<Vec<(String, String)>>();
+
+    let update_sql = "UPDATE content SET hash = :hash WHERE content_id = :content_id;";
+    for (content_id, body) in rows {
+        conn.execute(update_sql, named_params! {":hash": hash_text(&body), ":content_id": content_id}).unwrap();
+    }
+}
+
+/**
+* Appends the body of the source note to the target note and removes the source note,
+* the titles and timestamps of both notes are saved in the history of the target note
+*/
+pub fn merge_notes(store: &SqliteStore, source_id: NoteId, target_id: NoteId) -> bool {
+    if source_id == target_id {
+        return false
+    }
+
+    let get_note = |note_id: NoteId| store.get_note(note_id).filter(|note| !note.protected);
+
+    let (source, target) = match (get_note(source_id), get_note(target_id)) {
+        (Some(source), Some(target)) => (source, target),
+        _ => return false
     };
 
-    let mut stmt = conn.prepare(&sql).unwrap();
-    let result = match stmt.query_row(named_params! {":key": key},|row| {
-        Ok(KeyValuePair {
-            key: key.to_string(),
-            value: row.get(0)?
-        })
-    }) {
-        Ok(data) => {
-            data
-        },
-        Err(_) => {
-            TrustyPrinter {}.print_error(format!("{}", "Could not get select val sql."));
-            exit(Errors::KeyValSelectErr as i32)
+    let merged_body = format!("{}\n\n{}", target.body, source.body);
+    let history_sql = "INSERT INTO history (content_id, title, created, updated, event, recorded) \
+    VALUES (:content_id, :title, :created, :updated, :event, CURRENT_TIMESTAMP);";
+    let result = store.with_transaction(|tx| {
+        for (note, event) in [(&target, "merge_target"), (&source, "merged_from")] {
+            tx.execute(history_sql, named_params! {
+                ":content_id": target.content_id.as_str(),
+                ":title": note.title,
+                ":created": to_sqlite_timestamp(note.created),
+                ":updated": to_sqlite_timestamp(note.updated),
+                ":event": event,
+            })?;
         }
-    };
-    result
+        tx.execute("UPDATE content SET body = :body, hash = :hash WHERE content_id = :content_id;", named_params! {
+            ":body": merged_body,
+            ":hash": hash_text(&merged_body),
+            ":content_id": target.content_id.as_str(),
+        })?;
+        tx.execute("UPDATE notes SET updated = CURRENT_TIMESTAMP WHERE content_id = :content_id;", named_params! {":content_id": target.content_id.as_str()})?;
+        tx.execute("DELETE FROM notes WHERE note_id = :note_id;", named_params! {":note_id": source_id.0})?;
+        tx.execute("DELETE FROM content WHERE content_id = :content_id;", named_params! {":content_id": source.content_id.as_str()})
+    });
+
+    result.is_ok()
+}
+
+pub fn get_note_history(store: &SqliteStore, note_id: usize) -> Vec<HistoryEntry> {
+    let sql = "SELECT history.title, history.created, history.updated, history.event, history.recorded, notes.protected \
+    FROM history JOIN notes on notes.content_id = history.content_id WHERE notes.note_id = :note_id ORDER BY history_id;";
+    let mut stmt = store.conn().prepare(sql).unwrap();
+    let results = stmt.query_map(named_params! {":note_id": note_id}, |row| {
+        let is_protected: bool = row.get(5)?;
+        let title: String = if is_protected { "🔒 ENCRYPTED".to_string() } else {row.get(0).unwrap_or("NULL".to_string())};
+        Ok(HistoryEntry {
+            title,
+            created: row.get(1)?,
+            updated: row.get(2)?,
+            event: row.get(3)?,
+            recorded: row.get(4)?,
+        })
+    }).unwrap();
+
+    results.filter_map(|res| res.ok()).collect()
 }
 
-pub fn add_key_value(cpo: &dyn PathOperations, table: &str, key: &str, value: &str) -> bool {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
+pub fn get_config_value(store: &dyn NoteStore, key: &str, default: &str) -> String {
+    store.get_value("config", key).unwrap_or(default.to_string())
+}
 
-    let sql = match table.to_lowercase().as_str() {
-        // these match tables created during setup
-        "app" => {
-            get_key_val_insert_sql(table)
-        },
-        "config" => {
-            get_key_val_insert_sql(table)
+/**
+* Like get_value_from_attr_table but returns None for a missing key instead of exiting
+*/
+pub fn get_app_value(store: &dyn NoteStore, key: &str) -> Option<String> {
+    store.get_value("app", key)
+}
+
+pub fn set_config_value(store: &dyn NoteStore, key: &str, value: &str) -> bool {
+    let insert = store.get_value("config", key).is_none();
+
+    store.save_values("config", &[(key, value)], insert)
+}
+
+/**
+* @due - a local date or datetime like 2024-07-01 or 2024-07-01 09:30, None clears the due date
+*/
+pub fn set_note_due(store: &SqliteStore, note_id: NoteId, due: Option<&str>) -> bool {
+    let conn = store.conn();
+    // due dates are saved as UTC like the created and updated timestamps, invalid dates convert to NULL
+    let code = match due {
+        Some(due) => {
+            let sql = "UPDATE notes SET due = datetime(:due, 'utc') WHERE note_id = :note_id AND datetime(:due, 'utc') IS NOT NULL;";
+            conn.execute(sql, named_params! {":note_id": note_id.0, ":due": due}).unwrap_or(0)
         }
-        _ => {
-            TrustyPrinter {}.print_error(format!("{}", "Could not create key val."));
-            exit(Errors::KeyValInsertErr as i32)
+        None => {
+            let sql = "UPDATE notes SET due = NULL WHERE note_id = :note_id;";
+            conn.execute(sql, named_params! {":note_id": note_id.0}).unwrap_or(0)
         }
     };
 
-    let stmt = conn.prepare(&sql);
-    let code = stmt.unwrap().execute(named_params! {
-        ":key": key,
-        ":value" : value}).unwrap_or(0);
-
     code > 0
 }
 
-// @todo refactor to reuse/simplify key_val CRUD func logic
-pub fn update_key_value(cpo: &dyn PathOperations, table: &str, key: &str, value: &str) -> bool {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
+/**
+* @within_hours - only return overdue notes and notes due in the next n hours, None returns every note with a due date
+*/
+pub fn get_due_notes(store: &SqliteStore, within_hours: Option<u32>) -> Vec<DueNote> {
+    let sql = "SELECT note_id, title, protected, content_id, due, due <= CURRENT_TIMESTAMP \
+    FROM notes WHERE due IS NOT NULL AND trashed IS FALSE \
+    AND (:within_hours IS NULL OR due <= datetime('now', '+' || :within_hours || ' hours')) ORDER BY due;";
+    let mut stmt = store.conn().prepare(sql).unwrap();
+    let results = stmt.query_map(named_params! {":within_hours": within_hours}, |row| {
+        let is_protected: bool = row.get(2)?;
+        let title: String = if is_protected { "🔒 ENCRYPTED".to_string() } else {row.get(1).unwrap_or("NULL".to_string())};
+        Ok(DueNote {
+            note_id: NoteId(row.get(0)?),
+            title,
+            content_id: ContentId::from(row.get::<usize, String>(3)?),
+            due: parse_utc_timestamp(&row.get::<usize, String>(4)?).unwrap_or_default(),
+            overdue: row.get(5)?,
+        })
+    }).unwrap();
 
-    let sql = match table.to_lowercase().as_str() {
-        // these match tables created during setup
-        // @todo these matches are probably over kill since this is trusted code being concatenated
-        "app" => {
-            get_key_val_update_sql(table)
-        },
-        "config" => {
-            get_key_val_update_sql(table)
-        }
-        _ => {
-            TrustyPrinter {}.print_error(format!("{}", "Could not update key val."));
-            exit(Errors::KeyValUpdateErr as i32)
-        }
-    };
+    results.filter_map(|res| res.ok()).collect()
+}
 
-    // @todo this could be refactor into its own function
-    let stmt = conn.prepare(&sql);
-    let code = stmt.unwrap().execute(named_params! {
-        ":key": key,
-        ":value" : value}).unwrap_or(0);
+pub fn list_unprotected_notes(store: &SqliteStore) -> Vec<Note> {
+    let mut notes = store.query_notes("WHERE notes.protected is FALSE AND notes.trashed is FALSE", &[]);
+    notes.sort_by_key(|note| note.id);
 
-    code > 0
+    notes
 }
 
-pub fn update_protected_flag(cpo: &dyn PathOperations, note_id: usize, protected: bool) -> bool {
-    let db_path = cpo.get_trusty_db_path();
-    let conn = get_db_conn(&db_path);
-   let sql = "UPDATE notes set protected = :protected WHERE note_id = :note_id;";
+/**
+* Finds the notes that match a query, including trashed notes unless the query has a trashed: term
+*/
+pub fn query_note_ids(store: &SqliteStore, query: &Query) -> Vec<usize> {
+    let sql = format!("SELECT notes.note_id FROM notes JOIN content on notes.content_id = content.content_id WHERE {} ORDER BY notes.note_id;", query.condition());
+    let mut stmt = store.conn().prepare(&sql).unwrap();
+    let results = stmt.query_map(query.params().as_slice(), |row| row.get(0)).unwrap();
 
-    let code = conn.execute(&sql, named_params! {
-        ":note_id": note_id,
-        ":protected": protected
-    }).unwrap_or(0);
+    results.filter_map(|res| res.ok()).collect()
+}
 
-    code > 0
+pub fn get_current_timestamp(store: &SqliteStore) -> String {
+    store.conn().query_row("SELECT CURRENT_TIMESTAMP;", [], |row| row.get(0)).unwrap()
+}
+
+/**
+* Lists the notes outside of the trash that match a query, newest first
+*/
+pub fn list_matching_note_records(store: &SqliteStore, query: &Query) -> Vec<Note> {
+    store.query_notes(&format!("WHERE notes.trashed IS FALSE AND {}", query.condition()), query.params().as_slice())
 }
 
-pub fn trash_note(cpo: &dyn PathOperations, id: usize) -> bool {
-    set_note_trash(cpo, id, true)
+pub fn trash_note(store: &dyn NoteStore, id: usize) -> bool {
+    set_note_trash(store, id, true)
 }
 
-pub fn restore_note(cpo: &dyn PathOperations, id: usize) -> bool {
-    set_note_trash(cpo, id, false)
+pub fn restore_note(store: &dyn NoteStore, id: usize) -> bool {
+    set_note_trash(store, id, false)
 }
 
-pub fn delete_note(cpo: &dyn PathOperations, note_id: usize, force: bool) -> bool {
-    let result = delete_note_by_id(cpo, note_id, force);
+pub fn delete_note(store: &dyn NoteStore, note_id: NoteId, force: bool) -> bool {
+    let result = store.delete_note(note_id, force);
     if result {
         TrustyPrinter {}.println(format!("Note: {} deleted.", note_id))
     } else {
@@ -0,0 +1,635 @@
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, named_params, Transaction};
+
+use crate::content_type::ContentType;
+use crate::dates::{parse_utc_timestamp, to_sqlite_timestamp};
+use crate::note::{ContentId, NewNote, Note, NoteId};
+use crate::setup::{get_db_conn, PathOperations};
+use crate::sql::{DBStats, get_key_val_insert_sql, get_key_val_select_sql, get_key_val_update_sql, LargeNoteSummary, SummaryStats};
+use crate::utils::hash_text;
+
+// the key value tables created during setup
+const KEY_VALUE_TABLES: [&str; 2] = ["app", "config"];
+
+/**
+* Where notes, app state and config are kept. Titles and bodies are stored as given, protected notes are
+* encrypted before they are saved and decrypted after they are read.
+*/
+pub trait NoteStore {
+    /**
+    * Saves a new note and makes it the last touched note, returns the id of the new note
+    * @data_key - the wrapped key of a protected note, see security::seal_note
+    */
+    fn insert_note(&self, title: &str, body: &str, data_key: Option<&str>) -> Option<NoteId>;
+    /**
+    * Saves a note made with Note::builder and returns it as it was stored
+    */
+    fn create_note(&self, note: &NewNote) -> Option<Note> {
+        let id = self.insert_note(&note.title, &note.body, note.data_key.as_deref())?;
+        if note.due.is_some() {
+            self.set_due(id, note.due);
+        }
+        if let Some(content_type) = note.content_type {
+            self.set_content_type(id, content_type);
+        }
+
+        self.get_note(id)
+    }
+    fn get_note(&self, id: NoteId) -> Option<Note>;
+    /**
+    * Lists the notes in or out of the trash, newest first
+    */
+    fn list_notes(&self, trashed: bool) -> Vec<Note>;
+    fn update_body(&self, id: NoteId, body: &str) -> bool;
+    fn update_title(&self, id: NoteId, title: &str) -> bool;
+    fn set_trashed(&self, id: NoteId, trashed: bool) -> bool;
+    fn set_due(&self, id: NoteId, due: Option<DateTime<Utc>>) -> bool;
+    /**
+    * Bumps the updated time so that a sync carries the new type to the other database
+    */
+    fn set_content_type(&self, id: NoteId, content_type: ContentType) -> bool;
+    /**
+    * Protected notes are only deleted with force
+    */
+    fn delete_note(&self, id: NoteId, force: bool) -> bool;
+    /**
+    * Deletes every trashed note, returns how many were deleted
+    */
+    fn empty_trash(&self) -> usize;
+    /**
+    * Finds notes with a title or body that contains the text, ignoring case. Protected notes are never searched.
+    */
+    fn search_notes(&self, text: &str) -> Vec<NoteId>;
+    /**
+    * Finds the unprotected notes with exactly this body
+    */
+    fn find_duplicates(&self, body: &str) -> Vec<NoteId>;
+    fn last_touched(&self) -> Option<NoteId>;
+    fn set_last_touched(&self, id: NoteId) -> bool;
+    /**
+    * @table - app or config
+    */
+    fn get_value(&self, table: &str, key: &str) -> Option<String>;
+    /**
+    * Adds or updates several key values at once, none of them are saved if any of them fail
+    * @insert - add new keys instead of updating existing ones
+    */
+    fn save_values(&self, table: &str, pairs: &[(&str, &str)], insert: bool) -> bool;
+    /**
+    * Replaces the wrapped data keys of protected notes and updates app values like the boss key,
+    * nothing is saved if any of them fail
+    */
+    fn save_data_keys(&self, data_keys: &[(NoteId, String)], app_values: &[(&str, &str)]) -> bool;
+    /**
+    * None when there are no notes
+    */
+    fn summary(&self) -> Option<SummaryStats>;
+}
+
+pub(crate) fn key_value_table(table: &str) -> Option<&'static str> {
+    let table = table.to_lowercase();
+    KEY_VALUE_TABLES.into_iter().find(|name| *name == table)
+}
+
+/**
+* Keeps notes in a tRusty database, every call uses the same connection
+*/
+pub struct SqliteStore {
+    conn: Connection
+}
+
+impl SqliteStore {
+    pub fn open(cpo: &dyn PathOperations) -> SqliteStore {
+        SqliteStore { conn: get_db_conn(&cpo.get_trusty_db_path()) }
+    }
+
+    pub fn from_connection(conn: Connection) -> SqliteStore {
+        SqliteStore { conn }
+    }
+
+    /**
+    * For queries the NoteStore trait does not cover, ex: the query language, history and sync
+    */
+    pub(crate) fn conn(&self) -> &Connection {
+        &self.conn
+    }
+
+    /**
+    * Runs every statement in the closure as one transaction, nothing is saved if the closure returns an error
+    * @fun - is passed the open transaction, use it in place of a connection
+    */
+    pub fn with_transaction<T, F>(&self, fun: F) -> rusqlite::Result<T> where F: FnOnce(&Transaction) -> rusqlite::Result<T> {
+        let tx = self.conn.unchecked_transaction()?;
+        // an uncommitted transaction is rolled back when it is dropped
+        let result = fun(&tx)?;
+        tx.commit()?;
+
+        Ok(result)
+    }
+
+    /**
+    * Notes as they are stored, protected notes keep their encrypted title and body
+    * @filter - a WHERE clause over the notes and content tables, ex: WHERE notes.note_id = :note_id
+    */
+    pub(crate) fn query_notes(&self, filter: &str, params: &[(&str, &dyn rusqlite::ToSql)]) -> Vec<Note> {
+        let sql = format!("SELECT notes.note_id, notes.content_id, notes.title, content.body, notes.protected, notes.trashed, \
+        notes.created, notes.updated, notes.due, notes.content_type, notes.data_key FROM notes JOIN content on notes.content_id = content.content_id {} ORDER BY notes.updated DESC;", filter);
+        let mut stmt = self.conn.prepare(&sql).unwrap();
+        let results = stmt.query_map(params, |row| {
+            let timestamp = |index: usize| -> rusqlite::Result<Option<DateTime<Utc>>> {
+                Ok(row.get::<usize, Option<String>>(index)?.as_deref().and_then(parse_utc_timestamp))
+            };
+
+            Ok(Note {
+                id: NoteId(row.get(0)?),
+                content_id: ContentId::from(row.get::<usize, String>(1)?),
+                title: row.get::<usize, Option<String>>(2)?.unwrap_or_default(),
+                body: row.get::<usize, Option<String>>(3)?.unwrap_or_default(),
+                protected: row.get(4)?,
+                trashed: row.get::<usize, Option<bool>>(5)?.unwrap_or(false),
+                created: timestamp(6)?.unwrap_or_default(),
+                updated: timestamp(7)?.unwrap_or_default(),
+                due: timestamp(8)?,
+                content_type: row.get::<usize, Option<String>>(9)?.as_deref().and_then(ContentType::parse),
+                data_key: row.get(10)?,
+            })
+        }).unwrap();
+
+        results.filter_map(|res| res.ok()).collect()
+    }
+
+    fn query_note_ids(&self, sql: &str, params: &[(&str, &dyn rusqlite::ToSql)]) -> Vec<NoteId> {
+        let mut stmt = self.conn.prepare(sql).unwrap();
+        let results = stmt.query_map(params, |row| Ok(NoteId(row.get(0)?))).unwrap();
+
+        results.filter_map(|res| res.ok()).collect()
+    }
+
+    fn query_summary(&self) -> rusqlite::Result<SummaryStats> {
+        let largest_note_sql = "SELECT note_id, title, content.content_id, \
+        MAX(length(body)) from content JOIN notes on content.content_id = notes.content_id;";
+        let total_trashed_sql = "SELECT (SELECT COUNT(note_id) from notes), \
+        (SELECT COUNT(note_id) from notes WHERE trashed is TRUE);";
+
+        let large_note_stats = self.conn.query_row(largest_note_sql, [], |row| {
+            Ok(LargeNoteSummary {
+                note_id: NoteId(row.get(0)?),
+                title: row.get(1)?,
+                content_id: ContentId::from(row.get::<usize, String>(2)?),
+                content_size: row.get(3)?,
+            })
+        })?;
+        // the note updated first or last, ex: ASC for the stalest note
+        let note_by_update = |order: &str| {
+            let filter = format!("WHERE notes.note_id = (SELECT note_id FROM notes ORDER BY updated {} LIMIT 1)", order);
+            self.query_notes(&filter, &[]).pop().ok_or(rusqlite::Error::QueryReturnedNoRows)
+        };
+        let state_note_stats = note_by_update("ASC")?;
+        let fresh_note_stats = note_by_update("DESC")?;
+        let db_stats = self.conn.query_row(total_trashed_sql, [], |row| {
+            Ok(DBStats {
+                total: row.get(0)?,
+                trashed: row.get(1)?
+            })
+        })?;
+
+        Ok(SummaryStats { db_stats, large_note_stats, state_note_stats, fresh_note_stats })
+    }
+}
+
+impl NoteStore for SqliteStore {
+    fn insert_note(&self, title: &str, body: &str, data_key: Option<&str>) -> Option<NoteId> {
+        let content_id = ContentId::generate();
+        let protected = data_key.is_some();
+        let note_insert = "INSERT INTO notes (title, protected, created, updated, content_id, content_type, data_key) \
+        VALUES (:title, :protected, CURRENT_TIMESTAMP, CURRENT_TIMESTAMP, :content_id, :content_type, :data_key);";
+        let content_insert = "INSERT INTO content (content_id, body, hash) VALUES (:content_id, :body, :hash);";
+        let hash = if protected { None } else { Some(hash_text(body)) };
+        // the type of a protected note is detected after it is decrypted so that it does not say anything about the note
+        let content_type = if protected { None } else { Some(ContentType::detect(body).as_str()) };
+
+        let last_inserted_sql = "UPDATE app SET value = (SELECT last_insert_rowid()) WHERE key = 'last_touched';";
+
+        // The integrity of these 2 inserts needs to be guaranteed.
+        let result = self.with_transaction(|tx| {
+            tx.execute(content_insert, named_params! {
+                ":content_id": content_id.as_str(),
+                ":body": body,
+                ":hash": hash,
+            })?;
+
+            tx.execute(note_insert, named_params! {
+                ":title": title,
+                ":protected": protected,
+                ":content_id": content_id.as_str(),
+                ":content_type": content_type,
+                ":data_key": data_key,
+            })?;
+
+            let note_id = NoteId(tx.last_insert_rowid() as usize);
+            tx.execute(last_inserted_sql, ())?;
+            Ok(note_id)
+        });
+
+        result.ok()
+    }
+
+    fn get_note(&self, id: NoteId) -> Option<Note> {
+        self.query_notes("WHERE notes.note_id = :note_id", named_params! {":note_id": id.0}).into_iter().next()
+    }
+
+    fn list_notes(&self, trashed: bool) -> Vec<Note> {
+        self.query_notes("WHERE notes.trashed is :trashed", named_params! {":trashed": trashed})
+    }
+
+    fn update_body(&self, id: NoteId, body: &str) -> bool {
+        let sql = "UPDATE content SET body = :body, \
+        hash = CASE WHEN (SELECT protected FROM notes WHERE note_id = :note_id) THEN NULL ELSE :hash END \
+        WHERE content_id = (SELECT content_id FROM notes WHERE note_id = :note_id);";
+        let ts_sql = "UPDATE notes SET updated = CURRENT_TIMESTAMP WHERE note_id = :note_id;";
+        let result = self.with_transaction(|tx| {
+            let count = tx.execute(sql, named_params! {":note_id": id.0, ":body": body, ":hash": hash_text(body)})?;
+            tx.execute(ts_sql, named_params! {":note_id": id.0})?;
+            Ok(count)
+        });
+
+        result.unwrap_or(0) > 0
+    }
+
+    fn update_title(&self, id: NoteId, title: &str) -> bool {
+        let sql = "UPDATE notes SET title = :title, updated = CURRENT_TIMESTAMP WHERE note_id = :note_id;";
+
+        self.conn.execute(sql, named_params! {":note_id": id.0, ":title": title}).unwrap_or(0) > 0
+    }
+
+    fn set_trashed(&self, id: NoteId, trashed: bool) -> bool {
+        let sql = "UPDATE notes SET trashed = :trashed, updated = CURRENT_TIMESTAMP WHERE note_id = :note_id;";
+
+        self.conn.execute(sql, named_params! {":note_id": id.0, ":trashed": trashed}).unwrap_or(0) > 0
+    }
+
+    fn set_due(&self, id: NoteId, due: Option<DateTime<Utc>>) -> bool {
+        let sql = "UPDATE notes SET due = :due WHERE note_id = :note_id;";
+
+        self.conn.execute(sql, named_params! {":note_id": id.0, ":due": due.map(to_sqlite_timestamp)}).unwrap_or(0) > 0
+    }
+
+    fn set_content_type(&self, id: NoteId, content_type: ContentType) -> bool {
+        let sql = "UPDATE notes SET content_type = :content_type, updated = CURRENT_TIMESTAMP WHERE note_id = :note_id;";
+
+        self.conn.execute(sql, named_params! {":note_id": id.0, ":content_type": content_type.as_str()}).unwrap_or(0) > 0
+    }
+
+    fn delete_note(&self, id: NoteId, force: bool) -> bool {
+        let sql = match force {
+            true => "DELETE FROM notes WHERE note_id = :note_id;",
+            false => "DELETE FROM notes WHERE note_id = :note_id AND protected is FALSE;"
+        };
+
+        self.conn.execute(sql, named_params! {":note_id": id.0}).unwrap_or(0) > 0
+    }
+
+    fn empty_trash(&self) -> usize {
+        self.conn.execute("DELETE FROM notes WHERE trashed is TRUE;", ()).unwrap_or(0)
+    }
+
+    fn search_notes(&self, text: &str) -> Vec<NoteId> {
+        let sql = "SELECT note_id FROM notes JOIN content on notes.content_id = content.content_id \
+        WHERE notes.protected IS FALSE AND (instr(lower(notes.title), lower(:text)) > 0 OR instr(lower(content.body), lower(:text)) > 0) \
+        ORDER BY note_id;";
+
+        self.query_note_ids(sql, named_params! {":text": text})
+    }
+
+    fn find_duplicates(&self, body: &str) -> Vec<NoteId> {
+        let sql = "SELECT note_id FROM notes JOIN content on notes.content_id = content.content_id \
+        WHERE content.hash = :hash AND notes.protected IS FALSE ORDER BY note_id;";
+
+        self.query_note_ids(sql, named_params! {":hash": hash_text(body)})
+    }
+
+    fn last_touched(&self) -> Option<NoteId> {
+        self.get_value("app", "last_touched")?.parse().ok()
+    }
+
+    fn set_last_touched(&self, id: NoteId) -> bool {
+        self.save_values("app", &[("last_touched", &id.to_string())], false)
+    }
+
+    fn get_value(&self, table: &str, key: &str) -> Option<String> {
+        let sql = get_key_val_select_sql(key_value_table(table)?);
+
+        self.conn.query_row(&sql, named_params! {":key": key}, |row| row.get(0)).ok()
+    }
+
+    fn save_values(&self, table: &str, pairs: &[(&str, &str)], insert: bool) -> bool {
+        let Some(table) = key_value_table(table) else {
+            return false
+        };
+        let sql = if insert { get_key_val_insert_sql(table) } else { get_key_val_update_sql(table) };
+
+        let result = self.with_transaction(|tx| {
+            for (key, value) in pairs {
+                if tx.execute(&sql, named_params! {":key": key, ":value": value})? == 0 {
+                    return Err(rusqlite::Error::QueryReturnedNoRows)
+                }
+            }
+            Ok(())
+        });
+
+        result.is_ok()
+    }
+
+    fn save_data_keys(&self, data_keys: &[(NoteId, String)], app_values: &[(&str, &str)]) -> bool {
+        let key_sql = "UPDATE notes SET data_key = :data_key WHERE note_id = :note_id AND protected is TRUE;";
+        let value_sql = get_key_val_update_sql("app");
+
+        let result = self.with_transaction(|tx| {
+            for (id, data_key) in data_keys {
+                if tx.execute(key_sql, named_params! {":note_id": id.0, ":data_key": data_key})? == 0 {
+                    return Err(rusqlite::Error::QueryReturnedNoRows)
+                }
+            }
+            for (key, value) in app_values {
+                if tx.execute(&value_sql, named_params! {":key": key, ":value": value})? == 0 {
+                    return Err(rusqlite::Error::QueryReturnedNoRows)
+                }
+            }
+            Ok(())
+        });
+
+        result.is_ok()
+    }
+
+    fn summary(&self) -> Option<SummaryStats> {
+        self.query_summary().ok()
+    }
+}
+
+/**
+* Keeps notes in memory, ex: for tests and scripts that should not touch your notes
+*/
+pub struct MemoryStore {
+    notes: RefCell<BTreeMap<NoteId, Note>>,
+    values: RefCell<BTreeMap<(&'static str, String), String>>,
+    last_id: Cell<usize>
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        let store = MemoryStore { notes: RefCell::new(BTreeMap::new()), values: RefCell::new(BTreeMap::new()), last_id: Cell::new(0) };
+        // matches the app state a new database starts with
+        store.save_values("app", &[("last_touched", "0")], true);
+        store
+    }
+
+    // timestamps are kept to the second like the SQLite ones
+    fn now() -> DateTime<Utc> {
+        parse_utc_timestamp(&to_sqlite_timestamp(Utc::now())).unwrap_or_default()
+    }
+
+    /**
+    * Changes a note, returns false when the note does not exist
+    * @touch - bump the updated time
+    */
+    fn update<F>(&self, id: NoteId, touch: bool, fun: F) -> bool where F: FnOnce(&mut Note) {
+        match self.notes.borrow_mut().get_mut(&id) {
+            Some(note) => {
+                fun(note);
+                if touch {
+                    note.updated = MemoryStore::now();
+                }
+                true
+            }
+            None => false
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl NoteStore for MemoryStore {
+    fn insert_note(&self, title: &str, body: &str, data_key: Option<&str>) -> Option<NoteId> {
+        let protected = data_key.is_some();
+        // like AUTOINCREMENT ids are never reused
+        let id = NoteId(self.last_id.get() + 1);
+        self.last_id.set(id.0);
+        let now = MemoryStore::now();
+        self.notes.borrow_mut().insert(id, Note {
+            id,
+            content_id: ContentId::generate(),
+            title: title.to_string(),
+            body: body.to_string(),
+            protected,
+            trashed: false,
+            created: now,
+            updated: now,
+            due: None,
+            content_type: if protected { None } else { Some(ContentType::detect(body)) },
+            data_key: data_key.map(|key| key.to_string()),
+        });
+        self.set_last_touched(id);
+
+        Some(id)
+    }
+
+    fn get_note(&self, id: NoteId) -> Option<Note> {
+        self.notes.borrow().get(&id).cloned()
+    }
+
+    fn list_notes(&self, trashed: bool) -> Vec<Note> {
+        let mut notes = self.notes.borrow().values().filter(|note| note.trashed == trashed).cloned().collect::<Vec<Note>>();
+        notes.sort_by(|a, b| b.updated.cmp(&a.updated).then(b.id.cmp(&a.id)));
+        notes
+    }
+
+    fn update_body(&self, id: NoteId, body: &str) -> bool {
+        self.update(id, true, |note| note.body = body.to_string())
+    }
+
+    fn update_title(&self, id: NoteId, title: &str) -> bool {
+        self.update(id, true, |note| note.title = title.to_string())
+    }
+
+    fn set_trashed(&self, id: NoteId, trashed: bool) -> bool {
+        self.update(id, true, |note| note.trashed = trashed)
+    }
+
+    fn set_due(&self, id: NoteId, due: Option<DateTime<Utc>>) -> bool {
+        self.update(id, false, |note| note.due = due)
+    }
+
+    fn set_content_type(&self, id: NoteId, content_type: ContentType) -> bool {
+        self.update(id, true, |note| note.content_type = Some(content_type))
+    }
+
+    fn delete_note(&self, id: NoteId, force: bool) -> bool {
+        let mut notes = self.notes.borrow_mut();
+        match notes.get(&id) {
+            Some(note) if force || !note.protected => notes.remove(&id).is_some(),
+            _ => false
+        }
+    }
+
+    fn empty_trash(&self) -> usize {
+        let mut notes = self.notes.borrow_mut();
+        let count = notes.len();
+        notes.retain(|_, note| !note.trashed);
+        count - notes.len()
+    }
+
+    fn search_notes(&self, text: &str) -> Vec<NoteId> {
+        let text = text.to_lowercase();
+        self.notes.borrow().values()
+            .filter(|note| !note.protected && (note.title.to_lowercase().contains(&text) || note.body.to_lowercase().contains(&text)))
+            .map(|note| note.id)
+            .collect()
+    }
+
+    fn find_duplicates(&self, body: &str) -> Vec<NoteId> {
+        self.notes.borrow().values().filter(|note| !note.protected && note.body == body).map(|note| note.id).collect()
+    }
+
+    fn last_touched(&self) -> Option<NoteId> {
+        self.get_value("app", "last_touched")?.parse().ok()
+    }
+
+    fn set_last_touched(&self, id: NoteId) -> bool {
+        self.save_values("app", &[("last_touched", &id.to_string())], false)
+    }
+
+    fn get_value(&self, table: &str, key: &str) -> Option<String> {
+        self.values.borrow().get(&(key_value_table(table)?, key.to_string())).cloned()
+    }
+
+    fn save_values(&self, table: &str, pairs: &[(&str, &str)], insert: bool) -> bool {
+        let Some(table) = key_value_table(table) else {
+            return false
+        };
+        let mut values = self.values.borrow_mut();
+        // inserts fail on existing keys and updates on missing ones, like the SQL statements
+        if pairs.iter().any(|(key, _)| values.contains_key(&(table, key.to_string())) == insert) {
+            return false
+        }
+        for (key, value) in pairs {
+            values.insert((table, key.to_string()), value.to_string());
+        }
+
+        true
+    }
+
+    fn save_data_keys(&self, data_keys: &[(NoteId, String)], app_values: &[(&str, &str)]) -> bool {
+        let is_protected = |id: &NoteId| self.notes.borrow().get(id).is_some_and(|note| note.protected);
+        if !data_keys.iter().all(|(id, _)| is_protected(id)) || !self.save_values("app", app_values, false) {
+            return false
+        }
+        for (id, data_key) in data_keys {
+            self.update(*id, false, |note| note.data_key = Some(data_key.to_string()));
+        }
+
+        true
+    }
+
+    fn summary(&self) -> Option<SummaryStats> {
+        let notes = self.notes.borrow();
+        let largest = notes.values().max_by_key(|note| note.body.chars().count())?;
+        let stalest = notes.values().min_by(|a, b| a.updated.cmp(&b.updated))?;
+        let freshest = notes.values().max_by(|a, b| a.updated.cmp(&b.updated))?;
+
+        Some(SummaryStats {
+            db_stats: DBStats { total: notes.len() as i32, trashed: notes.values().filter(|note| note.trashed).count() as i32 },
+            large_note_stats: LargeNoteSummary {
+                note_id: largest.id,
+                title: largest.title.to_string(),
+                content_id: largest.content_id.clone(),
+                content_size: largest.body.chars().count(),
+            },
+            state_note_stats: stalest.clone(),
+            fresh_note_stats: freshest.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::content_type::ContentType;
+    use crate::note::NoteId;
+    use crate::store::{MemoryStore, NoteStore};
+
+    #[test]
+    fn test_memory_store_notes() {
+        let store = MemoryStore::new();
+        assert_eq!(store.last_touched(), Some(NoteId(0)));
+        assert_eq!(store.insert_note("foo", "foo body", None), Some(NoteId(1)));
+        assert_eq!(store.insert_note("bar", "{\"bar\": 1}", None), Some(NoteId(2)));
+        assert_eq!(store.last_touched(), Some(NoteId(2)));
+        assert_eq!(store.get_note(NoteId(2)).unwrap().content_type, Some(ContentType::Json));
+
+        assert!(store.update_title(NoteId(1), "foo title"));
+        assert!(store.update_body(NoteId(1), "new body"));
+        assert!(!store.update_body(NoteId(42), "nothing"));
+        let note = store.get_note(NoteId(1)).unwrap();
+        assert_eq!((note.title.as_str(), note.body.as_str()), ("foo title", "new body"));
+
+        assert!(store.set_trashed(NoteId(1), true));
+        assert_eq!(store.list_notes(true).len(), 1);
+        assert_eq!(store.list_notes(false).iter().map(|note| note.id).collect::<Vec<NoteId>>(), vec![NoteId(2)]);
+        assert_eq!(store.empty_trash(), 1);
+        assert!(store.get_note(NoteId(1)).is_none());
+        // ids are never reused
+        assert_eq!(store.insert_note("baz", "baz body", Some("wrapped key")), Some(NoteId(3)));
+        assert!(!store.delete_note(NoteId(3), false));
+        assert!(store.delete_note(NoteId(3), true));
+    }
+
+    #[test]
+    fn test_memory_store_search() {
+        let store = MemoryStore::new();
+        store.insert_note("Deploy steps", "same body", None);
+        store.insert_note("groceries", "buy milk", None);
+        store.insert_note("secret", "same body", Some("wrapped key"));
+        store.insert_note("copy", "same body", None);
+
+        assert_eq!(store.search_notes("DEPLOY"), vec![NoteId(1)]);
+        assert_eq!(store.search_notes("Milk"), vec![NoteId(2)]);
+        // protected notes are never searched
+        assert!(store.search_notes("secret").is_empty());
+        assert_eq!(store.find_duplicates("same body"), vec![NoteId(1), NoteId(4)]);
+        assert!(store.find_duplicates("same").is_empty());
+    }
+
+    #[test]
+    fn test_memory_store_values() {
+        let store = MemoryStore::new();
+        assert!(store.save_values("config", &[("a", "1"), ("b", "2")], true));
+        // nothing is saved when one of the keys fails
+        assert!(!store.save_values("config", &[("c", "3"), ("a", "4")], true));
+        assert_eq!(store.get_value("config", "c"), None);
+        assert!(store.save_values("CONFIG", &[("a", "5")], false));
+        assert_eq!(store.get_value("config", "a").as_deref(), Some("5"));
+        assert!(!store.save_values("config", &[("missing", "5")], false));
+        assert!(!store.save_values("notes", &[("a", "1")], true));
+        assert_eq!(store.get_value("app", "a"), None);
+    }
+
+    #[test]
+    fn test_memory_store_summary() {
+        let store = MemoryStore::new();
+        assert!(store.summary().is_none());
+        store.insert_note("small", "a", None);
+        store.insert_note("large", "a much longer body", None);
+        store.set_trashed(NoteId(1), true);
+
+        let summary = store.summary().unwrap();
+        assert_eq!((summary.db_stats.total, summary.db_stats.trashed), (2, 1));
+        assert_eq!(summary.large_note_stats.title, "large");
+        assert_eq!(summary.large_note_stats.content_size, 18);
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{Connection, named_params, Transaction};
+use uuid::Uuid;
+
+use crate::db_encryption::apply_db_key;
+use crate::setup::{get_db_conn, migrate_trusty_db, PathOperations};
+use crate::utils::hash_text;
+
+// a trusty.db outside of the trusty home directory, like one on a USB drive
+struct SyncPathOperations {
+    db_path: PathBuf
+}
+
+impl PathOperations for SyncPathOperations {
+    fn get_trusty_dir(&self) -> PathBuf {
+        self.db_path.parent().unwrap_or(Path::new(".")).to_path_buf()
+    }
+    fn get_trusty_db_path(&self) -> PathBuf {
+        self.db_path.to_path_buf()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Side {
+    Local,
+    Remote
+}
+
+impl Side {
+    fn schema(&self) -> &str {
+        match self {
+            Side::Local => "main",
+            Side::Remote => "remote"
+        }
+    }
+
+    fn other(&self) -> Side {
+        match self {
+            Side::Local => Side::Remote,
+            Side::Remote => Side::Local
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SyncChangeKind {
+    Added,
+    Updated,
+    Trashed,
+    Restored,
+    Deleted,
+    ConflictCopy
+}
+
+pub struct SyncChange {
+    pub content_id: String,
+    pub title: String,
+    pub kind: SyncChangeKind
+}
+
+pub struct SyncReport {
+    // changes made to this vault
+    pub local: Vec<SyncChange>,
+    // changes made to the other database
+    pub remote: Vec<SyncChange>,
+    pub last_sync: Option<String>,
+    // protected notes that were not copied because the other database has another boss key
+    pub skipped_protected: usize
+}
+
+impl SyncReport {
+    pub(crate) fn record(&mut self, side: Side, note: &SyncNote, kind: SyncChangeKind) {
+        let change = SyncChange {
+            content_id: note.content_id.to_string(),
+            title: if note.protected { "🔒 ENCRYPTED".to_string() } else { note.title.to_string() },
+            kind,
+        };
+        match side {
+            Side::Local => self.local.push(change),
+            Side::Remote => self.remote.push(change)
+        }
+    }
+}
+
+// protected notes keep their encrypted title and body
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SyncNote {
+    pub(crate) content_id: String,
+    pub(crate) title: String,
+    pub(crate) body: String,
+    pub(crate) protected: bool,
+    pub(crate) trashed: bool,
+    pub(crate) created: String,
+    pub(crate) updated: String,
+    pub(crate) due: Option<String>,
+    pub(crate) content_type: Option<String>,
+    // the wrapped key of a protected note, it is copied with the note but a note is not changed by re-wrapping it
+    pub(crate) data_key: Option<String>
+}
+
+impl SyncNote {
+    fn same_text(&self, other: &SyncNote) -> bool {
+        self.title.eq(&other.title) && self.body.eq(&other.body) && self.protected == other.protected
+    }
+
+    pub(crate) fn same_state(&self, other: &SyncNote) -> bool {
+        self.same_text(other) && self.trashed == other.trashed && self.due == other.due && self.content_type == other.content_type
+    }
+
+    /**
+    * Describes how a note changed from an older version of itself
+    */
+    pub(crate) fn change_from(&self, old: Option<&SyncNote>) -> SyncChangeKind {
+        match old {
+            None => SyncChangeKind::Added,
+            Some(old) if !old.trashed && self.trashed && old.same_text(self) => SyncChangeKind::Trashed,
+            Some(old) if old.trashed && !self.trashed && old.same_text(self) => SyncChangeKind::Restored,
+            Some(_) => SyncChangeKind::Updated
+        }
+    }
+}
+
+pub(crate) fn read_notes(tx: &Transaction, side: Side) -> rusqlite::Result<HashMap<String, SyncNote>> {
+    let sql = format!("SELECT n.content_id, n.title, c.body, n.protected, n.trashed, n.created, n.updated, n.due, n.content_type, \
+    n.data_key FROM {schema}.notes n JOIN {schema}.content c on n.content_id = c.content_id;", schema = side.schema());
+    let mut stmt = tx.prepare(&sql)?;
+    let rows = stmt.query_map([], |row| {
+        Ok(SyncNote {
+            content_id: row.get(0)?,
+            title: row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
+            body: row.get::<usize, Option<String>>(2)?.unwrap_or_default(),
+            protected: row.get::<usize, Option<bool>>(3)?.unwrap_or(false),
+            trashed: row.get::<usize, Option<bool>>(4)?.unwrap_or(false),
+            created: row.get::<usize, Option<String>>(5)?.unwrap_or_default(),
+            updated: row.get::<usize, Option<String>>(6)?.unwrap_or_default(),
+            due: row.get(7)?,
+            content_type: row.get(8)?,
+            data_key: row.get(9)?,
+        })
+    })?;
+
+    let mut notes = HashMap::new();
+    for row in rows {
+        let note = row?;
+        notes.insert(note.content_id.to_string(), note);
+    }
+
+    Ok(notes)
+}
+
+pub(crate) fn read_tombstones(tx: &Transaction, side: Side) -> rusqlite::Result<HashMap<String, String>> {
+    let sql = format!("SELECT content_id, deleted FROM {}.tombstones;", side.schema());
+    let mut stmt = tx.prepare(&sql)?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<usize, String>(0)?, row.get::<usize, String>(1)?)))?;
+
+    rows.collect()
+}
+
+/**
+* Adds the note to one side or overwrites the note with the same content_id
+*/
+pub(crate) fn write_note(tx: &Transaction, side: Side, note: &SyncNote) -> rusqlite::Result<()> {
+    let schema = side.schema();
+    let hash = if note.protected { None } else { Some(hash_text(&note.body)) };
+    tx.execute(&format!("INSERT INTO {}.content (content_id, body, hash) VALUES (:content_id, :body, :hash) \
+    ON CONFLICT(content_id) DO UPDATE SET body = :body, hash = :hash;", schema), named_params! {
+        ":content_id": note.content_id,
+        ":body": note.body,
+        ":hash": hash,
+    })?;
+
+    let params = named_params! {
+        ":content_id": note.content_id,
+        ":title": note.title,
+        ":protected": note.protected,
+        ":trashed": note.trashed,
+        ":created": note.created,
+        ":updated": note.updated,
+        ":due": note.due,
+        ":content_type": note.content_type,
+        ":data_key": note.data_key,
+    };
+    let updated = tx.execute(&format!("UPDATE {}.notes SET title = :title, protected = :protected, trashed = :trashed, \
+    created = :created, updated = :updated, due = :due, content_type = :content_type, data_key = :data_key WHERE content_id = :content_id;", schema), params)?;
+    if updated == 0 {
+        tx.execute(&format!("INSERT INTO {}.notes (title, protected, trashed, created, updated, due, content_type, content_id, data_key) \
+        VALUES (:title, :protected, :trashed, :created, :updated, :due, :content_type, :content_id, :data_key);", schema), params)?;
+    }
+    // a note that was deleted and then edited on the other side comes back
+    tx.execute(&format!("DELETE FROM {}.tombstones WHERE content_id = :content_id;", schema), named_params! {":content_id": note.content_id})?;
+
+    Ok(())
+}
+
+pub(crate) fn delete_note(tx: &Transaction, side: Side, content_id: &str, deleted: &str) -> rusqlite::Result<()> {
+    let schema = side.schema();
+    tx.execute(&format!("DELETE FROM {}.notes WHERE content_id = :content_id;", schema), named_params! {":content_id": content_id})?;
+    tx.execute(&format!("DELETE FROM {}.content WHERE content_id = :content_id;", schema), named_params! {":content_id": content_id})?;
+    write_tombstone(tx, side, content_id, deleted)
+}
+
+fn write_tombstone(tx: &Transaction, side: Side, content_id: &str, deleted: &str) -> rusqlite::Result<()> {
+    // keep the original time so that both databases agree on when the note was deleted
+    tx.execute(&format!("INSERT OR REPLACE INTO {}.tombstones (content_id, deleted) VALUES (:content_id, :deleted);", side.schema()),
+               named_params! {":content_id": content_id, ":deleted": deleted})?;
+
+    Ok(())
+}
+
+fn get_app_id(tx: &Transaction, side: Side) -> rusqlite::Result<String> {
+    tx.query_row(&format!("SELECT value FROM {}.config WHERE key = 'trusty_app_id';", side.schema()), [], |row| row.get(0))
+}
+
+/**
+* Protected notes can only be opened in a vault with the same boss key, a vault that was not unlocked since
+* the fingerprint was added has none and is treated as different
+*/
+fn same_boss_key(tx: &Transaction) -> rusqlite::Result<bool> {
+    let local = get_value(tx, Side::Local, "app", "boss_key_fingerprint")?;
+    let remote = get_value(tx, Side::Remote, "app", "boss_key_fingerprint")?;
+
+    Ok(local.is_some() && local.eq(&remote))
+}
+
+fn get_last_sync(tx: &Transaction, side: Side, key: &str) -> rusqlite::Result<Option<String>> {
+    get_value(tx, side, "config", key)
+}
+
+fn get_value(tx: &Transaction, side: Side, table: &str, key: &str) -> rusqlite::Result<Option<String>> {
+    let sql = format!("SELECT value FROM {}.{} WHERE key = :key;", side.schema(), table);
+    match tx.query_row(&sql, named_params! {":key": key}, |row| row.get(0)) {
+        Ok(value) => Ok(Some(value)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(err) => Err(err)
+    }
+}
+
+fn set_last_sync(tx: &Transaction, side: Side, key: &str, value: &str) -> rusqlite::Result<()> {
+    let sql = format!("INSERT INTO {}.config (key, value) VALUES (:key, :value) ON CONFLICT(key) DO UPDATE SET value = :value;", side.schema());
+    tx.execute(&sql, named_params! {":key": key, ":value": value})?;
+
+    Ok(())
+}
+
+fn is_trusty_db(path: &Path) -> bool {
+    // the other database is attached with the key of this one, so it has to use the same key
+    match Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY).and_then(|conn| apply_db_key(&conn).map(|_| conn)) {
+        Ok(conn) => {
+            let sql = "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name IN ('notes', 'content', 'config');";
+            conn.query_row(sql, [], |row| row.get::<usize, usize>(0)).unwrap_or(0) == 3
+        }
+        Err(_) => false
+    }
+}
+
+/**
+* Copies a note to the side that does not have the newer version and reports it
+*/
+fn copy_note(tx: &Transaction, report: &mut SyncReport, to: Side, note: &SyncNote, old: Option<&SyncNote>) -> rusqlite::Result<()> {
+    write_note(tx, to, note)?;
+    report.record(to, note, note.change_from(old));
+
+    Ok(())
+}
+
+/**
+* Merges another trusty.db into this vault and this vault into it, in one transaction across both files.
+* Notes are matched by content_id and the newest update wins. When both sides changed the text of a note since
+* the last sync, the older version is kept on both sides as a new conflict copy. Deleted notes are removed from
+* the other side unless they were edited there after they were deleted. Protected notes are only copied when
+* both databases have the same boss key, otherwise they are skipped and counted in the report.
+*/
+pub fn sync_databases(cpo: &dyn PathOperations, remote_path: &Path) -> Result<SyncReport, String> {
+    if !remote_path.is_file() || !is_trusty_db(remote_path) {
+        return Err(format!("{} is not a tRusty database.", remote_path.display()))
+    }
+    let local_path = cpo.get_trusty_db_path();
+    let same_file = match (fs::canonicalize(&local_path), fs::canonicalize(remote_path)) {
+        (Ok(local), Ok(remote)) => local.eq(&remote),
+        _ => false
+    };
+    if same_file {
+        return Err("You cannot sync a database with itself.".to_string())
+    }
+    // the other database may have been made by an older version
+    migrate_trusty_db(&SyncPathOperations { db_path: remote_path.to_path_buf() });
+
+    let mut conn = get_db_conn(&local_path);
+    conn.execute("ATTACH DATABASE :path AS remote;", named_params! {":path": remote_path.to_string_lossy()})
+        .map_err(|err| format!("Could not open {}: {}", remote_path.display(), err))?;
+    let tx = conn.transaction().map_err(|err| err.to_string())?;
+    let report = reconcile(&tx).map_err(|err| format!("Could not sync, nothing was changed: {}", err))?;
+    tx.commit().map_err(|err| format!("Could not sync, nothing was changed: {}", err))?;
+
+    Ok(report)
+}
+
+fn reconcile(tx: &Transaction) -> rusqlite::Result<SyncReport> {
+    let local_key = format!("last_sync_{}", get_app_id(tx, Side::Remote)?);
+    let remote_key = format!("last_sync_{}", get_app_id(tx, Side::Local)?);
+    let last_sync = get_last_sync(tx, Side::Local, &local_key)?;
+    let now: String = tx.query_row("SELECT CURRENT_TIMESTAMP;", [], |row| row.get(0))?;
+    let mut report = SyncReport { local: vec![], remote: vec![], last_sync: last_sync.clone(), skipped_protected: 0 };
+    let same_boss_key = same_boss_key(tx)?;
+
+    let local_notes = read_notes(tx, Side::Local)?;
+    let remote_notes = read_notes(tx, Side::Remote)?;
+    let local_tombstones = read_tombstones(tx, Side::Local)?;
+    let remote_tombstones = read_tombstones(tx, Side::Remote)?;
+
+    let content_ids = local_notes.keys().chain(remote_notes.keys()).chain(local_tombstones.keys()).chain(remote_tombstones.keys())
+        .cloned()
+        .collect::<BTreeSet<String>>();
+    // without a previous sync every difference in the text is treated as a conflict
+    let changed_since_sync = |note: &SyncNote| last_sync.as_ref().map(|last_sync| note.updated.gt(last_sync)).unwrap_or(true);
+
+    for content_id in content_ids {
+        match (local_notes.get(&content_id), remote_notes.get(&content_id)) {
+            (Some(local), Some(remote)) => {
+                if local.same_state(remote) {
+                    continue
+                }
+                if (local.protected || remote.protected) && !same_boss_key {
+                    report.skipped_protected += 1;
+                    continue
+                }
+                let (newer, newer_side, older) = if remote.updated.gt(&local.updated) {
+                    (remote, Side::Remote, local)
+                } else {
+                    (local, Side::Local, remote)
+                };
+
+                if !local.same_text(remote) && changed_since_sync(local) && changed_since_sync(remote) {
+                    let conflict_copy = SyncNote {
+                        content_id: Uuid::new_v4().to_string(),
+                        title: if older.protected { older.title.to_string() } else { format!("{} (conflict copy {})", older.title, older.updated) },
+                        // the copy is a new note, the title keeps the time of the edit
+                        updated: now.to_string(),
+                        ..older.clone()
+                    };
+                    for side in [Side::Local, Side::Remote] {
+                        write_note(tx, side, &conflict_copy)?;
+                        report.record(side, &conflict_copy, SyncChangeKind::ConflictCopy);
+                    }
+                }
+                copy_note(tx, &mut report, newer_side.other(), newer, Some(older))?;
+            }
+            (Some(note), None) | (None, Some(note)) => {
+                let side = if local_notes.contains_key(&content_id) { Side::Local } else { Side::Remote };
+                let tombstones = if side == Side::Local { &remote_tombstones } else { &local_tombstones };
+                match tombstones.get(&content_id) {
+                    Some(deleted) if note.updated.le(deleted) => {
+                        delete_note(tx, side, &content_id, deleted)?;
+                        report.record(side, note, SyncChangeKind::Deleted);
+                    }
+                    _ if note.protected && !same_boss_key => {
+                        report.skipped_protected += 1;
+                    }
+                    _ => {
+                        copy_note(tx, &mut report, side.other(), note, None)?;
+                    }
+                }
+            }
+            (None, None) => {
+                // only a tombstone is left, make sure both sides have it
+                for (side, tombstones, other_tombstones) in [(Side::Local, &remote_tombstones, &local_tombstones), (Side::Remote, &local_tombstones, &remote_tombstones)] {
+                    if let (Some(deleted), None) = (tombstones.get(&content_id), other_tombstones.get(&content_id)) {
+                        write_tombstone(tx, side, &content_id, deleted)?;
+                    }
+                }
+            }
+        }
+    }
+
+    set_last_sync(tx, Side::Local, &local_key, &now)?;
+    set_last_sync(tx, Side::Remote, &remote_key, &now)?;
+
+    Ok(report)
+}
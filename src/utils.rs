@@ -1,4 +1,7 @@
 // use magic_crypt::MagicCryptTrait;
+use std::collections::HashSet;
+
+use sha2::{Digest, Sha256};
 use unicode_segmentation::UnicodeSegmentation;
 
 pub struct CharCount {
@@ -29,6 +32,54 @@ pub(crate) fn make_text_single_line(text: &str) -> String {
     new_text.collect::<String>().trim().to_string()
 }
 
+/**
+* Counts terminal columns the same way truncate_rich_text does, non ASCII graphemes like emoji take 2 columns
+*/
+pub(crate) fn display_width(text: &str) -> usize {
+    text.graphemes(true).map(|ch| if ch.is_ascii() { 1 } else { 2 }).sum()
+}
+
+/**
+* Finds the note ID at the start of a line printed by tru, ex: a menu row or a dump line, or the id of a JSON line
+*/
+pub(crate) fn parse_menu_line(line: &str) -> Option<usize> {
+    let line = line.trim();
+    if line.starts_with('{') {
+        let value = serde_json::from_str::<serde_json::Value>(line).ok()?;
+        let id = value.get("id").or_else(|| value.get("note_id"))?;
+        return id.as_u64().map(|id| id as usize).or_else(|| id.as_str()?.trim().parse().ok())
+    }
+
+    line.split(|ch: char| ch.is_whitespace() || ch == '|').find(|token| !token.is_empty())?.parse().ok()
+}
+
+/**
+* The matching lines (`    12: text`), context lines (`    11- text`) and gaps (`    --`) --grep prints under each note
+*/
+pub(crate) fn is_grep_line(line: &str) -> bool {
+    let Some(token) = line.split_whitespace().next() else {
+        return false
+    };
+
+    token.eq("--") || token.strip_suffix([':', '-']).is_some_and(|number| !number.is_empty() && number.chars().all(|ch| ch.is_ascii_digit()))
+}
+
+/**
+* Reads the note IDs of menu lines, each ID once in the order they are found. The lines under each note in --grep
+* output are skipped so its results can be piped into -g. Returns the first line that is not a menu line as the error.
+*/
+pub(crate) fn parse_menu_lines(text: &str) -> Result<Vec<usize>, String> {
+    let mut ids: Vec<usize> = vec![];
+    for line in text.lines().filter(|line| !line.trim().is_empty() && !is_grep_line(line)) {
+        match parse_menu_line(line) {
+            Some(id) => if !ids.contains(&id) { ids.push(id) },
+            None => return Err(line.to_string())
+        }
+    }
+
+    Ok(ids)
+}
+
 pub(crate) fn truncate_rich_text(text: &str, size: usize) -> String {
     let chars = text.graphemes(true).collect::<Vec<&str>>();
     let mut filtered_chars: Vec<&str> = vec!();
@@ -51,9 +102,62 @@ pub(crate) fn truncate_rich_text(text: &str, size: usize) -> String {
     filtered_chars.join("").to_string()
 }
 
+pub(crate) fn hash_text(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/**
+* The lowercase words of a text, compare them with word_similarity
+*/
+pub(crate) fn word_set(text: &str) -> HashSet<String> {
+    text.unicode_words().map(|word| word.to_lowercase()).collect()
+}
+
+/**
+* Jaccard similarity of two word sets, 1.0 means both texts contain the same words
+*/
+pub(crate) fn word_similarity(words_a: &HashSet<String>, words_b: &HashSet<String>) -> f64 {
+    if words_a.is_empty() && words_b.is_empty() {
+        return 1.0
+    }
+
+    let shared = words_a.intersection(words_b).count();
+    let total = words_a.union(words_b).count();
+
+    shared as f64 / total as f64
+}
+
+// see RFC 5545 section 3.3.11 for TEXT values
+pub(crate) fn escape_ics_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/**
+* Folds an iCalendar content line with CRLF so no line is longer than 75 octets, see RFC 5545 section 3.1.
+* Continuation lines start with a space and characters are never split.
+*/
+pub(crate) fn fold_ics_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut line_octets = 0;
+    for c in line.chars() {
+        if line_octets + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            line_octets = 1;
+        }
+        folded.push(c);
+        line_octets += c.len_utf8();
+    }
+
+    folded
+}
+
 #[cfg(test)]
 mod test {
-    use crate::utils::{make_text_single_line, truncate_rich_text, slice_text};
+    use crate::utils::{display_width, escape_ics_text, fold_ics_line, hash_text, make_text_single_line, is_grep_line, parse_menu_line, parse_menu_lines, truncate_rich_text, slice_text, word_set, word_similarity};
 
     #[test]
     fn test_slice_text() {
@@ -96,4 +200,69 @@ mod test {
         assert_eq!(result_7, "");
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("foo"), 3);
+        assert_eq!(display_width("A🥷🏽B"), 4);
+        assert_eq!(display_width(""), 0);
+    }
+
+    #[test]
+    fn test_parse_menu_line() {
+        assert_eq!(parse_menu_line("        1 | 2024-07-01 22:56:27 | Get Started with tRusty"), Some(1));
+        assert_eq!(parse_menu_line("12"), Some(12));
+        assert_eq!(parse_menu_line(" 7|foo"), Some(7));
+        assert_eq!(parse_menu_line("3\tGroceries"), Some(3));
+        assert_eq!(parse_menu_line("{\"id\": 5, \"title\": \"foo\"}"), Some(5));
+        assert_eq!(parse_menu_line("{\"note_id\": \"6\"}"), Some(6));
+        assert_eq!(parse_menu_line("{\"title\": \"foo\"}"), None);
+        assert_eq!(parse_menu_line("{\"id\": "), None);
+        assert_eq!(parse_menu_line("Get Started"), None);
+        assert_eq!(parse_menu_line(""), None);
+    }
+
+    #[test]
+    fn test_parse_menu_lines() {
+        assert!(is_grep_line("    12: kubectl apply"));
+        assert!(is_grep_line("     4- context"));
+        assert!(is_grep_line("    --"));
+        assert!(!is_grep_line("       12 | 🔒 Secret"));
+        assert!(!is_grep_line("12"));
+        assert_eq!(parse_menu_lines("        3 | Deploy\n    1: deploy\n    --\n\n       12 | Secret\n3\n"), Ok(vec![3, 12]));
+        assert_eq!(parse_menu_lines("3\nfoo\n"), Err("foo".to_string()));
+    }
+
+    #[test]
+    fn test_escape_ics_text() {
+        assert_eq!(escape_ics_text("Deploy; then rollback, maybe"), "Deploy\\; then rollback\\, maybe");
+        assert_eq!(escape_ics_text("C:\\temp\nnext"), "C:\\\\temp\\nnext");
+    }
+
+    #[test]
+    fn test_fold_ics_line() {
+        assert_eq!(fold_ics_line("VERSION:2.0"), "VERSION:2.0");
+
+        let folded = fold_ics_line(&format!("SUMMARY:{}", "é".repeat(60)));
+        let lines = folded.split("\r\n").collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert!(lines[1].starts_with(' '));
+        // unfolding gives back the original line
+        assert_eq!(lines.join("").replacen(" ", "", 1), format!("SUMMARY:{}", "é".repeat(60)));
+    }
+
+    #[test]
+    fn test_hash_text() {
+        assert_eq!(hash_text("foo"), "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae");
+        assert_ne!(hash_text("foo"), hash_text("foo "));
+    }
+
+    #[test]
+    fn test_word_similarity() {
+        let text_similarity = |text_a: &str, text_b: &str| word_similarity(&word_set(text_a), &word_set(text_b));
+        assert_eq!(text_similarity("foo bar", "Bar foo"), 1.0);
+        assert_eq!(text_similarity("foo bar", "baz bez"), 0.0);
+        assert_eq!(text_similarity("foo bar baz", "foo bar"), 2.0 / 3.0);
+        assert_eq!(text_similarity("", ""), 1.0);
+    }
+}
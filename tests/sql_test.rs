@@ -2,13 +2,31 @@
 * Add test here that need to check the db
 */
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
+use chrono::{TimeZone, Utc};
 use mockall::automock;
+use rusqlite::named_params;
 use tempfile::tempdir;
 
-use tru::render::Printer;
+use tru::api::ApiSession;
+use tru::archive::{collect_archive, collect_matching_archive, import_archive, ImportMode, open_archive, seal_archive};
+use tru::backup::{check_integrity, create_backup, find_backup, list_backups, preview_restore, restore_backup, rotate_backups};
+use tru::bulk::{BulkAction, run_bulk_action};
+use tru::checklist::{toggle_note_item, ToggleError};
+use tru::content_type::ContentType;
+use tru::dates::{parse_since_local, TimeDisplay};
+use tru::git_sync::git_sync;
+use tru::sync::{sync_databases, SyncChangeKind};
+use tru::query::Query;
+use tru::render::{ListOptions, Printer};
+use tru::rpc::{handle_rpc_line, run_rpc};
+use tru::security::{change_password, init_password, recover_password, rotate_boss_key, unlock_boss_key};
+use tru::server::{get_api_token, handle_request};
 use tru::setup::{create_trusty_dir, get_db_conn, init_trusty_db, PathOperations};
-use tru::sql::{add_key_value, add_note, delete_note, dump_notes, empty_trash, get_last_touched_note, get_note_by_id, get_note_from_menu_line_by_id, get_summary, get_value_from_attr_table, list_note_titles, restore_note, set_note_trash, SimpleNoteView, trash_note, update_key_value, update_last_touched, update_note_by_content_id, update_note_by_note_id, update_note_ts_by_content_id, update_note_ts_by_note_id, update_protected_flag, update_title_by_content_id};
+use tru::sql::{add_key_value, add_note, save_key_values, get_config_value, get_note_history, merge_notes, set_config_value, delete_note, dump_matching_notes, dump_notes, empty_trash, get_last_touched_note, get_note_by_id, get_note_from_menu_line_by_id, get_summary, get_due_notes, get_value_from_attr_table, list_note_completions, list_note_titles, list_picker_notes, list_unprotected_notes, query_note_ids, restore_note, set_note_due, set_note_pinned, set_note_trash, SimpleNoteView, trash_note, update_key_value, update_last_touched, update_note_by_content_id, update_note_by_note_id, update_note_ts_by_content_id, update_note_ts_by_note_id, update_protected_flag, update_title_by_content_id};
+use tru::note::{Note, NoteId};
+use tru::store::{MemoryStore, NoteStore, SqliteStore};
 
 struct TestPrinter{}
 #[cfg_attr(test, automock)]
@@ -56,10 +74,11 @@ fn test_default_note(note: SimpleNoteView) {
 #[test]
 fn test_add_note() {
     let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
         let title = "foo";
         let body = "bar";
-        add_note(mock, title, body, false);
-        let note = get_note_by_id(mock, 2);
+        add_note(&store, title, body, false);
+        let note = get_note_by_id(&store, NoteId(2));
         assert_eq!(note.title, title);
         assert_eq!(note.body, body);
         // todo! only test protected flows using E2E so that prompts are not opened
@@ -73,7 +92,8 @@ fn test_add_note() {
 fn test_populate_trusty_sys_tables() {
     // this also test get_note_by_id
     let test = | mock: &dyn PathOperations | {
-        let note = get_note_by_id(mock, 1);
+        let store = SqliteStore::open(mock);
+        let note = get_note_by_id(&store, NoteId(1));
         // This tests the setup of the notes and content table from a users perspective
         // this test both create_trusty_sys_tables and populate_trusty_sys_tables
         test_default_note(note);
@@ -85,11 +105,92 @@ fn test_populate_trusty_sys_tables() {
 #[test]
 fn test_list_note_titles() {
     let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
         let mut mock_printer = MockTestPrinter::new();
         mock_printer.expect_println().times(2).return_const(());
         mock_printer.expect_print_error().times(0).return_const(());
 
-        list_note_titles(mock, &mock_printer);
+        list_note_titles(&store, &mock_printer, &ListOptions::default());
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_list_pinned_and_trashed_notes() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "foo body", false);
+        add_note(&store, "bar", "bar body", false);
+        assert!(set_note_pinned(&store, NoteId(1), true));
+        assert!(!set_note_pinned(&store, NoteId(42), true));
+        set_note_trash(&store, 2, true);
+
+        let list = |options: ListOptions| {
+            let lines = Arc::new(Mutex::new(vec![]));
+            let printed = lines.clone();
+            let mut mock_printer = MockTestPrinter::new();
+            mock_printer.expect_println().returning(move |text| printed.lock().unwrap().push(text));
+            mock_printer.expect_print_error().times(0).return_const(());
+            list_note_titles(&store, &mock_printer, &options);
+            let lines = lines.lock().unwrap().clone();
+            lines
+        };
+
+        let lines = list(ListOptions { width: 80, compact: true, ..ListOptions::default() });
+        assert_eq!(lines.len(), 2);
+        // pinned notes are listed last
+        assert!(lines[0].contains("| bar"));
+        assert!(lines[1].contains("| 📌 Get Started with tRusty"));
+
+        let lines = list(ListOptions { width: 100, include_trashed: true, ..ListOptions::default() });
+        assert_eq!(lines.len(), 6);
+        assert!(lines.iter().any(|line| line.contains("| 🗑 foo")));
+        assert!(lines.iter().all(|line| line.chars().count() <= 100));
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_list_notes_since() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "foo body", false);
+        let conn = get_db_conn(&mock.get_trusty_db_path());
+        conn.execute("UPDATE notes SET updated = '2020-01-01 00:00:00' WHERE note_id = 1;", ()).unwrap();
+
+        let mut mock_printer = MockTestPrinter::new();
+        mock_printer.expect_println().withf(|text| text.contains("| foo")).times(1).return_const(());
+        mock_printer.expect_print_error().times(0).return_const(());
+        let options = ListOptions { compact: true, time: TimeDisplay::Utc, since: parse_since_local("2 days ago"), ..ListOptions::default() };
+        list_note_titles(&store, &mock_printer, &options);
+
+        let mut mock_printer = MockTestPrinter::new();
+        mock_printer.expect_println().withf(|text| text.contains("| 2020-01-01 00:00:00 | Get Started")).times(1).return_const(());
+        mock_printer.expect_println().times(1).return_const(());
+        let options = ListOptions { compact: true, time: TimeDisplay::Utc, since: parse_since_local("2019-12-31"), ..ListOptions::default() };
+        list_note_titles(&store, &mock_printer, &options);
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_list_note_completions() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "foo body", false);
+        add_note(&store, "secret title", "secret body", false);
+        add_note(&store, "bar", "bar body", false);
+        update_protected_flag(&store, NoteId(3), true);
+        set_note_trash(&store, 4, true);
+
+        let notes = list_note_completions(&store, false);
+        assert_eq!(notes.len(), 3);
+        assert!(notes.contains(&(NoteId(2), "foo".to_string())));
+        assert!(notes.contains(&(NoteId(3), "🔒".to_string())));
+        assert_eq!(list_note_completions(&store, true), vec![(NoteId(4), "bar".to_string())]);
     };
 
     create_test_db(test);
@@ -98,9 +199,34 @@ fn test_list_note_titles() {
 #[test]
 fn test_get_note_from_menu_line_by_id() {
     let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
         let test_line = "        1 | 2024-07-01 22:56:27 | Get Started with tRusty";
-        let note = get_note_from_menu_line_by_id(mock, test_line);
+        let note = get_note_from_menu_line_by_id(&store, test_line);
         test_default_note(note);
+        // short lines and JSON lines from the API work too
+        test_default_note(get_note_from_menu_line_by_id(&store, "1"));
+        test_default_note(get_note_from_menu_line_by_id(&store, "{\"id\": 1, \"title\": \"Get Started with tRusty\"}"));
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_list_picker_notes() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "secret", "secret body", false);
+        add_note(&store, "old", "old body", false);
+        update_protected_flag(&store, NoteId(2), true);
+        set_note_trash(&store, 3, true);
+
+        let notes = list_picker_notes(&store);
+        assert_eq!(notes.len(), 2);
+        let protected = notes.iter().find(|note| note.id == 2).unwrap();
+        assert_eq!(protected.title, "🔒 ENCRYPTED");
+        assert_eq!(protected.body, None);
+        let welcome = notes.iter().find(|note| note.id == 1).unwrap();
+        assert_eq!(welcome.body.as_deref(), Some("Welcome to tRusty the CLI notes app. -Ron"));
     };
 
     create_test_db(test);
@@ -109,12 +235,13 @@ fn test_get_note_from_menu_line_by_id() {
 #[test]
 fn test_update_last_touched() {
     let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
         let title = "foo";
-        add_note(mock, title, "bar", false);
-        let note_1 = get_last_touched_note(mock);
+        add_note(&store, title, "bar", false);
+        let note_1 = get_last_touched_note(&store);
         assert_eq!(note_1.title, title);
-        update_last_touched(mock, "1");
-        let note_2 = get_last_touched_note(mock);
+        update_last_touched(&store, "1");
+        let note_2 = get_last_touched_note(&store);
         assert_ne!(note_2.title, title);
     };
 
@@ -124,21 +251,22 @@ fn test_update_last_touched() {
 #[test]
 fn test_update_note_functions() {
     let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
         let conn = get_db_conn(&mock.get_trusty_db_path());
-        let note = get_note_by_id(mock, 1);
+        let note = get_note_by_id(&store, NoteId(1));
         assert!(update_note_ts_by_note_id(1, &conn));
-        assert!(update_note_ts_by_content_id(&note.content_id, &conn));
+        assert!(update_note_ts_by_content_id(note.content_id.as_str(), &conn));
         let text = "foobar";
-        assert!(update_note_by_note_id(mock, 1, text));
-        let note_2 = get_note_by_id(mock, 1);
+        assert!(update_note_by_note_id(&store, 1, text));
+        let note_2 = get_note_by_id(&store, NoteId(1));
         assert_eq!(note_2.body, text);
         let text_2 = "barbaz";
-        assert!(update_note_by_content_id(mock, &note_2.content_id, text_2));
-        let note_3 = get_note_by_id(mock, 1);
+        assert!(update_note_by_content_id(&store, note_2.content_id.as_str(), text_2));
+        let note_3 = get_note_by_id(&store, NoteId(1));
         assert_eq!(note_3.body, text_2);
         let text_3 = "foo title";
-        assert!(update_title_by_content_id(mock, &note_3.content_id, text_3));
-        let note_4 = get_note_by_id(mock, 1);
+        assert!(update_title_by_content_id(&store, note_3.content_id.as_str(), text_3));
+        let note_4 = get_note_by_id(&store, NoteId(1));
         assert_eq!(note_4.title, text_3);
     };
 
@@ -149,11 +277,14 @@ fn test_update_note_functions() {
 #[test]
 fn test_delete_note() {
     let test = | mock: &dyn PathOperations | {
-        assert!(delete_note(mock, 1, true));
+        let store = SqliteStore::open(mock);
+        assert!(delete_note(&store, NoteId(1), true));
         // db needs a note to get summary right now
-        add_note(mock, "foo", "bar", false);
-        let summary = get_summary(mock);
+        add_note(&store, "foo", "bar", false);
+        let summary = get_summary(&store);
         assert_eq!(summary.db_stats.total, 1);
+        assert_eq!((summary.state_note_stats.id, summary.fresh_note_stats.id), (NoteId(2), NoteId(2)));
+        assert_eq!((summary.large_note_stats.note_id, summary.large_note_stats.content_size), (NoteId(2), 3));
     };
 
     create_test_db(test);
@@ -162,19 +293,20 @@ fn test_delete_note() {
 #[test]
 fn test_trash_feature() {
     let test = | mock: &dyn PathOperations | {
-        assert!(set_note_trash(mock, 1, true));
-        let summary = get_summary(mock);
+        let store = SqliteStore::open(mock);
+        assert!(set_note_trash(&store, 1, true));
+        let summary = get_summary(&store);
         assert_eq!(summary.db_stats.trashed, 1);
-        empty_trash(mock);
+        empty_trash(&store);
         // db needs a note to get summary right now
-        add_note(mock, "foo", "bar", false);
-        let summary_2 = get_summary(mock);
+        add_note(&store, "foo", "bar", false);
+        let summary_2 = get_summary(&store);
         assert_eq!(summary_2.db_stats.trashed, 0);
         // test trash wrapper funcs
-        add_note(mock, "foo", "bar", false);
-        assert!(trash_note(mock, 3));
-        assert!(restore_note(mock, 3));
-        assert!(delete_note(mock, 3, false));
+        add_note(&store, "foo", "bar", false);
+        assert!(trash_note(&store, 3));
+        assert!(restore_note(&store, 3));
+        assert!(delete_note(&store, NoteId(3), false));
     };
 
     create_test_db(test);
@@ -184,10 +316,11 @@ fn test_trash_feature() {
 #[test]
 fn test_dump_notes() {
     let test = | mock: &dyn PathOperations | {
-        add_note(mock, "foo", "bar", false);
-        add_note(mock, "foo", "bar", false);
-        add_note(mock, "foo", "bar", false);
-        let notes = dump_notes(mock, false);
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "bar", false);
+        add_note(&store, "foo", "bar", false);
+        add_note(&store, "foo", "bar", false);
+        let notes = dump_notes(&store, false);
         assert_eq!(notes.len(), 4);
     };
 
@@ -197,16 +330,17 @@ fn test_dump_notes() {
 #[test]
 fn test_attr_functions() {
     let test = | mock: &dyn PathOperations | {
-        let value = get_value_from_attr_table(mock, "app", "last_touched");
+        let store = SqliteStore::open(mock);
+        let value = get_value_from_attr_table(&store, "app", "last_touched");
         assert_eq!(value.value, "0");
         let key_2 = "foo";
         let val_2 = "42";
-        add_key_value(mock, "app", key_2, val_2);
-        let value_2 = get_value_from_attr_table(mock, "app", key_2);
+        add_key_value(&store, "app", key_2, val_2);
+        let value_2 = get_value_from_attr_table(&store, "app", key_2);
         assert_eq!(value_2.value, val_2);
         let updated_val = "99";
-        update_key_value(mock, "app", key_2, updated_val);
-        let updated_value = get_value_from_attr_table(mock, "app", key_2);
+        update_key_value(&store, "app", key_2, updated_val);
+        let updated_value = get_value_from_attr_table(&store, "app", key_2);
         assert_eq!(updated_value.value, updated_val);
     };
 
@@ -216,7 +350,989 @@ fn test_attr_functions() {
 #[test]
 fn test_update_protected_flag() {
     let test = | mock: &dyn PathOperations | {
-        assert!(update_protected_flag(mock, 1, true));
+        let store = SqliteStore::open(mock);
+        assert!(update_protected_flag(&store, NoteId(1), true));
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_due_notes() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "bar", false);
+        add_note(&store, "baz", "bez", false);
+        assert!(set_note_due(&store, NoteId(1), Some("2000-01-01 09:30")));
+        assert!(set_note_due(&store, NoteId(2), Some("2999-01-01")));
+        assert!(!set_note_due(&store, NoteId(3), Some("not a date")));
+        let notes = get_due_notes(&store, None);
+        assert_eq!(notes.len(), 2);
+        assert!(notes[0].overdue);
+        assert_eq!(notes[0].note_id, NoteId(1));
+        assert!(!notes[1].overdue);
+        // only overdue notes are due in the next day
+        let soon = get_due_notes(&store, Some(24));
+        assert_eq!(soon.len(), 1);
+        assert!(set_note_due(&store, NoteId(1), None));
+        assert_eq!(get_due_notes(&store, None).len(), 1);
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_list_unprotected_notes() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "todo", "- [ ] foo\n- [x] bar", false);
+        add_note(&store, "trashed", "- [ ] baz", false);
+        trash_note(&store, 3);
+        let notes = list_unprotected_notes(&store);
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[1].title, "todo");
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_toggle_note_item() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        let boss_key = "test-boss-key";
+        add_note(&store, "todo", "- [ ] foo\n- [ ] bar", false);
+        add_note(&store, "secret", "- [ ] baz", false);
+        assert!(toggle_note_item(&store, 2, 2, None).is_ok());
+        assert_eq!(get_note_by_id(&store, NoteId(2)).body, "- [ ] foo\n- [x] bar");
+        assert_eq!(toggle_note_item(&store, 2, 3, None), Err(ToggleError::ItemNotFound));
+        assert_eq!(toggle_note_item(&store, 42, 1, None), Err(ToggleError::NoteNotFound));
+
+        run_bulk_action(&store, BulkAction::Protect, &[3], Some(boss_key));
+        let sealed = store.get_note(NoteId(3)).unwrap().body;
+        // a protected note is never written without the key that opens it
+        assert_eq!(toggle_note_item(&store, 3, 1, None), Err(ToggleError::Locked));
+        assert_eq!(toggle_note_item(&store, 3, 1, Some("wrong-boss-key")), Err(ToggleError::Locked));
+        assert_eq!(store.get_note(NoteId(3)).unwrap().body, sealed);
+        assert!(toggle_note_item(&store, 3, 1, Some(boss_key)).is_ok());
+        assert!(run_bulk_action(&store, BulkAction::Unprotect, &[3], Some(boss_key))[0].ok);
+        assert_eq!(get_note_by_id(&store, NoteId(3)).body, "- [x] baz");
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_duplicate_policy() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        assert_eq!(get_config_value(&store, "duplicate_policy", "warn"), "warn");
+        assert!(add_note(&store, "foo", "same body", false));
+        // the default policy warns but still saves the note
+        assert!(add_note(&store, "foo", "same body", false));
+        assert_eq!(store.find_duplicates("same body"), vec![NoteId(2), NoteId(3)]);
+        assert!(set_config_value(&store, "duplicate_policy", "skip"));
+        assert!(!add_note(&store, "foo", "same body", false));
+        assert_eq!(store.find_duplicates("same body").len(), 2);
+        // edits keep the hash current
+        update_note_by_note_id(&store, 3, "new body");
+        assert_eq!(store.find_duplicates("new body"), vec![NoteId(3)]);
+        // protected notes are never hashed
+        update_protected_flag(&store, NoteId(3), true);
+        assert!(store.find_duplicates("new body").is_empty());
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_merge_notes() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "foo body", false);
+        add_note(&store, "bar", "bar body", false);
+        assert!(!merge_notes(&store, NoteId(3), NoteId(3)));
+        assert!(!merge_notes(&store, NoteId(99), NoteId(2)));
+        assert!(merge_notes(&store, NoteId(3), NoteId(2)));
+        let note = get_note_by_id(&store, NoteId(2));
+        assert_eq!(note.title, "foo");
+        assert_eq!(note.body, "foo body\n\nbar body");
+        let history = get_note_history(&store, 2);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].title, "foo");
+        assert_eq!(history[1].title, "bar");
+        assert_eq!(history[1].event, "merged_from");
+        assert_eq!(dump_notes(&store, false).len(), 2);
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_bulk_actions() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "alpha", false);
+        add_note(&store, "bar", "beta", false);
+        let results = run_bulk_action(&store, BulkAction::Trash, &[1, 2, 99], None);
+        assert!(results[0].ok && results[1].ok);
+        assert!(!results[2].ok);
+        assert_eq!(get_summary(&store).db_stats.trashed, 2);
+        let results_2 = run_bulk_action(&store, BulkAction::Restore, &[1, 2], None);
+        assert!(results_2.iter().all(|result| result.ok));
+        assert_eq!(get_summary(&store).db_stats.trashed, 0);
+        // protected notes are skipped by a regular delete
+        let boss_key = "test-boss-key";
+        let results_3 = run_bulk_action(&store, BulkAction::Protect, &[2, 3], Some(boss_key));
+        assert!(results_3.iter().all(|result| result.ok));
+        assert!(!run_bulk_action(&store, BulkAction::Protect, &[2], Some(boss_key))[0].ok);
+        let results_4 = run_bulk_action(&store, BulkAction::Delete, &[1, 2], None);
+        assert!(results_4[0].ok);
+        assert!(!results_4[1].ok);
+        let results_5 = run_bulk_action(&store, BulkAction::Unprotect, &[2, 3], Some(boss_key));
+        assert!(results_5.iter().all(|result| result.ok));
+        let notes = dump_notes(&store, false);
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].title, "foo");
+        assert_eq!(notes[0].body, "alpha");
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_search_note_ids() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "Deploy notes", "kubectl apply", false);
+        add_note(&store, "Groceries", "eggs, milk", false);
+        assert_eq!(store.search_notes("deploy"), vec![NoteId(2)]);
+        assert_eq!(store.search_notes("MILK"), vec![NoteId(3)]);
+        assert!(store.search_notes("nothing").is_empty());
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_query_notes() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "Deploy notes", "kubectl apply #infra", false);
+        add_note(&store, "Infra budget", "#infrastructure costs", false);
+        add_note(&store, "Secret", "#infra keys", false);
+        let conn = get_db_conn(&mock.get_trusty_db_path());
+        conn.execute("UPDATE notes SET protected = TRUE WHERE note_id = 4;", ()).unwrap();
+        conn.execute("UPDATE notes SET updated = '2020-01-01 00:00:00' WHERE note_id = 1;", ()).unwrap();
+        set_note_trash(&store, 3, true);
+
+        let ids = |text: &str| query_note_ids(&store, &Query::parse(text).unwrap());
+        // protected notes are never matched on their text
+        assert_eq!(ids("tag:infra"), vec![2]);
+        assert_eq!(ids("tag:infrastructure"), vec![3]);
+        assert_eq!(ids("protected:no -title:deploy"), vec![1, 3]);
+        assert_eq!(ids("trashed:no protected:no updated:>2020-01-01"), vec![2]);
+        assert_eq!(ids("size:>=20 size:<21"), vec![2]);
+        assert_eq!(ids(r#""'; DROP TABLE notes; --""#), Vec::<usize>::new());
+        assert_eq!(ids("updated:2020-01-01"), vec![1]);
+
+        let dumped = dump_matching_notes(&store, false, &Query::parse("infra").unwrap());
+        assert_eq!(dumped.iter().map(|note| note.id).collect::<Vec<NoteId>>(), vec![NoteId(2), NoteId(3)]);
+        let archive = collect_matching_archive(&store, None, &Query::parse("protected:no trashed:no").unwrap()).unwrap();
+        assert_eq!(archive.notes.len(), 2);
+
+        // the trash is only listed when the query asks for it
+        let list = |text: &str| {
+            let lines = Arc::new(Mutex::new(vec![]));
+            let printed = lines.clone();
+            let mut mock_printer = MockTestPrinter::new();
+            mock_printer.expect_println().returning(move |text| printed.lock().unwrap().push(text));
+            let options = ListOptions { width: 100, compact: true, query: Query::parse(text).unwrap(), ..ListOptions::default() };
+            list_note_titles(&store, &mock_printer, &options);
+            let lines = lines.lock().unwrap().clone();
+            lines
+        };
+        assert_eq!(list("infra").len(), 1);
+        let lines = list("infra trashed:yes");
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].contains("🗑 Infra budget"));
+    };
+
+    create_test_db(test);
+}
+
+fn add_failure_trigger(mock: &dyn PathOperations, trigger: &str) {
+    let conn = get_db_conn(&mock.get_trusty_db_path());
+    conn.execute(&format!("CREATE TRIGGER fail_write {} BEGIN SELECT RAISE(ABORT, 'injected failure'); END;", trigger), ()).unwrap();
+}
+
+fn count_rows(mock: &dyn PathOperations, table: &str) -> usize {
+    let conn = get_db_conn(&mock.get_trusty_db_path());
+    conn.query_row(&format!("SELECT COUNT(*) FROM {};", table), (), |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn test_with_transaction() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        let result: rusqlite::Result<()> = store.with_transaction(|tx| {
+            tx.execute("UPDATE app SET value = 'foo' WHERE key = 'last_touched';", ())?;
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        });
+        assert!(result.is_err());
+        assert_ne!(get_value_from_attr_table(&store, "app", "last_touched").value, "foo");
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_insert_note_rollback() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        let last_touched = get_value_from_attr_table(&store, "app", "last_touched").value;
+        add_failure_trigger(mock, "BEFORE INSERT ON notes");
+        assert!(!add_note(&store, "foo", "bar", false));
+        // the content row must not be left behind without a note
+        assert_eq!(count_rows(mock, "content"), 1);
+        assert_eq!(count_rows(mock, "notes"), 1);
+        assert_eq!(get_value_from_attr_table(&store, "app", "last_touched").value, last_touched);
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_update_note_rollback() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "bar", false);
+        add_failure_trigger(mock, "BEFORE UPDATE ON notes");
+        assert!(!update_note_by_note_id(&store, 2, "baz"));
+        assert_eq!(get_note_by_id(&store, NoteId(2)).body, "bar");
+        assert!(!update_protected_flag(&store, NoteId(2), true));
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_merge_notes_rollback() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "foo body", false);
+        add_note(&store, "bar", "bar body", false);
+        add_failure_trigger(mock, "BEFORE DELETE ON notes");
+        assert!(!merge_notes(&store, NoteId(3), NoteId(2)));
+        assert_eq!(get_note_by_id(&store, NoteId(2)).body, "foo body");
+        assert!(get_note_history(&store, 2).is_empty());
+        assert_eq!(count_rows(mock, "notes"), 3);
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_bulk_actions_rollback() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "foo", "alpha", false);
+        add_note(&store, "bar", "beta", false);
+        add_failure_trigger(mock, "BEFORE UPDATE ON notes WHEN NEW.note_id = 3");
+        let results = run_bulk_action(&store, BulkAction::Trash, &[1, 2, 3], None);
+        assert!(results.iter().all(|result| !result.ok));
+        assert!(results[0].message.starts_with("rolled back"));
+        assert_eq!(get_summary(&store).db_stats.trashed, 0);
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_save_key_values() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        assert!(save_key_values(&store, "config", &[("foo", "1"), ("bar", "2")], true));
+        assert!(save_key_values(&store, "config", &[("foo", "3"), ("bar", "4")], false));
+        assert_eq!(get_config_value(&store, "bar", ""), "4");
+        // a missing key fails the whole batch
+        assert!(!save_key_values(&store, "config", &[("foo", "5"), ("missing", "6")], false));
+        assert_eq!(get_config_value(&store, "foo", ""), "3");
+        assert!(!save_key_values(&store, "config", &[("baz", "7"), ("foo", "8")], true));
+        assert_eq!(get_config_value(&store, "baz", ""), "");
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_backup_and_restore() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_key_value(&store, "app", "recovery_code", "encrypted-recovery-code");
+        add_note(&store, "foo", "bar", false);
+        let backup = create_backup(mock).unwrap();
+        assert_eq!(backup.integrity, "ok");
+        assert_eq!(backup.manifest.note_count, 2);
+        assert!(!backup.manifest.recovery_fingerprint.is_empty());
+
+        add_note(&store, "baz", "qux", false);
+        update_key_value(&store, "app", "recovery_code", "new-encrypted-recovery-code");
+        let backups = list_backups(mock);
+        assert_eq!(backups.len(), 1);
+        assert!(!backups[0].recovery_matches);
+
+        let preview = preview_restore(mock, &backup.path);
+        assert_eq!(preview.current_note_count, 3);
+        assert_eq!(preview.backup.manifest.note_count, 2);
+
+        let safety_backup = restore_backup(mock, &backup.path).unwrap();
+        assert_eq!(safety_backup.manifest.note_count, 3);
+        assert_eq!(dump_notes(&store, false).len(), 2);
+        assert_eq!(get_value_from_attr_table(&store, "app", "recovery_code").value, "encrypted-recovery-code");
+        assert!(find_backup(mock, &safety_backup.manifest.file_name).is_some());
+        assert!(find_backup(mock, "missing.db").is_none());
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_rotate_backups() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        set_config_value(&store, "backup_keep", "2");
+        let first = create_backup(mock).unwrap();
+        create_backup(mock).unwrap();
+        let last = create_backup(mock).unwrap();
+        assert_eq!(rotate_backups(mock, &store), vec![first.manifest.file_name.to_string()]);
+        let backups = list_backups(mock);
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0].manifest.file_name, last.manifest.file_name);
+        assert!(!first.path.exists());
+        assert!(!first.path.with_extension("manifest").exists());
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_restore_corrupt_backup() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        let backup = create_backup(mock).unwrap();
+        std::fs::write(&backup.path, "not a database").unwrap();
+        assert_ne!(check_integrity(&backup.path), "ok");
+        assert!(restore_backup(mock, &backup.path).is_err());
+        assert_eq!(dump_notes(&store, false).len(), 1);
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_archive_export_import() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        let boss_key = "test-boss-key";
+        add_note(&store, "foo", "bar", false);
+        add_note(&store, "secret", "hunter2", false);
+        run_bulk_action(&store, BulkAction::Protect, &[3], Some(boss_key));
+        set_config_value(&store, "duplicate_policy", "skip");
+        set_config_value(&store, "api_token", "local-token");
+        set_config_value(&store, "git_remote", "git@example.com:me/notes.git");
+        assert!(collect_archive(&store, None).is_err());
+        let archive = collect_archive(&store, Some(boss_key)).unwrap();
+        assert_eq!(archive.notes.len(), 3);
+        assert_eq!(archive.notes[2].body, "hunter2");
+        assert!(!archive.config.contains_key("trusty_app_id"));
+        assert!(!archive.config.contains_key("api_token"));
+        assert!(!archive.config.contains_key("git_remote"));
+        let sealed = seal_archive(&archive, "archive passphrase");
+        assert!(!sealed.contains("hunter2"));
+        let opened = open_archive(&sealed, "archive passphrase").unwrap();
+
+        create_test_db(| other: &dyn PathOperations | {
+            let other_store = SqliteStore::open(other);
+            let other_boss_key = "other-boss-key";
+            assert!(import_archive(&other_store, &opened, ImportMode::Merge, None).is_err());
+            let report = import_archive(&other_store, &opened, ImportMode::Merge, Some(other_boss_key)).unwrap();
+            assert_eq!(report.imported, 3);
+            assert_eq!(report.config_keys, 1);
+            // both welcome notes are kept and the secret note is protected again with the key of this vault
+            assert_eq!(dump_notes(&other_store, false).len(), 3);
+            let report_2 = import_archive(&other_store, &opened, ImportMode::Merge, Some(other_boss_key)).unwrap();
+            assert_eq!(report_2.imported, 0);
+            assert_eq!(report_2.skipped, 3);
+            let unprotected = run_bulk_action(&other_store, BulkAction::Unprotect, &[4], Some(other_boss_key));
+            assert!(unprotected[0].ok);
+            assert_eq!(get_note_by_id(&other_store, NoteId(4)).body, "hunter2");
+
+            let report_3 = import_archive(&other_store, &opened, ImportMode::Overwrite, Some(other_boss_key)).unwrap();
+            assert_eq!(report_3.imported, 3);
+            assert_eq!(dump_notes(&other_store, false).len(), 2);
+            assert_eq!(get_config_value(&other_store, "duplicate_policy", ""), "skip");
+            assert_eq!(get_config_value(&other_store, "api_token", ""), "");
+        });
+    };
+
+    create_test_db(test);
+}
+
+fn run_sql(mock: &dyn PathOperations, sql: &str) {
+    get_db_conn(&mock.get_trusty_db_path()).execute(sql, ()).unwrap();
+}
+
+fn get_content_id(mock: &dyn PathOperations, title: &str) -> String {
+    let conn = get_db_conn(&mock.get_trusty_db_path());
+    conn.query_row("SELECT content_id FROM notes WHERE title = :title;", named_params! {":title": title}, |row| row.get(0)).unwrap()
+}
+
+#[test]
+fn test_sync_databases() {
+    let test = | local: &dyn PathOperations | {
+        let local_store = SqliteStore::open(local);
+        add_note(&local_store, "foo", "local note", false);
+        create_test_db(| remote: &dyn PathOperations | {
+            let remote_store = SqliteStore::open(remote);
+            add_note(&remote_store, "bar", "remote note", false);
+            let remote_path = remote.get_trusty_db_path();
+
+            // the first sync copies notes both ways
+            let report = sync_databases(local, &remote_path).unwrap();
+            assert!(report.last_sync.is_none());
+            assert_eq!(report.local.len(), 2);
+            assert_eq!(report.remote.len(), 2);
+            assert!(report.local.iter().all(|change| change.kind == SyncChangeKind::Added));
+            assert_eq!(count_rows(local, "notes"), 4);
+            assert_eq!(count_rows(remote, "notes"), 4);
+            assert!(sync_databases(local, &remote_path).unwrap().local.is_empty());
+
+            // an edit on one side wins without a conflict
+            let foo_id = get_content_id(local, "foo");
+            run_sql(remote, &format!("UPDATE content SET body = 'edited remotely' WHERE content_id = '{}';", foo_id));
+            run_sql(remote, &format!("UPDATE notes SET updated = '2999-01-01 00:00:00' WHERE content_id = '{}';", foo_id));
+            let report_2 = sync_databases(local, &remote_path).unwrap();
+            assert_eq!(report_2.local.len(), 1);
+            assert_eq!(report_2.local[0].kind, SyncChangeKind::Updated);
+            assert!(report_2.remote.is_empty());
+            assert_eq!(get_note_by_id(&local_store, NoteId(2)).body, "edited remotely");
+
+            // edits on both sides keep the older version as a conflict copy
+            let bar_id = get_content_id(local, "bar");
+            run_sql(local, &format!("UPDATE content SET body = 'local edit' WHERE content_id = '{}';", bar_id));
+            run_sql(local, &format!("UPDATE notes SET updated = '2999-01-02 00:00:00' WHERE content_id = '{}';", bar_id));
+            run_sql(remote, &format!("UPDATE content SET body = 'remote edit' WHERE content_id = '{}';", bar_id));
+            run_sql(remote, &format!("UPDATE notes SET updated = '2999-01-03 00:00:00' WHERE content_id = '{}';", bar_id));
+            let report_3 = sync_databases(local, &remote_path).unwrap();
+            assert!(report_3.local.iter().any(|change| change.kind == SyncChangeKind::ConflictCopy));
+            assert!(report_3.remote.iter().any(|change| change.kind == SyncChangeKind::ConflictCopy));
+            assert_eq!(count_rows(local, "notes"), 5);
+            assert_eq!(count_rows(remote, "notes"), 5);
+            let conflict_title = "bar (conflict copy 2999-01-02 00:00:00)";
+            assert_eq!(get_note_by_id(&remote_store, get_note_id(remote, conflict_title)).body, "local edit");
+            assert_eq!(get_note_by_id(&local_store, get_note_id(local, "bar")).body, "remote edit");
+
+            // trash and deletes are carried over
+            run_sql(local, &format!("UPDATE notes SET trashed = TRUE, updated = '2999-02-01 00:00:00' WHERE content_id = '{}';", foo_id));
+            run_sql(remote, &format!("DELETE FROM notes WHERE title = '{}';", conflict_title));
+            let report_4 = sync_databases(local, &remote_path).unwrap();
+            assert_eq!(report_4.remote[0].kind, SyncChangeKind::Trashed);
+            assert_eq!(report_4.local[0].kind, SyncChangeKind::Deleted);
+            assert_eq!(get_summary(&remote_store).db_stats.trashed, 1);
+            assert_eq!(count_rows(local, "notes"), 4);
+            assert_eq!(count_rows(local, "tombstones"), 1);
+        });
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_sync_protected_notes() {
+    let test = | local: &dyn PathOperations | {
+        let local_store = SqliteStore::open(local);
+        init_password(&local_store, "Foobar123").unwrap();
+        let boss_key = unlock_boss_key(&local_store, "Foobar123").unwrap();
+        local_store.create_note(&Note::builder().title("Secret").body("hunter2").protect(&boss_key).build().unwrap()).unwrap();
+        add_note(&local_store, "foo", "local note", false);
+
+        create_test_db(| remote: &dyn PathOperations | {
+            let remote_store = SqliteStore::open(remote);
+            init_password(&remote_store, "Other123").unwrap();
+            let remote_path = remote.get_trusty_db_path();
+
+            // another password means another boss key, the protected note could never be opened there
+            let report = sync_databases(local, &remote_path).unwrap();
+            assert_eq!(report.skipped_protected, 1);
+            assert_eq!(report.remote.len(), 2);
+            assert_eq!(count_rows(remote, "notes"), 3);
+            assert!(remote_store.list_notes(false).iter().all(|note| !note.protected));
+
+            // a vault with the same boss key gets the note and opens it with its own password
+            let keys = ["password", "boss_key", "recovery_code", "recovery_boss_key", "boss_key_fingerprint"]
+                .map(|key| (key, local_store.get_value("app", key).unwrap()));
+            assert!(remote_store.save_values("app", &keys.iter().map(|(key, value)| (*key, value.as_str())).collect::<Vec<(&str, &str)>>(), false));
+            assert!(change_password(&remote_store, "Foobar123", "Other123", false).is_ok());
+            let report_2 = sync_databases(local, &remote_path).unwrap();
+            assert_eq!(report_2.skipped_protected, 0);
+            assert_eq!(report_2.remote.len(), 1);
+            let secret = remote_store.list_notes(false).into_iter().find(|note| note.protected).unwrap();
+            let mut session = ApiSession::new(&remote_store);
+            session.unlock("Other123").unwrap();
+            assert_eq!(session.get(secret.id.0).unwrap()["body"].as_str(), Some("hunter2"));
+        });
+    };
+
+    create_test_db(test);
+}
+
+fn get_note_id(mock: &dyn PathOperations, title: &str) -> NoteId {
+    let conn = get_db_conn(&mock.get_trusty_db_path());
+    NoteId(conn.query_row("SELECT note_id FROM notes WHERE title = :title;", named_params! {":title": title}, |row| row.get(0)).unwrap())
+}
+
+#[test]
+fn test_sync_invalid_database() {
+    let test = | mock: &dyn PathOperations | {
+        assert!(sync_databases(mock, &mock.get_trusty_db_path()).is_err());
+        let not_a_db = mock.get_trusty_dir().join("foo.txt");
+        std::fs::write(&not_a_db, "foo").unwrap();
+        assert!(sync_databases(mock, &not_a_db).is_err());
+        assert!(sync_databases(mock, &mock.get_trusty_dir().join("missing.db")).is_err());
+    };
+
+    create_test_db(test);
+}
+
+fn set_up_git_sync(mock: &dyn PathOperations, store: &dyn NoteStore, bare_repo: &std::path::Path) {
+    set_config_value(store, "git_remote", &bare_repo.to_string_lossy());
+    set_config_value(store, "git_sync_dir", &mock.get_trusty_dir().join("git").to_string_lossy());
+}
+
+#[test]
+fn test_git_sync() {
+    let test = | laptop: &dyn PathOperations | {
+        let laptop_store = SqliteStore::open(laptop);
+        let bare_repo = laptop.get_trusty_dir().join("notes.git");
+        let output = std::process::Command::new("git").args(["init", "--quiet", "--bare"]).arg(&bare_repo).output().unwrap();
+        assert!(output.status.success());
+        set_up_git_sync(laptop, &laptop_store, &bare_repo);
+        add_note(&laptop_store, "foo", "laptop note", false);
+        add_note(&laptop_store, "secret", "hunter2", false);
+        run_bulk_action(&laptop_store, BulkAction::Protect, &[3], Some("test-boss-key"));
+        let report = git_sync(laptop, &laptop_store).unwrap();
+        assert_eq!(report.remote.len(), 3);
+        assert!(report.local.is_empty());
+        let files = laptop.get_trusty_dir().join("git").join("notes");
+        let secret_file = files.join(format!("{}.txt", get_content_id_by_note_id(laptop, 3)));
+        let secret_text = std::fs::read_to_string(secret_file).unwrap();
+        assert!(secret_text.contains("protected: true"));
+        assert!(!secret_text.contains("hunter2") && !secret_text.contains("secret"));
+
+        // a damaged file stops the sync instead of deleting its note
+        let foo_file = files.join(format!("{}.txt", get_content_id(laptop, "foo")));
+        let foo_text = std::fs::read_to_string(&foo_file).unwrap();
+        std::fs::write(&foo_file, foo_text.replace("---\n", "")).unwrap();
+        assert!(git_sync(laptop, &laptop_store).is_err_and(|err| err.contains("is not a tRusty note")));
+        assert_eq!(count_rows(laptop, "notes"), 3);
+        std::fs::write(&foo_file, foo_text).unwrap();
+
+        create_test_db(| desktop: &dyn PathOperations | {
+            let desktop_store = SqliteStore::open(desktop);
+            set_up_git_sync(desktop, &desktop_store, &bare_repo);
+            let report_2 = git_sync(desktop, &desktop_store).unwrap();
+            assert_eq!(report_2.local.len(), 3);
+            assert_eq!(count_rows(desktop, "notes"), 4);
+
+            // the same note edited on both machines
+            let foo_id = get_content_id(desktop, "foo");
+            run_sql(desktop, &format!("UPDATE content SET body = 'desktop edit' WHERE content_id = '{}';", foo_id));
+            run_sql(desktop, &format!("UPDATE notes SET updated = '2999-01-01 00:00:00' WHERE content_id = '{}';", foo_id));
+            git_sync(desktop, &desktop_store).unwrap();
+            run_sql(laptop, &format!("UPDATE content SET body = 'laptop edit' WHERE content_id = '{}';", foo_id));
+            run_sql(laptop, &format!("UPDATE notes SET updated = '2999-01-02 00:00:00' WHERE content_id = '{}';", foo_id));
+            let report_3 = git_sync(laptop, &laptop_store).unwrap();
+            assert!(report_3.local.iter().any(|change| change.kind == SyncChangeKind::ConflictCopy));
+            assert_eq!(get_note_by_id(&laptop_store, get_note_id(laptop, "foo")).body, "desktop edit");
+            assert_eq!(get_note_by_id(&laptop_store, get_note_id(laptop, "foo (conflict copy 2999-01-02 00:00:00)")).body, "laptop edit");
+
+            // deletes are pushed and applied on the other machine
+            delete_note(&desktop_store, get_note_id(desktop, "foo"), false);
+            let report_4 = git_sync(desktop, &desktop_store).unwrap();
+            assert_eq!(report_4.remote[0].kind, SyncChangeKind::Deleted);
+            assert!(report_4.local.iter().any(|change| change.title.starts_with("foo (conflict copy")));
+            let report_5 = git_sync(laptop, &laptop_store).unwrap();
+            assert_eq!(report_5.local[0].kind, SyncChangeKind::Deleted);
+            assert_eq!(count_rows(laptop, "notes"), count_rows(desktop, "notes"));
+            assert!(get_content_ids(laptop).iter().all(|content_id| !content_id.eq(&foo_id)));
+        });
+    };
+
+    create_test_db(test);
+}
+
+fn get_content_id_by_note_id(mock: &dyn PathOperations, note_id: usize) -> String {
+    let conn = get_db_conn(&mock.get_trusty_db_path());
+    conn.query_row("SELECT content_id FROM notes WHERE note_id = :note_id;", named_params! {":note_id": note_id}, |row| row.get(0)).unwrap()
+}
+
+fn get_content_ids(mock: &dyn PathOperations) -> Vec<String> {
+    let conn = get_db_conn(&mock.get_trusty_db_path());
+    let mut stmt = conn.prepare("SELECT content_id FROM notes;").unwrap();
+    let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+#[test]
+fn test_api_server() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        init_password(&store, "correct horse").unwrap();
+        let token = get_api_token(&store);
+        assert_eq!(get_api_token(&store), token);
+        let auth = format!("Bearer {}", token);
+        let auth = Some(auth.as_str());
+        let mut session = ApiSession::new(&store);
+
+        let (status, _) = handle_request(&mut session, &token, "GET", "/notes", None, "");
+        assert_eq!(status, 401);
+        let (status, _) = handle_request(&mut session, &token, "GET", "/notes", Some("Bearer foo"), "");
+        assert_eq!(status, 401);
+
+        let (status, json) = handle_request(&mut session, &token, "POST", "/notes", auth, r#"{"title": "foo", "body": "bar baz"}"#);
+        assert_eq!(status, 200);
+        let id = json["id"].as_u64().unwrap() as usize;
+        let (status, json) = handle_request(&mut session, &token, "GET", &format!("/notes/{}", id), auth, "");
+        assert_eq!(status, 200);
+        assert_eq!(json["title"], "foo");
+        assert_eq!(json["body"], "bar baz");
+        let (_, json) = handle_request(&mut session, &token, "PUT", &format!("/notes/{}", id), auth, r#"{"body": "qux"}"#);
+        assert_eq!(json["body"], "qux");
+        assert_eq!(json["title"], "foo");
+        let (_, json) = handle_request(&mut session, &token, "GET", "/search?q=qux", auth, "");
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        let (status, _) = handle_request(&mut session, &token, "POST", "/notes", auth, r#"{"title": "foo"}"#);
+        assert_eq!(status, 400);
+        let (status, _) = handle_request(&mut session, &token, "GET", "/notes/42", auth, "");
+        assert_eq!(status, 404);
+
+        let (status, _) = handle_request(&mut session, &token, "POST", &format!("/notes/{}/trash", id), auth, "");
+        assert_eq!(status, 200);
+        let (_, json) = handle_request(&mut session, &token, "GET", "/notes?trashed=true", auth, "");
+        assert_eq!(json[0]["id"], id);
+        handle_request(&mut session, &token, "POST", &format!("/notes/{}/restore", id), auth, "");
+        let (_, json) = handle_request(&mut session, &token, "GET", "/notes", auth, "");
+        assert!(json.as_array().unwrap().iter().any(|note| note["id"] == id));
+
+        // protected notes need the password
+        let (status, _) = handle_request(&mut session, &token, "POST", "/notes", auth, r#"{"title": "secret", "body": "hunter2", "protected": true}"#);
+        assert_eq!(status, 423);
+        let (status, _) = handle_request(&mut session, &token, "POST", "/unlock", auth, r#"{"password": "wrong horse"}"#);
+        assert_eq!(status, 401);
+        let (status, _) = handle_request(&mut session, &token, "POST", "/unlock", auth, r#"{"password": "correct horse"}"#);
+        assert_eq!(status, 200);
+        let (_, json) = handle_request(&mut session, &token, "POST", "/notes", auth, r#"{"title": "secret", "body": "hunter2", "protected": true}"#);
+        let secret_id = json["id"].as_u64().unwrap() as usize;
+        let (_, json) = handle_request(&mut session, &token, "GET", &format!("/notes/{}", secret_id), auth, "");
+        assert_eq!(json["body"], "hunter2");
+        assert_eq!(json["protected"], true);
+        assert!(unlock_boss_key(&store, "correct horse").is_some());
+        assert_ne!(store.get_note(NoteId(secret_id)).unwrap().body, "hunter2");
+
+        handle_request(&mut session, &token, "POST", "/lock", auth, "");
+        let (status, _) = handle_request(&mut session, &token, "GET", &format!("/notes/{}", secret_id), auth, "");
+        assert_eq!(status, 423);
+        let (_, json) = handle_request(&mut session, &token, "GET", "/notes", auth, "");
+        let secret = json.as_array().unwrap().iter().find(|note| note["id"] == secret_id).unwrap();
+        assert_eq!(secret["title"], "🔒 ENCRYPTED");
+        let (status, _) = handle_request(&mut session, &token, "DELETE", &format!("/notes/{}", secret_id), auth, "");
+        assert_eq!(status, 423);
+
+        let (status, json) = handle_request(&mut session, &token, "DELETE", &format!("/notes/{}", id), auth, "");
+        assert_eq!(status, 200);
+        assert_eq!(json["deleted"], true);
+        assert!(store.get_note(NoteId(id)).is_none());
+        let (status, _) = handle_request(&mut session, &token, "PATCH", &format!("/notes/{}", id), auth, "");
+        assert_eq!(status, 404);
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_json_rpc() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        init_password(&store, "correct horse").unwrap();
+        let input = [
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "create", "params": {"title": "foo", "body": "bar baz"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 2, "method": "search", "params": {"text": "bar"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 3, "method": "create", "params": {"title": "secret", "body": "hunter2", "protected": true}}"#,
+            r#"{"jsonrpc": "2.0", "method": "unlock", "params": {"password": "correct horse"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 4, "method": "create", "params": {"title": "secret", "body": "hunter2", "protected": true}}"#,
+            "",
+            r#"{"jsonrpc": "2.0", "id": 5, "method": "get", "params": {"id": "foo"}}"#,
+            r#"{"jsonrpc": "2.0", "id": 6, "method": "delete_everything"}"#,
+            r#"{"id": 7, "method": "list"}"#,
+            "foo",
+        ].join("\n");
+        let mut output = vec![];
+        run_rpc(&store, input.as_bytes(), &mut output).unwrap();
+        let responses = String::from_utf8(output).unwrap().lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<serde_json::Value>>();
+
+        // the unlock notification has no response
+        assert_eq!(responses.len(), 8);
+        let id = responses[0]["result"]["id"].as_u64().unwrap() as usize;
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(responses[1]["result"][0]["id"], id);
+        assert_eq!(responses[2]["error"]["code"], 423);
+        let secret_id = responses[3]["result"]["id"].as_u64().unwrap() as usize;
+        assert!(store.get_note(NoteId(secret_id)).unwrap().protected);
+        assert_eq!(responses[4]["error"]["code"], -32602);
+        assert_eq!(responses[5]["error"]["code"], -32601);
+        assert_eq!(responses[6]["error"]["code"], -32600);
+        assert_eq!(responses[7]["error"]["code"], -32700);
+        assert_eq!(responses[7]["id"], serde_json::Value::Null);
+
+        let mut session = ApiSession::new(&store);
+        let response = handle_rpc_line(&mut session, &format!(r#"{{"jsonrpc": "2.0", "id": "a", "method": "get", "params": {{"id": {}}}}}"#, secret_id)).unwrap();
+        assert_eq!(response["error"]["code"], 423);
+        handle_rpc_line(&mut session, r#"{"jsonrpc": "2.0", "id": "b", "method": "unlock", "params": {"password": "correct horse"}}"#);
+        let response = handle_rpc_line(&mut session, &format!(r#"{{"jsonrpc": "2.0", "id": "c", "method": "get", "params": {{"id": {}}}}}"#, secret_id)).unwrap();
+        assert_eq!(response["id"], "c");
+        assert_eq!(response["result"]["body"], "hunter2");
+        let response = handle_rpc_line(&mut session, &format!(r#"{{"jsonrpc": "2.0", "id": "d", "method": "trash", "params": {{"id": {}}}}}"#, id)).unwrap();
+        assert_eq!(response["result"]["trashed"], true);
+        let response = handle_rpc_line(&mut session, r#"{"jsonrpc": "2.0", "id": "e", "method": "list", "params": {"trashed": true}}"#).unwrap();
+        assert_eq!(response["result"][0]["id"], id);
+        let response = handle_rpc_line(&mut session, &format!(r#"{{"jsonrpc": "2.0", "id": "f", "method": "update", "params": {{"id": {}, "body": "qux"}}}}"#, id)).unwrap();
+        assert_eq!(response["result"]["body"], "qux");
+    };
+
+    create_test_db(test);
+}
+
+#[test]
+fn test_note_content_type() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        add_note(&store, "response", "{\"foo\": [1, 2]}", false);
+        add_note(&store, "script", "echo hi", false);
+        let json_id = get_note_id(mock, "response");
+        let script_id = get_note_id(mock, "script");
+        assert_eq!(store.get_note(json_id).and_then(|note| note.content_type), Some(ContentType::Json));
+        assert_eq!(store.get_note(script_id).and_then(|note| note.content_type), Some(ContentType::Text));
+
+        assert!(store.set_content_type(script_id, ContentType::Sh));
+        assert!(!store.set_content_type(NoteId(42), ContentType::Sh));
+        assert_eq!(store.get_note(script_id).and_then(|note| note.content_type), Some(ContentType::Sh));
+        let dumped = dump_notes(&store, false);
+        let script = dumped.iter().find(|note| note.id == script_id).unwrap();
+        assert_eq!(script.content_type, Some(ContentType::Sh));
+
+        let archive = collect_archive(&store, None).unwrap();
+        let opened = open_archive(&seal_archive(&archive, "archive passphrase"), "archive passphrase").unwrap();
+        create_test_db(| other: &dyn PathOperations | {
+            let other_store = SqliteStore::open(other);
+            import_archive(&other_store, &opened, ImportMode::Merge, None).unwrap();
+            assert_eq!(other_store.get_note(get_note_id(other, "script")).and_then(|note| note.content_type), Some(ContentType::Sh));
+            assert_eq!(other_store.get_note(get_note_id(other, "response")).and_then(|note| note.content_type), Some(ContentType::Json));
+        });
+    };
+
+    create_test_db(test);
+}
+
+// the same checks run against every NoteStore so the backends stay interchangeable
+fn check_note_store(store: &dyn NoteStore) {
+    let first = store.insert_note("first", "first body", None).unwrap();
+    let second = store.insert_note("second", "a longer second body", None).unwrap();
+    assert_eq!(store.last_touched(), Some(second));
+    assert!(store.set_last_touched(first));
+    assert_eq!(store.last_touched(), Some(first));
+
+    assert!(store.update_body(first, "edited body"));
+    assert!(store.update_title(first, "edited"));
+    assert!(!store.update_body(NoteId(4242), "missing"));
+    let note = store.get_note(first).unwrap();
+    assert_eq!((note.title.as_str(), note.body.as_str(), note.trashed), ("edited", "edited body", false));
+
+    assert!(store.set_trashed(first, true));
+    assert!(store.list_notes(true).iter().any(|note| note.id == first));
+    assert!(!store.list_notes(false).iter().any(|note| note.id == first));
+    assert_eq!(store.empty_trash(), 1);
+    assert!(store.get_note(first).is_none());
+
+    let protected = store.insert_note("secret", "encrypted body", Some("wrapped key")).unwrap();
+    assert!(!store.delete_note(protected, false));
+    assert!(store.delete_note(protected, true));
+    assert!(store.delete_note(second, false));
+    assert!(store.get_note(second).is_none());
+
+    assert!(store.save_values("config", &[("store_a", "1"), ("store_b", "2")], true));
+    assert!(!store.save_values("config", &[("store_c", "3"), ("store_a", "4")], true));
+    assert_eq!(store.get_value("config", "store_c"), None);
+    assert!(store.save_values("config", &[("store_a", "5")], false));
+    assert_eq!(store.get_value("config", "store_a").as_deref(), Some("5"));
+    assert!(!store.save_values("config", &[("store_missing", "5")], false));
+    assert!(!store.save_values("notes", &[("store_a", "1")], true));
+
+    let due = Utc.with_ymd_and_hms(2024, 7, 1, 9, 30, 0).unwrap();
+    let new_note = Note::builder().title("Deploy").body("kubectl apply").content_type(ContentType::Sh).due(due).build().unwrap();
+    let created = store.create_note(&new_note).unwrap();
+    assert_eq!(store.last_touched(), Some(created.id));
+    assert_eq!((created.title.as_str(), created.body.as_str()), ("Deploy", "kubectl apply"));
+    assert_eq!((created.due, created.content_type), (Some(due), Some(ContentType::Sh)));
+    assert!(created.updated >= created.created);
+    assert!(store.set_due(created.id, None));
+    assert_eq!(store.get_note(created.id).unwrap().due, None);
+
+    let summary = store.summary().unwrap();
+    assert!(summary.db_stats.total >= 1);
+}
+
+#[test]
+fn test_note_stores() {
+    check_note_store(&MemoryStore::new());
+    create_test_db(| mock: &dyn PathOperations | {
+        check_note_store(&SqliteStore::open(mock));
+    });
+}
+
+#[test]
+fn test_memory_store_password() {
+    let store = MemoryStore::new();
+    assert!(init_password(&store, "Foobar123").is_some());
+    assert!(unlock_boss_key(&store, "Foobar123").is_some());
+    assert!(unlock_boss_key(&store, "wrong").is_none());
+}
+
+fn check_rotate_boss_key(store: &dyn NoteStore) -> NoteId {
+    init_password(store, "Foobar123").unwrap();
+    let old_boss_key = unlock_boss_key(store, "Foobar123").unwrap();
+    let plain = store.insert_note("plain", "plain body", None).unwrap();
+    let secret = store.create_note(&Note::builder().title("Secret").body("hunter2").protect(&old_boss_key).build().unwrap()).unwrap();
+    let old_recovery_boss_key = store.get_value("app", "recovery_boss_key");
+    assert!(secret.data_key.is_some());
+
+    assert_eq!(rotate_boss_key(store, "wrong"), Err("Password incorrect.".to_string()));
+    assert!(rotate_boss_key(store, "Foobar123").is_ok());
+    let new_boss_key = unlock_boss_key(store, "Foobar123").unwrap();
+    assert_ne!(new_boss_key, old_boss_key);
+    assert_ne!(store.get_value("app", "recovery_boss_key"), old_recovery_boss_key);
+
+    // only the wrapped key changes, the encrypted title and body stay as they were
+    let rotated = store.get_note(secret.id).unwrap();
+    assert_eq!((rotated.title.as_str(), rotated.body.as_str(), rotated.updated), (secret.title.as_str(), secret.body.as_str(), secret.updated));
+    assert_ne!(rotated.data_key, secret.data_key);
+    assert_eq!(store.get_note(plain).unwrap().data_key, None);
+
+    secret.id
+}
+
+#[test]
+fn test_rotate_boss_key() {
+    check_rotate_boss_key(&MemoryStore::new());
+
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        let secret_id = check_rotate_boss_key(&store);
+
+        // the note still opens with the same password after the rotation
+        let mut session = ApiSession::new(&store);
+        session.unlock("Foobar123").unwrap();
+        let json = session.get(secret_id.0).unwrap();
+        assert_eq!((json["title"].as_str(), json["body"].as_str()), (Some("Secret"), Some("hunter2")));
+    };
+
+    create_test_db(test);
+}
+
+fn check_change_password(store: &dyn NoteStore) {
+    let recovery_code = init_password(store, "Foobar123").unwrap();
+    let boss_key = unlock_boss_key(store, "Foobar123").unwrap();
+
+    assert_eq!(change_password(store, "wrong", "Newpass1", false), Err("Password incorrect.".to_string()));
+    assert_eq!(change_password(store, "Foobar123", "no!", false), Err("Password must be at least 8 characters.".to_string()));
+    assert_eq!(change_password(store, "Foobar123", "Newpass1", false), Ok(None));
+    assert!(unlock_boss_key(store, "Foobar123").is_none());
+    // the boss key is only locked again so notes do not change
+    assert_eq!(unlock_boss_key(store, "Newpass1"), Some(boss_key.to_string()));
+
+    // the recovery code still works until it is rotated
+    let new_recovery_code = recover_password(store, &recovery_code, "Recovered1").unwrap();
+    assert_eq!(unlock_boss_key(store, "Recovered1"), Some(boss_key.to_string()));
+    assert!(recover_password(store, &recovery_code, "Again123").is_err());
+
+    let rotated_code = change_password(store, "Recovered1", "Rotated1", true).unwrap().unwrap();
+    assert!(recover_password(store, &new_recovery_code, "Again123").is_err());
+    assert!(recover_password(store, &rotated_code, "Again123").is_ok());
+    assert_eq!(unlock_boss_key(store, "Again123"), Some(boss_key));
+}
+
+#[test]
+fn test_change_password() {
+    check_change_password(&MemoryStore::new());
+    create_test_db(|mock| check_change_password(&SqliteStore::open(mock)));
+
+    // nothing changes when one of the keys can not be saved, this vault has no recovery_boss_key
+    let source = MemoryStore::new();
+    init_password(&source, "Foobar123").unwrap();
+    let keys = ["password", "boss_key", "recovery_code"].map(|key| (key, source.get_value("app", key).unwrap()));
+    let store = MemoryStore::new();
+    assert!(store.save_values("app", &keys.iter().map(|(key, value)| (*key, value.as_str())).collect::<Vec<(&str, &str)>>(), true));
+    assert_eq!(change_password(&store, "Foobar123", "Newpass1", true), Err("Could not set password.".to_string()));
+    assert!(unlock_boss_key(&store, "Foobar123").is_some());
+}
+
+#[test]
+fn test_unicode_passphrase() {
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        init_password(&store, "Foobar123").unwrap();
+        set_config_value(&store, "password_min_length", "16");
+        assert_eq!(change_password(&store, "Foobar123", "Newpass123", false), Err("Password must be at least 16 characters.".to_string()));
+
+        // the same passphrase with a combining accent instead of é unlocks the notes
+        assert_eq!(change_password(&store, "Foobar123", "le caf\u{e9} du coin 🛟", false), Ok(None));
+        assert!(unlock_boss_key(&store, "le cafe\u{301} du coin 🛟").is_some());
+        assert!(unlock_boss_key(&store, "le cafe du coin 🛟").is_none());
+    };
+
+    create_test_db(test);
+}
+
+#[cfg(feature = "sqlcipher")]
+#[test]
+fn test_encrypt_database() {
+    use tru::db_encryption::{check_db_key, decrypt_database, encrypt_database, is_encrypted_db, set_db_key};
+
+    let test = | mock: &dyn PathOperations | {
+        let store = SqliteStore::open(mock);
+        let db_path = mock.get_trusty_db_path();
+        add_note(&store, "plain title", "plain body", false);
+        assert!(!is_encrypted_db(&db_path));
+
+        encrypt_database(&db_path, "correct horse battery").unwrap();
+        assert!(is_encrypted_db(&db_path));
+        assert!(!String::from_utf8_lossy(&std::fs::read(&db_path).unwrap()).contains("plain title"));
+        assert!(encrypt_database(&db_path, "correct horse battery").is_err());
+        // the key is kept for the rest of the session
+        assert_eq!(get_last_touched_note(&SqliteStore::open(mock)).title, "plain title");
+        let backup = create_backup(mock).unwrap();
+        assert!(is_encrypted_db(&backup.path));
+        assert_eq!(check_integrity(&backup.path), "ok");
+
+        assert!(check_db_key(&db_path, "correct horse battery"));
+        assert!(!check_db_key(&db_path, "wrong horse battery"));
+
+        decrypt_database(&db_path).unwrap();
+        assert!(!is_encrypted_db(&db_path));
+        set_db_key(None);
+        assert_eq!(get_last_touched_note(&SqliteStore::open(mock)).title, "plain title");
     };
 
     create_test_db(test);